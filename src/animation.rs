@@ -0,0 +1,406 @@
+use crate::math_traits::{CrossProduct, InnerProduct};
+use crate::noise::{Noise, PerlinNoise};
+use crate::scene_file::Value;
+use crate::scene_writer::{choice, Entry};
+use crate::utils::degrees_to_radians;
+use crate::vec3::{Point3, Vec3};
+use std::ops;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Quaternion {
+    pub w: f64,
+    pub v: Vec3,
+}
+
+impl Quaternion {
+    pub const fn identity() -> Self {
+        Self {
+            w: 1.0,
+            v: Vec3::zero(),
+        }
+    }
+
+    /// Rotation of `angle` degrees about `axis`.
+    pub fn from_axis_angle(axis: &Vec3, angle: f64) -> Self {
+        let (sin, cos) = (degrees_to_radians(angle) / 2.0).sin_cos();
+
+        Self {
+            w: cos,
+            v: sin * axis.unit(),
+        }
+    }
+
+    /// Rotation by `yaw` about y, then `pitch` about x, then `roll` about z,
+    /// in degrees and in the rotated frame.
+    pub fn from_euler(yaw: f64, pitch: f64, roll: f64) -> Self {
+        Self::from_axis_angle(&Vec3::new(0.0, 1.0, 0.0), yaw)
+            * Self::from_axis_angle(&Vec3::new(1.0, 0.0, 0.0), pitch)
+            * Self::from_axis_angle(&Vec3::new(0.0, 0.0, 1.0), roll)
+    }
+
+    pub fn conjugate(&self) -> Self {
+        Self {
+            w: self.w,
+            v: -self.v,
+        }
+    }
+
+    pub fn dot(&self, other: &Self) -> f64 {
+        self.w * other.w + self.v.dot(&other.v)
+    }
+
+    pub fn normalized(&self) -> Self {
+        let length = self.dot(self).sqrt();
+
+        Self {
+            w: self.w / length,
+            v: self.v / length,
+        }
+    }
+
+    pub fn rotate(&self, p: &Vec3) -> Vec3 {
+        let t = 2.0 * self.v.cross(p);
+        *p + self.w * t + self.v.cross(&t)
+    }
+
+    /// Spherical linear interpolation along the shorter arc. `t` outside of
+    /// `[0, 1]` extrapolates along the same great circle.
+    pub fn slerp(&self, other: &Self, t: f64) -> Self {
+        let mut other = *other;
+        let mut cos = self.dot(&other);
+
+        if cos < 0.0 {
+            other = Self {
+                w: -other.w,
+                v: -other.v,
+            };
+            cos = -cos;
+        }
+
+        let (a, b) = if cos > 0.9995 {
+            (1.0 - t, t)
+        } else {
+            let theta = cos.min(1.0).acos();
+            let sin = theta.sin();
+
+            (((1.0 - t) * theta).sin() / sin, (t * theta).sin() / sin)
+        };
+
+        Self {
+            w: a * self.w + b * other.w,
+            v: a * self.v + b * other.v,
+        }
+        .normalized()
+    }
+}
+
+impl ops::Mul for Quaternion {
+    type Output = Quaternion;
+
+    fn mul(self, _rhs: Quaternion) -> Quaternion {
+        Quaternion {
+            w: self.w * _rhs.w - self.v.dot(&_rhs.v),
+            v: self.w * _rhs.v + _rhs.w * self.v + self.v.cross(&_rhs.v),
+        }
+    }
+}
+
+/// Scale, then rotation, then translation.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Quaternion,
+    pub scale: Vec3,
+}
+
+impl Transform {
+    pub const fn identity() -> Self {
+        Self {
+            translation: Vec3::zero(),
+            rotation: Quaternion::identity(),
+            scale: Vec3::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    pub fn point(&self, p: &Point3) -> Point3 {
+        self.rotation.rotate(&(self.scale * *p)) + self.translation
+    }
+
+    pub fn vector(&self, v: &Vec3) -> Vec3 {
+        self.rotation.rotate(&(self.scale * *v))
+    }
+
+    /// Transforms a normal with the inverse transpose, unnormalized.
+    pub fn normal(&self, n: &Vec3) -> Vec3 {
+        self.rotation.rotate(&(*n / self.scale))
+    }
+
+    /// Factor by which the transform scales volumes.
+    pub fn determinant(&self) -> f64 {
+        self.scale.x() * self.scale.y() * self.scale.z()
+    }
+
+    pub fn inverse_point(&self, p: &Point3) -> Point3 {
+        self.inverse_vector(&(*p - self.translation))
+    }
+
+    pub fn inverse_vector(&self, v: &Vec3) -> Vec3 {
+        self.rotation.conjugate().rotate(v) / self.scale
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+/// Values that can be keyframed.
+pub trait Keyable: Copy {
+    fn lerp(&self, other: &Self, t: f64) -> Self;
+}
+
+impl Keyable for Vec3 {
+    fn lerp(&self, other: &Self, t: f64) -> Self {
+        (1.0 - t) * self + t * other
+    }
+}
+
+impl Keyable for Quaternion {
+    fn lerp(&self, other: &Self, t: f64) -> Self {
+        self.slerp(other, t)
+    }
+}
+
+impl Keyable for f64 {
+    fn lerp(&self, other: &Self, t: f64) -> Self {
+        (1.0 - t) * self + t * other
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Linear, or slerp for rotations.
+    Linear,
+    /// Uniform Catmull-Rom spline through the keys. Rotations use the same
+    /// construction with slerp in place of linear interpolation.
+    CatmullRom,
+}
+
+/// Keys sorted by time. The value is held before the first key and after
+/// the last one.
+#[derive(Debug, Clone)]
+pub struct Track<T: Keyable> {
+    pub keys: Vec<(f64, T)>,
+    pub interpolation: Interpolation,
+}
+
+impl<T: Keyable> Track<T> {
+    pub fn new(mut keys: Vec<(f64, T)>, interpolation: Interpolation) -> Self {
+        keys.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        Self {
+            keys,
+            interpolation,
+        }
+    }
+
+    pub fn constant(value: T) -> Self {
+        Self::new(vec![(0.0, value)], Interpolation::Linear)
+    }
+
+    pub fn is_animated(&self) -> bool {
+        self.keys.len() > 1
+    }
+
+    pub fn at(&self, time: f64) -> Option<T> {
+        let last = self.keys.len().checked_sub(1)?;
+        let upper = self.keys.partition_point(|key| key.0 <= time);
+
+        if upper == 0 {
+            return Some(self.keys[0].1);
+        }
+        if upper > last {
+            return Some(self.keys[last].1);
+        }
+
+        let (t1, p1) = self.keys[upper - 1];
+        let (t2, p2) = self.keys[upper];
+        let t = (time - t1) / (t2 - t1);
+
+        Some(match self.interpolation {
+            Interpolation::Linear => p1.lerp(&p2, t),
+            Interpolation::CatmullRom => {
+                // duplicate the end keys so the spline reaches them
+                let p0 = self.keys[(upper - 1).saturating_sub(1)].1;
+                let p3 = self.keys[(upper + 1).min(last)].1;
+
+                // Barry-Goldman pyramid with knots at -1, 0, 1 and 2
+                let a1 = p0.lerp(&p1, t + 1.0);
+                let a2 = p1.lerp(&p2, t);
+                let a3 = p2.lerp(&p3, t - 1.0);
+                let b1 = a1.lerp(&a2, (t + 1.0) / 2.0);
+                let b2 = a2.lerp(&a3, t / 2.0);
+
+                b1.lerp(&b2, t)
+            }
+        })
+    }
+}
+
+/// Procedural hand-held camera wobble: smooth noise offsets of up to about
+/// `amplitude` scene units and `angle` degrees, changing `frequency` times
+/// per time unit. The noise is drawn from `seed`.
+#[derive(Clone)]
+pub struct CameraShake {
+    pub noise: PerlinNoise,
+    pub amplitude: f64,
+    pub angle: f64,
+    pub frequency: f64,
+}
+
+impl CameraShake {
+    pub fn new(amplitude: f64, angle: f64, frequency: f64, seed: u32) -> Self {
+        Self {
+            noise: PerlinNoise::with_seed(seed),
+            amplitude,
+            angle,
+            frequency,
+        }
+    }
+
+    fn channel(&self, time: f64, channel: usize) -> f64 {
+        self.noise.fbm(
+            &Point3::new(time * self.frequency, 7.31 * channel as f64, 0.5),
+            3,
+        )
+    }
+
+    pub fn at(&self, time: f64) -> Transform {
+        Transform {
+            translation: self.amplitude
+                * Vec3::new(
+                    self.channel(time, 0),
+                    self.channel(time, 1),
+                    self.channel(time, 2),
+                ),
+            rotation: Quaternion::from_euler(
+                self.angle * self.channel(time, 3),
+                self.angle * self.channel(time, 4),
+                self.angle * self.channel(time, 5),
+            ),
+            scale: Vec3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+/// Position, rotation and scale tracks. Missing tracks are the identity.
+#[derive(Clone)]
+pub struct AnimatedTransform {
+    pub translation: Track<Vec3>,
+    pub rotation: Track<Quaternion>,
+    pub scale: Track<Vec3>,
+}
+
+impl AnimatedTransform {
+    pub fn new(translation: Track<Vec3>, rotation: Track<Quaternion>, scale: Track<Vec3>) -> Self {
+        Self {
+            translation,
+            rotation,
+            scale,
+        }
+    }
+
+    pub fn fixed(transform: &Transform) -> Self {
+        Self::new(
+            Track::constant(transform.translation),
+            Track::constant(transform.rotation),
+            Track::constant(transform.scale),
+        )
+    }
+
+    pub fn is_animated(&self) -> bool {
+        self.translation.is_animated() || self.rotation.is_animated() || self.scale.is_animated()
+    }
+
+    pub fn at(&self, time: f64) -> Transform {
+        let identity = Transform::identity();
+
+        Transform {
+            translation: self.translation.at(time).unwrap_or(identity.translation),
+            rotation: self
+                .rotation
+                .at(time)
+                .unwrap_or(identity.rotation)
+                .normalized(),
+            scale: self.scale.at(time).unwrap_or(identity.scale),
+        }
+    }
+
+    /// Adds the tracks that are not the identity to `entry`, as the loader
+    /// reads them.
+    pub fn export(&self, entry: Entry) -> Result<Entry, String> {
+        let tracks = [
+            (
+                self.translation.is_animated(),
+                self.translation.interpolation,
+            ),
+            (self.rotation.is_animated(), self.rotation.interpolation),
+            (self.scale.is_animated(), self.scale.interpolation),
+        ];
+        let mut interpolations = tracks
+            .iter()
+            .filter(|(animated, _)| *animated)
+            .map(|(_, interpolation)| *interpolation);
+        let interpolation = interpolations.next().unwrap_or(Interpolation::Linear);
+        if interpolations.any(|other| other != interpolation) {
+            return Err(String::from(
+                "transforms with differently interpolated tracks cannot be exported",
+            ));
+        }
+
+        let vector = |v: &Vec3| vec![v.x(), v.y(), v.z()];
+        let quaternion = |q: &Quaternion| vec![q.w, q.v.x(), q.v.y(), q.v.z()];
+        let identity = Transform::identity();
+
+        let interpolation = match interpolation {
+            Interpolation::Linear => "linear",
+            Interpolation::CatmullRom => "catmull_rom",
+        };
+
+        Ok(entry
+            .with_non_default(
+                "translation",
+                track_value(&self.translation, vector),
+                Value::from(vector(&identity.translation)),
+            )
+            .with_non_default(
+                "rotation",
+                track_value(&self.rotation, quaternion),
+                Value::from(quaternion(&identity.rotation)),
+            )
+            .with_non_default(
+                "scale",
+                track_value(&self.scale, vector),
+                Value::from(vector(&identity.scale)),
+            )
+            .with_non_default("interpolation", choice(interpolation), choice("linear")))
+    }
+}
+
+/// A track as the loader reads it: the value alone when it is constant,
+/// else `[time, values...]` per key.
+fn track_value<T: Keyable>(track: &Track<T>, numbers: impl Fn(&T) -> Vec<f64>) -> Value {
+    match track.keys.as_slice() {
+        [(_, value)] => Value::from(numbers(value)),
+        keys => Value::List(
+            keys.iter()
+                .map(|(time, value)| {
+                    let mut key = vec![*time];
+                    key.extend(numbers(value));
+                    Value::from(key)
+                })
+                .collect(),
+        ),
+    }
+}
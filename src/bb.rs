@@ -1,6 +1,7 @@
 use crate::objects::Hittable;
 use crate::ray::Ray;
-use crate::utils::random_uint;
+use crate::rng::Rng;
+use crate::scene_writer::{Entry, SceneWriter};
 use crate::vec3::Point3;
 use crate::{WorldElementType, WorldType};
 use std::cmp;
@@ -67,7 +68,9 @@ impl BoundingBoxHit for AABB {
     }
 }
 
-struct BvhNode {
+/// Bounding volume hierarchy over the objects of a world. Objects without
+/// a bounding box cannot be placed in the tree and must be kept outside.
+pub struct BvhNode {
     pub left: WorldElementType,
     pub right: WorldElementType,
     pub bounding_box: BoxedBoundingBoxType,
@@ -75,59 +78,55 @@ struct BvhNode {
 
 fn box_comparator(
     axis: usize,
-) -> Box<dyn Fn(&WorldElementType, &WorldElementType) -> cmp::Ordering> {
+    start_time: f64,
+    end_time: f64,
+) -> impl Fn(&WorldElementType, &WorldElementType) -> cmp::Ordering {
     assert!(axis < 3);
 
-    Box::new(move |a: &WorldElementType, b: &WorldElementType| {
-        let a_box = a.bounding_box(0.0, 0.0);
-        let b_box = b.bounding_box(0.0, 0.0);
+    move |a: &WorldElementType, b: &WorldElementType| {
+        let a_box = a.bounding_box(start_time, end_time).unwrap();
+        let b_box = b.bounding_box(start_time, end_time).unwrap();
 
-        assert!(a_box.is_some() || b_box.is_some());
-
-        a_box.unwrap().min()[axis]
-            .partial_cmp(&b_box.unwrap().min()[axis])
-            .unwrap()
-    })
+        a_box.min()[axis].total_cmp(&b_box.min()[axis])
+    }
 }
 
 impl BvhNode {
     pub fn new(
-        objects: &'static WorldType,
-        start: usize,
-        end: usize,
+        objects: &mut [WorldElementType],
         start_time: f64,
         end_time: f64,
+        rng: &mut Rng,
     ) -> Self {
         let left: WorldElementType;
         let right: WorldElementType;
 
-        let object_span = end - start;
-        assert!(object_span > 0);
+        assert!(!objects.is_empty());
 
-        let axis = random_uint(0, 2);
+        let axis = rng.random_uint(0, 2);
+        let comparator = box_comparator(axis, start_time, end_time);
 
-        let comparator = box_comparator(axis);
-
-        match object_span {
+        match objects.len() {
             1 => {
-                right = objects[start].clone();
-                left = objects[start].clone();
+                right = objects[0].clone();
+                left = objects[0].clone();
             }
-            2 => match comparator(&objects[start], &objects[end]) {
+            2 => match comparator(&objects[0], &objects[1]) {
                 cmp::Ordering::Less => {
-                    left = objects[start].clone();
-                    right = objects[end].clone();
+                    left = objects[0].clone();
+                    right = objects[1].clone();
                 }
                 _ => {
-                    right = objects[start].clone();
-                    left = objects[end].clone();
+                    right = objects[0].clone();
+                    left = objects[1].clone();
                 }
             },
             _ => {
-                let mid = (start + end) / 2;
-                left =
-                    Arc::new(Self::new(objects, start, start + mid, start_time, end_time)).clone();
-                right = Arc::new(Self::new(objects, mid, end, start_time, end_time)).clone()
+                objects.sort_by(comparator);
+
+                let (first, second) = objects.split_at_mut(objects.len() / 2);
+                left = Arc::new(Self::new(first, start_time, end_time, rng));
+                right = Arc::new(Self::new(second, start_time, end_time, rng));
             }
         }
 
@@ -142,6 +141,21 @@ impl BvhNode {
             bounding_box,
         }
     }
+
+    /// Puts the bounded objects of `world` into a tree, which is returned
+    /// together with the objects that have no bounding box.
+    pub fn build(world: &WorldType, start_time: f64, end_time: f64, rng: &mut Rng) -> WorldType {
+        let (mut bounded, mut result): (WorldType, WorldType) = world
+            .iter()
+            .cloned()
+            .partition(|object| object.bounding_box(start_time, end_time).is_some());
+
+        if !bounded.is_empty() {
+            result.push(Arc::new(Self::new(&mut bounded, start_time, end_time, rng)));
+        }
+
+        result
+    }
 }
 
 impl Hittable for BvhNode {
@@ -151,20 +165,28 @@ impl Hittable for BvhNode {
         }
 
         let hit_left = self.left.hit(ray, min, max);
-        let hit_right = if hit_left.is_none() {
-            self.right.hit(ray, min, max)
-        } else {
-            self.right.hit(ray, min, ray.time)
-        };
-
-        return if hit_left.is_some() {
-            hit_left
-        } else {
-            hit_right
-        };
+        let closest = hit_left.as_ref().map_or(max, |record| record.t);
+        let hit_right = self.right.hit(ray, min, closest);
+
+        hit_right.or(hit_left)
     }
 
     fn bounding_box(&self, _: f64, _: f64) -> Option<BoxedBoundingBoxType> {
         Some(self.bounding_box.clone())
     }
+
+    fn is_animated(&self) -> bool {
+        self.left.is_animated() || self.right.is_animated()
+    }
+
+    fn export(&self, writer: &mut SceneWriter) -> Result<Vec<Entry>, String> {
+        let mut entries = self.left.export(writer)?;
+
+        // a node over a single object holds it on both sides
+        if !Arc::ptr_eq(&self.left, &self.right) {
+            entries.extend(self.right.export(writer)?);
+        }
+
+        Ok(entries)
+    }
 }
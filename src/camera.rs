@@ -1,8 +1,34 @@
+use crate::animation::{AnimatedTransform, CameraShake, Transform};
+use crate::lens::{ApertureShape, BrownConrady, PhysicalLens};
 use crate::math_traits::{CrossProduct, InnerProduct};
 use crate::ray::Ray;
-use crate::utils::{degrees_to_radians, random_range};
+use crate::sampler::CameraSample;
+use crate::utils::degrees_to_radians;
 use crate::vec3::{Point3, Vec3};
+use std::f64::consts::PI;
+use std::sync::Arc;
 
+/// Maps a point on the image plane, with `u` and `v` in `[0, 1]` from the
+/// lower left corner, to a primary ray. `None` means no ray leaves the
+/// camera there, e.g. outside the image circle of a fisheye lens. The lens
+/// position and time of the ray come from `sample`.
+pub trait CameraModel {
+    fn get_ray(&self, u: f64, v: f64, sample: &CameraSample) -> Option<Ray>;
+}
+
+pub type WrappedCameraType = Arc<dyn CameraModel + Sync + Send>;
+
+/// Orthonormal camera basis: `u` points right, `v` up and `w` backwards,
+/// away from `lookat`.
+pub fn look_at_basis(lookfrom: &Point3, lookat: &Point3, vup: &Vec3) -> (Vec3, Vec3, Vec3) {
+    let w = (*lookfrom - *lookat).unit();
+    let u = vup.cross(&w).unit();
+    let v = w.cross(&u);
+
+    (u, v, w)
+}
+
+#[derive(Clone)]
 pub struct Camera {
     pub origin: Point3,
     pub lower_left_corner: Point3,
@@ -12,6 +38,13 @@ pub struct Camera {
     pub v: Vec3,
     pub w: Vec3,
     pub lens_radius: f64,
+    pub aperture_shape: ApertureShape,
+    pub focus_dist: f64,
+    /// A point on the plane of focus and its normal. The plane is
+    /// perpendicular to `w` unless the lens is tilted.
+    pub focus_point: Point3,
+    pub focus_normal: Vec3,
+    pub distortion: Option<BrownConrady>,
     pub start_time: f64,
     pub end_time: f64,
 }
@@ -31,9 +64,7 @@ impl Camera {
         let viewport_height = 2.0 * h;
         let viewport_width = aspect_ratio * viewport_height;
 
-        let w = (lookfrom - lookat).unit();
-        let u = vup.cross(&w).unit();
-        let v = w.cross(&u);
+        let (u, v, w) = look_at_basis(&lookfrom, &lookat, &vup);
 
         let origin = lookfrom;
         let horizontal = focus_dist * viewport_width * u;
@@ -51,11 +82,69 @@ impl Camera {
             v,
             w,
             lens_radius,
+            aperture_shape: ApertureShape::Circle,
+            focus_dist,
+            focus_point: origin - focus_dist * w,
+            focus_normal: w,
+            distortion: None,
             start_time: 0.0,
             end_time: 0.0,
         }
     }
 
+    /// Takes the field of view and aperture from real world lens and sensor
+    /// settings. An image of another aspect ratio than the sensor is cropped
+    /// from the middle of it.
+    pub fn with_lens(
+        lookfrom: Point3,
+        lookat: Point3,
+        vup: Vec3,
+        lens: &PhysicalLens,
+        aspect_ratio: f64,
+        focus_dist: f64,
+    ) -> Self {
+        Self::new(
+            lookfrom,
+            lookat,
+            vup,
+            lens.fov(aspect_ratio),
+            aspect_ratio,
+            lens.aperture(),
+            focus_dist,
+        )
+    }
+
+    pub fn with_aperture_shape(mut self, aperture_shape: ApertureShape) -> Self {
+        self.aperture_shape = aperture_shape;
+        self
+    }
+
+    /// Shifts the lens parallel to the sensor, by fractions of the image
+    /// width and height, which keeps parallel lines parallel.
+    pub fn with_shift(mut self, shift_x: f64, shift_y: f64) -> Self {
+        self.lower_left_corner =
+            self.lower_left_corner + shift_x * self.horizontal + shift_y * self.vertical;
+        self
+    }
+
+    /// Tilts the plane of focus about the horizontal axis by `tilt` degrees
+    /// and about the vertical axis by `swing` degrees, pivoting around the
+    /// point at the focus distance on the view axis. Positive tilt brings
+    /// the bottom of the frame closer into focus, as for a ground plane.
+    pub fn with_tilt(mut self, tilt: f64, swing: f64) -> Self {
+        let (tilt_sin, tilt_cos) = degrees_to_radians(tilt).sin_cos();
+        let (swing_sin, swing_cos) = degrees_to_radians(swing).sin_cos();
+
+        self.focus_normal =
+            (tilt_cos * (swing_cos * self.w + swing_sin * self.u) + tilt_sin * self.v).unit();
+        self
+    }
+
+    pub fn with_distortion(mut self, distortion: BrownConrady) -> Self {
+        self.distortion = Some(distortion);
+        self
+    }
+
     pub fn with_timing(
         lookfrom: Point3,
         lookat: Point3,
@@ -82,16 +171,533 @@ impl Camera {
 
         inst
     }
+}
 
+impl CameraModel for Camera {
     #[inline(always)]
-    pub fn get_ray(&self, u: f64, v: f64) -> Ray {
-        let rd = self.lens_radius * Vec3::random_in_unit_disk();
+    fn get_ray(&self, u: f64, v: f64, sample: &CameraSample) -> Option<Ray> {
+        let (u, v) = match &self.distortion {
+            None => (u, v),
+            Some(distortion) => {
+                // normalized image coordinates of the image plane
+                let half_width = self.horizontal.length() / (2.0 * self.focus_dist);
+                let half_height = self.vertical.length() / (2.0 * self.focus_dist);
+                let (x, y) = distortion
+                    .undistort((2.0 * u - 1.0) * half_width, (2.0 * v - 1.0) * half_height);
+
+                (0.5 + x / (2.0 * half_width), 0.5 + y / (2.0 * half_height))
+            }
+        };
+
+        let target = self.lower_left_corner + u * self.horizontal + v * self.vertical;
+
+        // move the target along the pinhole ray onto the (tilted) plane of focus
+        let pinhole = target - self.origin;
+        let t = (self.focus_point - self.origin).dot(&self.focus_normal)
+            / pinhole.dot(&self.focus_normal);
+        let target = if t.is_finite() && t > 0.0 {
+            self.origin + t * pinhole
+        } else {
+            target
+        };
+
+        let rd = self.lens_radius * self.aperture_shape.sample(sample.lens);
         let offset = self.u * rd.x() + self.v * rd.y();
 
-        Ray::with_timing(
+        Some(Ray::with_timing(
             self.origin + offset,
-            self.lower_left_corner + u * self.horizontal + v * self.vertical - self.origin - offset,
-            random_range(self.start_time, self.end_time),
-        )
+            target - self.origin - offset,
+            self.start_time + sample.time * (self.end_time - self.start_time),
+        ))
+    }
+}
+
+/// Parallel projection. `view_height` is the height of the view in scene
+/// units.
+pub struct OrthographicCamera {
+    pub lower_left_corner: Point3,
+    pub horizontal: Vec3,
+    pub vertical: Vec3,
+    pub direction: Vec3,
+    pub start_time: f64,
+    pub end_time: f64,
+}
+
+impl OrthographicCamera {
+    pub fn new(
+        lookfrom: Point3,
+        lookat: Point3,
+        vup: Vec3,
+        view_height: f64,
+        aspect_ratio: f64,
+    ) -> Self {
+        let (u, v, w) = look_at_basis(&lookfrom, &lookat, &vup);
+
+        let horizontal = aspect_ratio * view_height * u;
+        let vertical = view_height * v;
+
+        Self {
+            lower_left_corner: lookfrom - horizontal / 2.0 - vertical / 2.0,
+            horizontal,
+            vertical,
+            direction: -w,
+            start_time: 0.0,
+            end_time: 0.0,
+        }
+    }
+
+    pub fn with_timing(
+        lookfrom: Point3,
+        lookat: Point3,
+        vup: Vec3,
+        view_height: f64,
+        aspect_ratio: f64,
+        start_time: f64,
+        end_time: f64,
+    ) -> Self {
+        let mut inst = Self::new(lookfrom, lookat, vup, view_height, aspect_ratio);
+
+        inst.start_time = start_time;
+        inst.end_time = end_time;
+
+        inst
+    }
+}
+
+impl CameraModel for OrthographicCamera {
+    #[inline(always)]
+    fn get_ray(&self, u: f64, v: f64, sample: &CameraSample) -> Option<Ray> {
+        Some(Ray::with_timing(
+            self.lower_left_corner + u * self.horizontal + v * self.vertical,
+            self.direction,
+            self.start_time + sample.time * (self.end_time - self.start_time),
+        ))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FisheyeProjection {
+    /// Image radius proportional to the angle off axis, `r = f * theta`.
+    Equidistant,
+    /// Equal areas on the image cover equal solid angles,
+    /// `r = 2 * f * sin(theta / 2)`.
+    Equisolid,
+}
+
+/// Circular fisheye whose image circle touches the top and bottom of the
+/// frame and spans `fov` degrees across.
+pub struct FisheyeCamera {
+    pub origin: Point3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+    pub half_fov: f64,
+    pub aspect_ratio: f64,
+    pub projection: FisheyeProjection,
+    pub start_time: f64,
+    pub end_time: f64,
+}
+
+impl FisheyeCamera {
+    pub fn new(
+        lookfrom: Point3,
+        lookat: Point3,
+        vup: Vec3,
+        fov: f64,
+        aspect_ratio: f64,
+        projection: FisheyeProjection,
+    ) -> Self {
+        let (u, v, w) = look_at_basis(&lookfrom, &lookat, &vup);
+
+        Self {
+            origin: lookfrom,
+            u,
+            v,
+            w,
+            half_fov: degrees_to_radians(fov) / 2.0,
+            aspect_ratio,
+            projection,
+            start_time: 0.0,
+            end_time: 0.0,
+        }
+    }
+}
+
+impl CameraModel for FisheyeCamera {
+    fn get_ray(&self, u: f64, v: f64, sample: &CameraSample) -> Option<Ray> {
+        let x = (2.0 * u - 1.0) * self.aspect_ratio;
+        let y = 2.0 * v - 1.0;
+        let r = (x * x + y * y).sqrt();
+
+        if r > 1.0 {
+            return None;
+        }
+
+        let theta = match self.projection {
+            FisheyeProjection::Equidistant => r * self.half_fov,
+            FisheyeProjection::Equisolid => 2.0 * (r * (self.half_fov / 2.0).sin()).asin(),
+        };
+        let psi = y.atan2(x);
+
+        let direction =
+            theta.sin() * (psi.cos() * self.u + psi.sin() * self.v) - theta.cos() * self.w;
+
+        Some(Ray::with_timing(
+            self.origin,
+            direction,
+            self.start_time + sample.time * (self.end_time - self.start_time),
+        ))
+    }
+}
+
+/// 360 by 180 degree latitude-longitude panorama centered on `lookat`.
+#[derive(Clone)]
+pub struct EquirectangularCamera {
+    pub origin: Point3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+    pub start_time: f64,
+    pub end_time: f64,
+}
+
+impl EquirectangularCamera {
+    pub fn new(lookfrom: Point3, lookat: Point3, vup: Vec3) -> Self {
+        let (u, v, w) = look_at_basis(&lookfrom, &lookat, &vup);
+
+        Self {
+            origin: lookfrom,
+            u,
+            v,
+            w,
+            start_time: 0.0,
+            end_time: 0.0,
+        }
+    }
+
+    /// World direction for longitude `phi` (positive to the right of the
+    /// view direction) and latitude `theta` (positive upwards).
+    #[inline(always)]
+    pub fn direction(&self, phi: f64, theta: f64) -> Vec3 {
+        theta.cos() * (phi.sin() * self.u - phi.cos() * self.w) + theta.sin() * self.v
+    }
+}
+
+impl CameraModel for EquirectangularCamera {
+    fn get_ray(&self, u: f64, v: f64, sample: &CameraSample) -> Option<Ray> {
+        let phi = (u - 0.5) * 2.0 * PI;
+        let theta = (v - 0.5) * PI;
+
+        Some(Ray::with_timing(
+            self.origin,
+            self.direction(phi, theta),
+            self.start_time + sample.time * (self.end_time - self.start_time),
+        ))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Eye {
+    Left,
+    Right,
+}
+
+impl Eye {
+    #[inline(always)]
+    fn sign(&self) -> f64 {
+        match self {
+            Eye::Left => -1.0,
+            Eye::Right => 1.0,
+        }
+    }
+}
+
+/// A pair of cameras rendered into one packed image.
+pub struct StereoCamera {
+    pub left: WrappedCameraType,
+    pub right: WrappedCameraType,
+}
+
+impl StereoCamera {
+    /// Off-axis perspective stereo. Both eyes are shifted by half of
+    /// `interocular` along the `u` axis of `camera`, and their frusta are
+    /// skewed so that the two views coincide at `convergence` distance,
+    /// which ends up at zero parallax.
+    pub fn perspective(camera: &Camera, interocular: f64, convergence: f64) -> Self {
+        let eye = |eye: Eye| {
+            let offset = eye.sign() * interocular / 2.0 * camera.u;
+            let mut inst = camera.clone();
+
+            inst.origin = camera.origin + offset;
+            inst.lower_left_corner =
+                camera.lower_left_corner + (1.0 - camera.focus_dist / convergence) * offset;
+
+            Arc::new(inst)
+        };
+
+        Self {
+            left: eye(Eye::Left),
+            right: eye(Eye::Right),
+        }
+    }
+
+    /// Omni-directional stereo panorama around the centre of `panorama`.
+    pub fn omnidirectional(panorama: &EquirectangularCamera, interocular: f64) -> Self {
+        let eye = |eye: Eye| {
+            Arc::new(OdsCamera {
+                panorama: panorama.clone(),
+                eye_offset: eye.sign() * interocular / 2.0,
+            })
+        };
+
+        Self {
+            left: eye(Eye::Left),
+            right: eye(Eye::Right),
+        }
+    }
+
+    pub fn eye(&self, eye: Eye) -> &WrappedCameraType {
+        match eye {
+            Eye::Left => &self.left,
+            Eye::Right => &self.right,
+        }
+    }
+}
+
+/// One eye of an omni-directional stereo panorama: every ray starts on a
+/// circle of radius `eye_offset` around the centre, tangent to the circle,
+/// so each column sees the scene as an eye looking in that direction would.
+pub struct OdsCamera {
+    pub panorama: EquirectangularCamera,
+    pub eye_offset: f64,
+}
+
+impl CameraModel for OdsCamera {
+    fn get_ray(&self, u: f64, v: f64, sample: &CameraSample) -> Option<Ray> {
+        let phi = (u - 0.5) * 2.0 * PI;
+        let theta = (v - 0.5) * PI;
+        let panorama = &self.panorama;
+
+        let right = phi.cos() * panorama.u + phi.sin() * panorama.w;
+
+        Some(Ray::with_timing(
+            panorama.origin + self.eye_offset * right,
+            panorama.direction(phi, theta),
+            panorama.start_time + sample.time * (panorama.end_time - panorama.start_time),
+        ))
+    }
+}
+
+/// A camera flown along keyframed tracks. Every ray of `lens`, which is set
+/// up at `pivot`, is moved by the shake and then the animated transform at
+/// the ray's time, both about `pivot`, so camera motion blurs over the
+/// shutter interval of `lens`.
+pub struct AnimatedCamera {
+    pub lens: WrappedCameraType,
+    pub pivot: Point3,
+    pub transform: AnimatedTransform,
+    pub shake: Option<CameraShake>,
+}
+
+impl AnimatedCamera {
+    pub fn new(lens: WrappedCameraType, pivot: Point3, transform: AnimatedTransform) -> Self {
+        Self {
+            lens,
+            pivot,
+            transform,
+            shake: None,
+        }
+    }
+
+    pub fn with_shake(mut self, shake: CameraShake) -> Self {
+        self.shake = Some(shake);
+        self
+    }
+}
+
+impl CameraModel for AnimatedCamera {
+    fn get_ray(&self, u: f64, v: f64, sample: &CameraSample) -> Option<Ray> {
+        let ray = self.lens.get_ray(u, v, sample)?;
+        let transform = self.transform.at(ray.time);
+
+        let origin = ray.origin - self.pivot;
+        let (origin, direction) = match &self.shake {
+            None => (origin, ray.direction),
+            Some(shake) => {
+                let shake = shake.at(ray.time);
+                (shake.point(&origin), shake.vector(&ray.direction))
+            }
+        };
+
+        Some(Ray::with_timing(
+            self.pivot + transform.point(&origin),
+            transform.vector(&direction),
+            ray.time,
+        ))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Projection {
+    /// Thin lens camera; `aperture` zero is a pinhole.
+    Perspective {
+        fov: f64,
+        aperture: f64,
+        focus_dist: f64,
+    },
+    /// Thin lens camera set up like a real camera body and lens.
+    Physical {
+        lens: PhysicalLens,
+        focus_dist: f64,
+    },
+    Orthographic {
+        view_height: f64,
+    },
+    Fisheye {
+        fov: f64,
+        projection: FisheyeProjection,
+    },
+    Equirectangular,
+}
+
+/// Where a camera is and how it projects, independent of the image size
+/// and shutter interval it is later built for.
+#[derive(Clone)]
+pub struct CameraSettings {
+    pub lookfrom: Point3,
+    pub lookat: Point3,
+    pub vup: Vec3,
+    pub projection: Projection,
+    /// Shape of the aperture of thin lens cameras.
+    pub aperture_shape: ApertureShape,
+    /// Lens shift of thin lens cameras, in fractions of the image width and
+    /// height.
+    pub shift: (f64, f64),
+    /// Tilt and swing of the plane of focus of thin lens cameras, in
+    /// degrees.
+    pub tilt: (f64, f64),
+    pub distortion: Option<BrownConrady>,
+    /// Keyframed offsets from `lookfrom` and rotations about it, in world
+    /// axes.
+    pub motion: Option<AnimatedTransform>,
+    pub shake: Option<CameraShake>,
+}
+
+impl CameraSettings {
+    pub fn new(lookfrom: Point3, lookat: Point3, vup: Vec3, projection: Projection) -> Self {
+        Self {
+            lookfrom,
+            lookat,
+            vup,
+            projection,
+            aperture_shape: ApertureShape::Circle,
+            shift: (0.0, 0.0),
+            tilt: (0.0, 0.0),
+            distortion: None,
+            motion: None,
+            shake: None,
+        }
+    }
+
+    pub fn build(&self, aspect_ratio: f64, start_time: f64, end_time: f64) -> WrappedCameraType {
+        let (lookfrom, lookat, vup) = (self.lookfrom, self.lookat, self.vup);
+
+        let camera: WrappedCameraType = match self.projection {
+            Projection::Perspective { .. } | Projection::Physical { .. } => Arc::new(
+                self.thin_lens(aspect_ratio, start_time, end_time)
+                    .expect("perspective camera"),
+            ),
+            Projection::Orthographic { view_height } => Arc::new(OrthographicCamera::with_timing(
+                lookfrom,
+                lookat,
+                vup,
+                view_height,
+                aspect_ratio,
+                start_time,
+                end_time,
+            )),
+            Projection::Fisheye { fov, projection } => {
+                let mut camera =
+                    FisheyeCamera::new(lookfrom, lookat, vup, fov, aspect_ratio, projection);
+                camera.start_time = start_time;
+                camera.end_time = end_time;
+
+                Arc::new(camera)
+            }
+            Projection::Equirectangular => {
+                let mut camera = EquirectangularCamera::new(lookfrom, lookat, vup);
+                camera.start_time = start_time;
+                camera.end_time = end_time;
+
+                Arc::new(camera)
+            }
+        };
+
+        self.animate(camera)
+    }
+
+    /// Flies `camera`, which is set up at `lookfrom`, along the motion and
+    /// shake of the settings, if there are any.
+    pub fn animate(&self, camera: WrappedCameraType) -> WrappedCameraType {
+        if self.motion.is_none() && self.shake.is_none() {
+            return camera;
+        }
+
+        let transform = self
+            .motion
+            .clone()
+            .unwrap_or_else(|| AnimatedTransform::fixed(&Transform::identity()));
+        let camera = AnimatedCamera::new(camera, self.lookfrom, transform);
+
+        Arc::new(match &self.shake {
+            Some(shake) => camera.with_shake(shake.clone()),
+            None => camera,
+        })
+    }
+
+    /// The camera as a thin lens camera, which stereo rendering builds on.
+    /// `None` for the other projections.
+    pub fn thin_lens(&self, aspect_ratio: f64, start_time: f64, end_time: f64) -> Option<Camera> {
+        let (lookfrom, lookat, vup) = (self.lookfrom, self.lookat, self.vup);
+
+        let camera = match self.projection {
+            Projection::Perspective {
+                fov,
+                aperture,
+                focus_dist,
+            } => Camera::with_timing(
+                lookfrom,
+                lookat,
+                vup,
+                fov,
+                aspect_ratio,
+                aperture,
+                focus_dist,
+                start_time,
+                end_time,
+            ),
+            Projection::Physical { lens, focus_dist } => {
+                let mut camera =
+                    Camera::with_lens(lookfrom, lookat, vup, &lens, aspect_ratio, focus_dist);
+                camera.start_time = start_time;
+                camera.end_time = end_time;
+
+                camera
+            }
+            _ => return None,
+        };
+
+        let mut camera = camera.with_aperture_shape(self.aperture_shape.clone());
+        if self.shift != (0.0, 0.0) {
+            camera = camera.with_shift(self.shift.0, self.shift.1);
+        }
+        if self.tilt != (0.0, 0.0) {
+            camera = camera.with_tilt(self.tilt.0, self.tilt.1);
+        }
+        if let Some(distortion) = self.distortion {
+            camera = camera.with_distortion(distortion);
+        }
+
+        Some(camera)
     }
 }
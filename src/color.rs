@@ -3,16 +3,138 @@ use crate::vec3::Color;
 use std::io::Write;
 
 pub fn write_color<T: Write>(io: &mut T, color: &Color, samples_per_pixel: u32) {
+    let [r, g, b] = quantize(color, samples_per_pixel);
+
+    let _ = io.write(format!("{} {} {}\n", r, g, b).as_bytes());
+}
+
+/// Averages, gamma corrects and quantizes accumulated samples to 8 bits.
+pub fn quantize(color: &Color, samples_per_pixel: u32) -> [u8; 3] {
     let mut color = *color / samples_per_pixel as f64;
     color.gamma_correct(2.0);
 
-    let _ = io.write(
-        format!(
-            "{} {} {}\n",
-            utils::clamp_color(color.x(), 0.0, 0.999),
-            utils::clamp_color(color.y(), 0.0, 0.999),
-            utils::clamp_color(color.z(), 0.0, 0.999),
-        )
-        .as_bytes(),
-    );
+    [
+        utils::clamp_color(color.x(), 0.0, 0.999) as u8,
+        utils::clamp_color(color.y(), 0.0, 0.999) as u8,
+        utils::clamp_color(color.z(), 0.0, 0.999) as u8,
+    ]
+}
+
+#[inline(always)]
+pub fn srgb_to_linear(color: &Color) -> Color {
+    fn channel(c: f64) -> f64 {
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    }
+
+    Color::new(channel(color.x()), channel(color.y()), channel(color.z()))
+}
+
+/// Relative luminance of a linear Rec. 709 colour.
+#[inline(always)]
+pub fn luminance(color: &Color) -> f64 {
+    0.2126 * color.x() + 0.7152 * color.y() + 0.0722 * color.z()
+}
+
+/// Converts RGB to HSV, with hue in turns (`[0, 1)`).
+pub fn rgb_to_hsv(color: &Color) -> (f64, f64, f64) {
+    let (r, g, b) = (color.x(), color.y(), color.z());
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+
+    let hue = if delta <= 0.0 {
+        0.0
+    } else if max == r {
+        ((g - b) / delta).rem_euclid(6.0) / 6.0
+    } else if max == g {
+        ((b - r) / delta + 2.0) / 6.0
+    } else {
+        ((r - g) / delta + 4.0) / 6.0
+    };
+    let saturation = if max <= 0.0 { 0.0 } else { delta / max };
+
+    (hue, saturation, max)
+}
+
+pub fn hsv_to_rgb(hue: f64, saturation: f64, value: f64) -> Color {
+    let h = hue.rem_euclid(1.0) * 6.0;
+    let c = value * saturation;
+    let x = c * (1.0 - (h.rem_euclid(2.0) - 1.0).abs());
+    let m = value - c;
+
+    let (r, g, b) = match h as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+
+    Color::new(r + m, g + m, b + m)
+}
+
+/// Linear Rec. 709 colour of CIE XYZ tristimulus values, clamped to the
+/// gamut.
+pub fn xyz_to_rgb(x: f64, y: f64, z: f64) -> Color {
+    Color::new(
+        (3.2406 * x - 1.5372 * y - 0.4986 * z).max(0.0),
+        (-0.9689 * x + 1.8758 * y + 0.0415 * z).max(0.0),
+        (0.0557 * x - 0.2040 * y + 1.0570 * z).max(0.0),
+    )
+}
+
+/// Linear Rec. 709 colour of CIE xyY chromaticity and luminance.
+pub fn xyy_to_rgb(x: f64, y: f64, luminance: f64) -> Color {
+    if y <= 0.0 {
+        return Color::zero();
+    }
+
+    xyz_to_rgb(x / y * luminance, luminance, (1.0 - x - y) / y * luminance)
+}
+
+/// CIE 1931 colour matching functions at `lambda` nanometres, using the
+/// multi-lobe Gaussian fit of Wyman, Sloan and Shirley.
+fn color_matching(lambda: f64) -> (f64, f64, f64) {
+    let lobe = |mu: f64, below: f64, above: f64| {
+        let t = (lambda - mu) / if lambda < mu { below } else { above };
+        (-0.5 * t * t).exp()
+    };
+
+    (
+        1.056 * lobe(599.8, 37.9, 31.0) + 0.362 * lobe(442.0, 16.0, 26.7)
+            - 0.065 * lobe(501.1, 20.4, 26.2),
+        0.821 * lobe(568.8, 46.9, 40.5) + 0.286 * lobe(530.9, 16.3, 31.1),
+        1.217 * lobe(437.0, 11.8, 36.0) + 0.681 * lobe(459.0, 26.0, 13.8),
+    )
+}
+
+/// Colour of a black body at `kelvin`, scaled to unit luminance.
+pub fn blackbody(kelvin: f64) -> Color {
+    const H: f64 = 6.62607015e-34;
+    const C: f64 = 2.99792458e8;
+    const K: f64 = 1.380649e-23;
+
+    let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+    for step in 0..=80 {
+        let lambda = 380.0 + 5.0 * step as f64;
+        let meters = lambda * 1e-9;
+        let radiance = 1.0 / (meters.powi(5) * ((H * C / (meters * K * kelvin.max(1.0))).exp_m1()));
+        let (cx, cy, cz) = color_matching(lambda);
+
+        x += radiance * cx;
+        y += radiance * cy;
+        z += radiance * cz;
+    }
+
+    if y <= 0.0 || !y.is_finite() {
+        return Color::zero();
+    }
+
+    let color = xyz_to_rgb(x / y, 1.0, z / y);
+    color / luminance(&color)
 }
@@ -0,0 +1,116 @@
+/// Piecewise constant density over `[0, 1)` proportional to `func`, one
+/// equally wide segment per value.
+pub struct Distribution1D {
+    pub func: Vec<f64>,
+    cdf: Vec<f64>,
+    pub integral: f64,
+}
+
+impl Distribution1D {
+    /// Negative values count as zero. When all values are zero the density is
+    /// uniform instead.
+    pub fn new(func: Vec<f64>) -> Self {
+        let func: Vec<f64> = func.into_iter().map(|f| f.max(0.0)).collect();
+        let n = func.len().max(1) as f64;
+
+        let mut cdf = Vec::with_capacity(func.len() + 1);
+        cdf.push(0.0);
+        for f in func.iter() {
+            cdf.push(cdf[cdf.len() - 1] + f / n);
+        }
+
+        let integral = cdf[cdf.len() - 1];
+        for (i, c) in cdf.iter_mut().enumerate() {
+            *c = if integral > 0.0 {
+                *c / integral
+            } else {
+                i as f64 / n
+            };
+        }
+
+        Self {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    #[inline(always)]
+    fn len(&self) -> usize {
+        self.func.len()
+    }
+
+    /// Maps uniform `u` to a point in `[0, 1)`. Returns the point, its density
+    /// and the segment it falls in.
+    pub fn sample(&self, u: f64) -> (f64, f64, usize) {
+        let segment = self
+            .cdf
+            .partition_point(|c| *c <= u)
+            .saturating_sub(1)
+            .min(self.len().saturating_sub(1));
+
+        let width = self.cdf[segment + 1] - self.cdf[segment];
+        let offset = if width > 0.0 {
+            ((u - self.cdf[segment]) / width).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+
+        let x = ((segment as f64 + offset) / self.len() as f64).min(1.0 - f64::EPSILON);
+        (x, self.segment_pdf(segment), segment)
+    }
+
+    pub fn pdf(&self, x: f64) -> f64 {
+        self.segment_pdf(self.segment(x))
+    }
+
+    #[inline(always)]
+    fn segment(&self, x: f64) -> usize {
+        ((x * self.len() as f64).max(0.0) as usize).min(self.len().saturating_sub(1))
+    }
+
+    #[inline(always)]
+    fn segment_pdf(&self, segment: usize) -> f64 {
+        if self.integral > 0.0 {
+            self.func[segment] / self.integral
+        } else {
+            1.0
+        }
+    }
+}
+
+/// Piecewise constant density over `[0, 1)^2` proportional to a grid of
+/// values stored row by row. Rows are picked first, then a column within
+/// the row.
+pub struct Distribution2D {
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(func: &[f64], width: usize, height: usize) -> Self {
+        assert_eq!(width * height, func.len());
+
+        let rows: Vec<Distribution1D> = func
+            .chunks_exact(width)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(rows.iter().map(|row| row.integral).collect());
+
+        Self { rows, marginal }
+    }
+
+    /// Maps uniform `(u1, u2)` to a point `(x, y)` and its density.
+    pub fn sample(&self, u1: f64, u2: f64) -> ((f64, f64), f64) {
+        let (y, row_pdf, row) = self.marginal.sample(u2);
+        let (x, column_pdf, _) = self.rows[row].sample(u1);
+
+        ((x, y), row_pdf * column_pdf)
+    }
+
+    pub fn pdf(&self, x: f64, y: f64) -> f64 {
+        let row = self.marginal.segment(y);
+
+        self.marginal.segment_pdf(row) * self.rows[row].pdf(x)
+    }
+}
@@ -0,0 +1,339 @@
+use crate::color::luminance;
+use crate::image::Image;
+use crate::vec3::Color;
+use std::f64::consts::PI;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FilterType {
+    /// Equal weight over a square, the plain average of the pixel with the
+    /// default radius.
+    Box,
+    /// Weight falling off linearly to zero at the radius.
+    Tent,
+    /// Gaussian with a standard deviation of a third of the radius, shifted
+    /// down to reach zero at the radius.
+    Gaussian,
+    /// Mitchell-Netravali cubic with B = C = 1/3. Sharper than the Gaussian,
+    /// with small negative lobes.
+    Mitchell,
+    /// Sinc windowed by a wider sinc, with as many lobes as the radius.
+    /// Sharpest, but rings around hard edges.
+    Lanczos,
+}
+
+impl FilterType {
+    /// Names of the filters in scene files and on the command line.
+    pub const NAMES: [(&'static str, FilterType); 5] = [
+        ("box", FilterType::Box),
+        ("tent", FilterType::Tent),
+        ("gaussian", FilterType::Gaussian),
+        ("mitchell", FilterType::Mitchell),
+        ("lanczos", FilterType::Lanczos),
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::NAMES
+            .iter()
+            .find(|(option, _)| *option == name)
+            .map(|(_, filter)| *filter)
+    }
+
+    pub fn name(&self) -> &'static str {
+        Self::NAMES
+            .iter()
+            .find(|(_, filter)| filter == self)
+            .map_or("box", |(name, _)| name)
+    }
+
+    /// Radius in pixels used when none is given.
+    pub fn default_radius(&self) -> f64 {
+        match self {
+            FilterType::Box => 0.5,
+            FilterType::Tent => 1.0,
+            FilterType::Gaussian => 1.5,
+            FilterType::Mitchell => 2.0,
+            FilterType::Lanczos => 3.0,
+        }
+    }
+}
+
+/// Weight of a sample for the pixels around it, by its offset from their
+/// centers in pixels. Samples further than `radius` along either axis do not
+/// count.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Filter {
+    pub filter_type: FilterType,
+    pub radius: f64,
+}
+
+impl Filter {
+    pub fn new(filter_type: FilterType) -> Self {
+        Self {
+            filter_type,
+            radius: filter_type.default_radius(),
+        }
+    }
+
+    pub fn with_radius(mut self, radius: f64) -> Self {
+        self.radius = radius;
+        self
+    }
+
+    #[inline(always)]
+    pub fn evaluate(&self, dx: f64, dy: f64) -> f64 {
+        self.evaluate_1d(dx) * self.evaluate_1d(dy)
+    }
+
+    fn evaluate_1d(&self, x: f64) -> f64 {
+        let x = x.abs();
+        let radius = self.radius;
+
+        if x >= radius {
+            return 0.0;
+        }
+
+        match self.filter_type {
+            FilterType::Box => 1.0,
+            FilterType::Tent => radius - x,
+            FilterType::Gaussian => {
+                let sigma = radius / 3.0;
+                let gaussian = |x: f64| (-x * x / (2.0 * sigma * sigma)).exp();
+
+                gaussian(x) - gaussian(radius)
+            }
+            FilterType::Mitchell => mitchell(2.0 * x / radius),
+            FilterType::Lanczos => sinc(x) * sinc(x / radius),
+        }
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self::new(FilterType::Box)
+    }
+}
+
+/// Mitchell-Netravali cubic with B = C = 1/3 over `[0, 2]`.
+fn mitchell(x: f64) -> f64 {
+    const B: f64 = 1.0 / 3.0;
+    const C: f64 = 1.0 / 3.0;
+
+    let value = if x < 1.0 {
+        (12.0 - 9.0 * B - 6.0 * C) * x * x * x
+            + (-18.0 + 12.0 * B + 6.0 * C) * x * x
+            + (6.0 - 2.0 * B)
+    } else {
+        (-B - 6.0 * C) * x * x * x
+            + (6.0 * B + 30.0 * C) * x * x
+            + (-12.0 * B - 48.0 * C) * x
+            + (8.0 * B + 24.0 * C)
+    };
+
+    value / 6.0
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-5 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// Accumulated samples of one pixel.
+#[derive(Debug, Copy, Clone)]
+pub struct FilmPixel {
+    /// Sum of the filter weighted radiance of the samples.
+    pub weighted_sum: Color,
+    /// Sum of the filter weights of the samples.
+    pub weight: f64,
+    /// Number of samples taken inside the pixel, which may be fewer than
+    /// the samples contributing to it.
+    pub samples: u32,
+    /// Luminance of the finite samples taken inside the pixel.
+    pub luminance: RunningStatistics,
+}
+
+impl Default for FilmPixel {
+    fn default() -> Self {
+        Self {
+            weighted_sum: Color::zero(),
+            weight: 0.0,
+            samples: 0,
+            luminance: RunningStatistics::default(),
+        }
+    }
+}
+
+impl FilmPixel {
+    pub fn color(&self) -> Color {
+        if self.weight == 0.0 {
+            Color::zero()
+        } else {
+            self.weighted_sum / self.weight
+        }
+    }
+
+    /// Half width of the 95% confidence interval of the mean luminance,
+    /// relative to the mean. Infinite until there are two samples.
+    pub fn relative_error(&self) -> f64 {
+        // the mean of dark pixels is floored, so their noise is not
+        // magnified without bound
+        const MIN_MEAN: f64 = 0.01;
+        const Z_95: f64 = 1.96;
+
+        let statistics = &self.luminance;
+        if statistics.count < 2 {
+            return f64::INFINITY;
+        }
+
+        Z_95 * (statistics.variance() / statistics.count as f64).sqrt()
+            / statistics.mean.abs().max(MIN_MEAN)
+    }
+}
+
+/// Mean and variance of a stream of values, updated one value at a time
+/// (Welford's method).
+#[derive(Debug, Copy, Clone, Default)]
+pub struct RunningStatistics {
+    pub count: u32,
+    pub mean: f64,
+    /// Sum of squared differences from the mean.
+    squared_deviations: f64,
+}
+
+impl RunningStatistics {
+    pub fn add(&mut self, value: f64) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.squared_deviations += delta * (value - self.mean);
+    }
+
+    /// Unbiased sample variance.
+    pub fn variance(&self) -> f64 {
+        if self.count < 2 {
+            0.0
+        } else {
+            self.squared_deviations / (self.count - 1) as f64
+        }
+    }
+}
+
+/// Accumulation buffer the samples of a render are splatted into. Film
+/// positions have `x` to the right and `y` up from the lower left corner,
+/// and pixel `(i, j)` covers `[i, i + 1) x [j, j + 1)`. Every sample adds
+/// to all pixels within the filter radius of it, weighted by the filter.
+/// The sums are kept rather than the averages, so a render can be continued
+/// with more samples.
+pub struct Film {
+    pub width: usize,
+    pub height: usize,
+    pub filter: Filter,
+    /// Bottom row first.
+    pixels: Vec<FilmPixel>,
+}
+
+impl Film {
+    pub fn new(width: usize, height: usize, filter: Filter) -> Self {
+        Self {
+            width,
+            height,
+            filter,
+            pixels: vec![FilmPixel::default(); width * height],
+        }
+    }
+
+    #[inline(always)]
+    pub fn pixel(&self, i: usize, j: usize) -> &FilmPixel {
+        &self.pixels[j * self.width + i]
+    }
+
+    /// Adds a sample of `radiance` taken at film position `(x, y)`. Samples
+    /// that are not finite are counted but left out, so one of them does not
+    /// spoil the pixels around it.
+    pub fn add_sample(&mut self, x: f64, y: f64, radiance: Color) {
+        let finite =
+            radiance.x().is_finite() && radiance.y().is_finite() && radiance.z().is_finite();
+
+        let (i, j) = (x.floor(), y.floor());
+        if i >= 0.0 && j >= 0.0 && (i as usize) < self.width && (j as usize) < self.height {
+            let pixel = &mut self.pixels[j as usize * self.width + i as usize];
+            pixel.samples += 1;
+            if finite {
+                pixel.luminance.add(luminance(&radiance));
+            }
+        }
+
+        if !finite {
+            return;
+        }
+
+        let radius = self.filter.radius;
+        let (x0, x1) = self.pixel_range(x, radius, self.width);
+        let (y0, y1) = self.pixel_range(y, radius, self.height);
+
+        for j in y0..y1 {
+            for i in x0..x1 {
+                let weight = self
+                    .filter
+                    .evaluate(x - (i as f64 + 0.5), y - (j as f64 + 0.5));
+                if weight != 0.0 {
+                    let pixel = &mut self.pixels[j * self.width + i];
+                    pixel.weighted_sum += weight * radiance;
+                    pixel.weight += weight;
+                }
+            }
+        }
+    }
+
+    /// Pixels along one axis whose centers may lie within `radius` of
+    /// `position`.
+    fn pixel_range(&self, position: f64, radius: f64, size: usize) -> (usize, usize) {
+        let first = (position - 0.5 - radius).ceil().max(0.0) as usize;
+        let last = (position - 0.5 + radius).floor() + 1.0;
+
+        (first, (last.max(0.0) as usize).min(size))
+    }
+
+    /// Samples taken per pixel as a heat map, top row first: black for
+    /// none, through red and yellow to white for the most sampled pixel.
+    pub fn heat_map(&self) -> Image {
+        let most = self
+            .pixels
+            .iter()
+            .map(|pixel| pixel.samples)
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        let heat = |pixel: &FilmPixel| {
+            let t = 3.0 * pixel.samples as f64 / most as f64;
+            Color::new(
+                t.min(1.0),
+                (t - 1.0).clamp(0.0, 1.0),
+                (t - 2.0).clamp(0.0, 1.0),
+            )
+        };
+
+        let pixels = self
+            .pixels
+            .chunks(self.width)
+            .rev()
+            .flat_map(|row| row.iter().map(heat))
+            .collect();
+
+        Image::new(self.width, self.height, pixels)
+    }
+
+    /// The filtered linear radiance of every pixel, top row first.
+    pub fn image(&self) -> Image {
+        let pixels = self
+            .pixels
+            .chunks(self.width)
+            .rev()
+            .flat_map(|row| row.iter().map(FilmPixel::color))
+            .collect();
+
+        Image::new(self.width, self.height, pixels)
+    }
+}
@@ -0,0 +1,171 @@
+use crate::image::invalid_data;
+use crate::math_traits::InnerProduct;
+use crate::vec3::Vec3;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Luminous intensity distribution of a luminaire from an IES LM-63
+/// photometric file, with type C angles: vertical angles run from 0 at the
+/// nadir (the emission axis) to 180 straight up, horizontal angles go around
+/// the axis.
+pub struct IesProfile {
+    pub vertical: Vec<f64>,
+    pub horizontal: Vec<f64>,
+    /// Candela values, one row of vertical angles per horizontal angle.
+    pub candela: Vec<Vec<f64>>,
+    pub max_candela: f64,
+    /// The file the profile was loaded from, so scenes can refer to it again.
+    pub path: Option<PathBuf>,
+}
+
+impl IesProfile {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut profile = Self::parse(&fs::read_to_string(&path)?)?;
+        profile.path = Some(path.as_ref().to_path_buf());
+
+        Ok(profile)
+    }
+
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let mut lines = text.lines();
+
+        let tilt = loop {
+            let line = lines
+                .next()
+                .ok_or_else(|| invalid_data("missing TILT line"))?
+                .trim();
+
+            if let Some(tilt) = line.strip_prefix("TILT=") {
+                break tilt.trim().to_string();
+            }
+        };
+
+        let mut numbers = lines.flat_map(|line| {
+            line.split(|c: char| c.is_whitespace() || c == ',')
+                .filter(|token| !token.is_empty())
+        });
+        let mut next = || -> io::Result<f64> {
+            let token = numbers
+                .next()
+                .ok_or_else(|| invalid_data("unexpected end of IES data"))?;
+            token
+                .parse()
+                .map_err(|_| invalid_data(format!("invalid IES number {:?}", token)))
+        };
+
+        if tilt == "INCLUDE" {
+            // lamp to luminaire geometry, then the tilt angles and factors
+            next()?;
+            let count = next()? as usize;
+            for _ in 0..2 * count {
+                next()?;
+            }
+        } else if tilt != "NONE" {
+            return Err(invalid_data("TILT files are not supported"));
+        }
+
+        let _lamps = next()?;
+        let _lumens = next()?;
+        let multiplier = next()?;
+        let vertical_count = next()? as usize;
+        let horizontal_count = next()? as usize;
+        let photometric_type = next()?;
+        // units, luminous opening, ballast factor, future use and watts
+        for _ in 0..7 {
+            next()?;
+        }
+
+        if photometric_type != 1.0 {
+            return Err(invalid_data("only type C photometry is supported"));
+        }
+        if vertical_count == 0 || horizontal_count == 0 {
+            return Err(invalid_data("IES file has no angles"));
+        }
+
+        let vertical = (0..vertical_count)
+            .map(|_| next())
+            .collect::<io::Result<Vec<f64>>>()?;
+        let horizontal = (0..horizontal_count)
+            .map(|_| next())
+            .collect::<io::Result<Vec<f64>>>()?;
+        let candela = (0..horizontal_count)
+            .map(|_| {
+                (0..vertical_count)
+                    .map(|_| Ok(next()? * multiplier))
+                    .collect::<io::Result<Vec<f64>>>()
+            })
+            .collect::<io::Result<Vec<Vec<f64>>>>()?;
+
+        let max_candela = candela.iter().flatten().fold(0.0f64, |a, b| a.max(*b));
+
+        Ok(Self {
+            vertical,
+            horizontal,
+            candela,
+            max_candela,
+            path: None,
+        })
+    }
+
+    /// Candela at the given angles in degrees, interpolated bilinearly. The
+    /// symmetry implied by the last horizontal angle is applied, and the
+    /// intensity outside the measured vertical range is zero.
+    pub fn candela(&self, vertical: f64, horizontal: f64) -> f64 {
+        if vertical < self.vertical[0] || vertical > self.vertical[self.vertical.len() - 1] {
+            return 0.0;
+        }
+
+        let horizontal = horizontal.rem_euclid(360.0);
+        let horizontal = match self.horizontal[self.horizontal.len() - 1] {
+            last if last <= 0.0 => 0.0,
+            last if last <= 90.0 => {
+                let h = horizontal % 180.0;
+                if h > 90.0 {
+                    180.0 - h
+                } else {
+                    h
+                }
+            }
+            last if last <= 180.0 && horizontal > 180.0 => 360.0 - horizontal,
+            _ => horizontal,
+        };
+
+        let (h0, h1, th) = bracket(&self.horizontal, horizontal);
+        let (v0, v1, tv) = bracket(&self.vertical, vertical);
+
+        let row = |h: usize| (1.0 - tv) * self.candela[h][v0] + tv * self.candela[h][v1];
+        (1.0 - th) * row(h0) + th * row(h1)
+    }
+
+    /// Intensity towards `direction`, relative to the brightest direction.
+    /// `direction` is in the frame of the luminaire, with the emission axis
+    /// along z and horizontal angle zero along x.
+    pub fn relative(&self, direction: &Vec3) -> f64 {
+        if self.max_candela <= 0.0 {
+            return 0.0;
+        }
+
+        let direction = direction.unit();
+        let vertical = direction.z().clamp(-1.0, 1.0).acos().to_degrees();
+        let horizontal = direction.y().atan2(direction.x()).to_degrees();
+
+        self.candela(vertical, horizontal) / self.max_candela
+    }
+}
+
+/// Indices of the sorted `angles` around `angle` and the interpolation
+/// weight between them. Angles outside the range snap to the closest end.
+fn bracket(angles: &[f64], angle: f64) -> (usize, usize, f64) {
+    let upper = angles.partition_point(|a| *a <= angle);
+
+    if upper == 0 {
+        return (0, 0, 0.0);
+    }
+    if upper >= angles.len() {
+        return (angles.len() - 1, angles.len() - 1, 0.0);
+    }
+
+    let (a0, a1) = (angles[upper - 1], angles[upper]);
+    (upper - 1, upper, (angle - a0) / (a1 - a0))
+}
@@ -0,0 +1,594 @@
+use crate::color;
+use crate::math_traits::InnerProduct;
+use crate::vec3::Color;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Color>,
+    pub alpha: Vec<f64>,
+    /// The file the image was loaded from, so scenes can refer to it again.
+    pub source: Option<ImageSource>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ImageSource {
+    pub path: PathBuf,
+    pub srgb: bool,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>) -> Self {
+        assert_eq!(width * height, pixels.len());
+
+        Self {
+            width,
+            height,
+            alpha: vec![1.0; pixels.len()],
+            pixels,
+            source: None,
+        }
+    }
+
+    /// Loads a PPM (P3/P6), PNG, Radiance HDR or PFM file, picking the decoder
+    /// from the extension. PPM and PNG texel values are normalized to
+    /// `[0, 1]`, while the HDR formats keep their linear values. Colors are
+    /// converted from sRGB to linear when `srgb` is set.
+    pub fn load<P: AsRef<Path>>(path: P, srgb: bool) -> io::Result<Self> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+
+        let mut image = match extension.as_deref() {
+            Some("ppm") => Self::load_ppm(path)?,
+            Some("png") => Self::load_png(path)?,
+            Some("hdr") | Some("pic") => Self::load_hdr(path)?,
+            Some("pfm") => Self::load_pfm(path)?,
+            _ => {
+                return Err(invalid_data(format!(
+                    "unsupported image format: {}",
+                    path.display()
+                )))
+            }
+        };
+
+        if srgb {
+            for pixel in image.pixels.iter_mut() {
+                *pixel = color::srgb_to_linear(pixel);
+            }
+        }
+        image.source = Some(ImageSource {
+            path: path.to_path_buf(),
+            srgb,
+        });
+
+        Ok(image)
+    }
+
+    pub fn load_ppm<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        let magic = read_ppm_token(&mut reader)?;
+        let width: usize = parse_ppm_header(&read_ppm_token(&mut reader)?)?;
+        let height: usize = parse_ppm_header(&read_ppm_token(&mut reader)?)?;
+        let max_value: usize = parse_ppm_header(&read_ppm_token(&mut reader)?)?;
+
+        if max_value == 0 || max_value > u16::MAX as usize {
+            return Err(invalid_data(format!("invalid PPM max value {}", max_value)));
+        }
+
+        let scale = 1.0 / max_value as f64;
+        let count = sample_count(width, height, 3)?;
+        let mut samples = Vec::new();
+
+        match magic.as_str() {
+            "P3" => {
+                for _ in 0..count {
+                    let sample: usize = parse_ppm_header(&read_ppm_token(&mut reader)?)?;
+                    if sample > max_value {
+                        return Err(invalid_data(format!(
+                            "PPM sample {} is above the max value {}",
+                            sample, max_value
+                        )));
+                    }
+                    samples.push(sample as f64);
+                }
+            }
+            "P6" => {
+                let wide = max_value > u8::MAX as usize;
+                let raw = read_bytes(
+                    &mut reader,
+                    sample_count(width, height, if wide { 6 } else { 3 })?,
+                )?;
+
+                if wide {
+                    samples.extend(
+                        raw.chunks_exact(2)
+                            .map(|b| u16::from_be_bytes([b[0], b[1]]) as f64),
+                    );
+                } else {
+                    samples.extend(raw.iter().map(|b| *b as f64));
+                }
+            }
+            _ => return Err(invalid_data(format!("unsupported PPM type {}", magic))),
+        }
+
+        let pixels = samples
+            .chunks_exact(3)
+            .map(|c| Color::new(c[0] * scale, c[1] * scale, c[2] * scale))
+            .collect();
+
+        Ok(Self::new(width, height, pixels))
+    }
+
+    pub fn load_png<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+        decoder.set_transformations(png::Transformations::EXPAND);
+
+        let mut reader = decoder.read_info().map_err(invalid_data)?;
+        let buffer_size = reader
+            .output_buffer_size()
+            .ok_or_else(|| invalid_data("PNG image is too large"))?;
+        let mut buffer = vec![0u8; buffer_size];
+        let info = reader.next_frame(&mut buffer).map_err(invalid_data)?;
+
+        let channels = info.color_type.samples();
+        let (depth, scale) = match info.bit_depth {
+            png::BitDepth::Sixteen => (2, 1.0 / u16::MAX as f64),
+            _ => (1, 1.0 / u8::MAX as f64),
+        };
+
+        let sample = |data: &[u8], index: usize| -> f64 {
+            let offset = index * depth;
+            if depth == 2 {
+                u16::from_be_bytes([data[offset], data[offset + 1]]) as f64 * scale
+            } else {
+                data[offset] as f64 * scale
+            }
+        };
+
+        let width = info.width as usize;
+        let height = info.height as usize;
+        let mut pixels = Vec::with_capacity(width * height);
+        let mut alpha = Vec::with_capacity(width * height);
+
+        for y in 0..height {
+            let row = &buffer[y * info.line_size..(y + 1) * info.line_size];
+
+            for x in 0..width {
+                let base = x * channels;
+                let (color, a) = match info.color_type {
+                    png::ColorType::Grayscale => {
+                        let g = sample(row, base);
+                        (Color::new(g, g, g), 1.0)
+                    }
+                    png::ColorType::GrayscaleAlpha => {
+                        let g = sample(row, base);
+                        (Color::new(g, g, g), sample(row, base + 1))
+                    }
+                    png::ColorType::Rgb => (
+                        Color::new(
+                            sample(row, base),
+                            sample(row, base + 1),
+                            sample(row, base + 2),
+                        ),
+                        1.0,
+                    ),
+                    png::ColorType::Rgba => (
+                        Color::new(
+                            sample(row, base),
+                            sample(row, base + 1),
+                            sample(row, base + 2),
+                        ),
+                        sample(row, base + 3),
+                    ),
+                    png::ColorType::Indexed => {
+                        return Err(invalid_data("indexed PNG was not expanded"))
+                    }
+                };
+
+                pixels.push(color);
+                alpha.push(a);
+            }
+        }
+
+        Ok(Self {
+            width,
+            height,
+            pixels,
+            alpha,
+            source: None,
+        })
+    }
+
+    /// Radiance RGBE image, flat or with run length encoded scanlines.
+    pub fn load_hdr<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        if !line.starts_with("#?") {
+            return Err(invalid_data("missing Radiance HDR signature"));
+        }
+
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(invalid_data("unexpected end of HDR header"));
+            }
+
+            let line = line.trim();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(format) = line.strip_prefix("FORMAT=") {
+                if format != "32-bit_rle_rgbe" {
+                    return Err(invalid_data(format!("unsupported HDR format {}", format)));
+                }
+            }
+        }
+
+        line.clear();
+        reader.read_line(&mut line)?;
+        let resolution: Vec<&str> = line.split_whitespace().collect();
+        let (height, width, flip) = match resolution.as_slice() {
+            ["-Y", height, "+X", width] => (*height, *width, false),
+            ["+Y", height, "+X", width] => (*height, *width, true),
+            _ => {
+                return Err(invalid_data(format!(
+                    "unsupported HDR orientation {:?}",
+                    line.trim()
+                )))
+            }
+        };
+        let width: usize = width
+            .parse()
+            .map_err(|_| invalid_data("invalid HDR width"))?;
+        let height: usize = height
+            .parse()
+            .map_err(|_| invalid_data("invalid HDR height"))?;
+
+        // the pixels grow with the scanlines read, once the size is sane
+        sample_count(width, height, 4)?;
+        let mut pixels = Vec::new();
+        let mut scanline = vec![[0u8; 4]; width];
+
+        for _ in 0..height {
+            read_rgbe_scanline(&mut reader, &mut scanline)?;
+
+            pixels.extend(scanline.iter().map(|rgbe| {
+                if rgbe[3] == 0 {
+                    return Color::zero();
+                }
+
+                let scale = 2f64.powi(rgbe[3] as i32 - 136);
+                Color::new(
+                    (rgbe[0] as f64 + 0.5) * scale,
+                    (rgbe[1] as f64 + 0.5) * scale,
+                    (rgbe[2] as f64 + 0.5) * scale,
+                )
+            }));
+        }
+
+        if flip {
+            pixels = flip_rows(pixels, width);
+        }
+
+        Ok(Self::new(width, height, pixels))
+    }
+
+    /// Portable float map, colour (`PF`) or grey scale (`Pf`).
+    pub fn load_pfm<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        let magic = read_ppm_token(&mut reader)?;
+        let channels = match magic.as_str() {
+            "PF" => 3,
+            "Pf" => 1,
+            _ => return Err(invalid_data(format!("unsupported PFM type {}", magic))),
+        };
+        let width: usize = parse_ppm_header(&read_ppm_token(&mut reader)?)?;
+        let height: usize = parse_ppm_header(&read_ppm_token(&mut reader)?)?;
+        let scale: f64 = parse_ppm_header(&read_ppm_token(&mut reader)?)?;
+
+        let raw = read_bytes(&mut reader, sample_count(width, height, channels * 4)?)?;
+
+        let samples: Vec<f64> = raw
+            .chunks_exact(4)
+            .map(|b| {
+                let bytes = [b[0], b[1], b[2], b[3]];
+                if scale < 0.0 {
+                    f32::from_le_bytes(bytes) as f64
+                } else {
+                    f32::from_be_bytes(bytes) as f64
+                }
+            })
+            .collect();
+
+        let pixels = samples
+            .chunks_exact(channels)
+            .map(|c| match c {
+                [r, g, b] => Color::new(*r, *g, *b),
+                _ => Color::new(c[0], c[0], c[0]),
+            })
+            .collect();
+
+        // rows are stored bottom to top
+        Ok(Self::new(width, height, flip_rows(pixels, width)))
+    }
+
+    /// A grey scale copy of the alpha channel, e.g. for opacity masks.
+    pub fn alpha_image(&self) -> Self {
+        Self::new(
+            self.width,
+            self.height,
+            self.alpha.iter().map(|a| Color::new(*a, *a, *a)).collect(),
+        )
+    }
+
+    /// Places `right` to the right of `left`. Both must have the same height.
+    pub fn side_by_side(left: &Self, right: &Self) -> Self {
+        assert_eq!(left.height, right.height);
+
+        let mut pixels = Vec::with_capacity(left.pixels.len() + right.pixels.len());
+        for y in 0..left.height {
+            pixels.extend_from_slice(&left.pixels[y * left.width..(y + 1) * left.width]);
+            pixels.extend_from_slice(&right.pixels[y * right.width..(y + 1) * right.width]);
+        }
+
+        Self::new(left.width + right.width, left.height, pixels)
+    }
+
+    /// Places `bottom` below `top`. Both must have the same width.
+    pub fn top_bottom(top: &Self, bottom: &Self) -> Self {
+        assert_eq!(top.width, bottom.width);
+
+        let mut pixels = top.pixels.clone();
+        pixels.extend_from_slice(&bottom.pixels);
+
+        Self::new(top.width, top.height + bottom.height, pixels)
+    }
+
+    /// Writes a gamma corrected ASCII PPM, top row first.
+    pub fn write_ppm<T: Write>(&self, io: &mut T) {
+        let _ = io.write(format!("P3\n{} {}\n255\n", self.width, self.height).as_bytes());
+
+        for pixel in self.pixels.iter() {
+            color::write_color(io, pixel, 1);
+        }
+    }
+
+    /// Writes a gamma corrected 8 bit RGB PNG.
+    pub fn write_png<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(
+            BufWriter::new(File::create(path)?),
+            self.width as u32,
+            self.height as u32,
+        );
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let data: Vec<u8> = self
+            .pixels
+            .iter()
+            .flat_map(|pixel| color::quantize(pixel, 1))
+            .collect();
+
+        let mut writer = encoder.write_header().map_err(invalid_data)?;
+        writer.write_image_data(&data).map_err(invalid_data)?;
+        writer.finish().map_err(invalid_data)
+    }
+
+    /// Root mean square difference from `reference` over all pixels and
+    /// channels. Both must have the same size.
+    pub fn rmse(&self, reference: &Self) -> f64 {
+        assert_eq!(
+            (self.width, self.height),
+            (reference.width, reference.height)
+        );
+
+        let squared: f64 = self
+            .pixels
+            .iter()
+            .zip(reference.pixels.iter())
+            .map(|(pixel, expected)| (*pixel - *expected).length_squared())
+            .sum();
+
+        (squared / (3 * self.pixels.len()).max(1) as f64).sqrt()
+    }
+
+    #[inline(always)]
+    pub fn get(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x]
+    }
+}
+
+pub fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+fn flip_rows(pixels: Vec<Color>, width: usize) -> Vec<Color> {
+    pixels
+        .chunks_exact(width.max(1))
+        .rev()
+        .flatten()
+        .copied()
+        .collect()
+}
+
+/// Reads one scanline of RGBE pixels, which is either run length encoded
+/// per channel or stored flat.
+fn read_rgbe_scanline<R: BufRead>(reader: &mut R, scanline: &mut [[u8; 4]]) -> io::Result<()> {
+    let width = scanline.len();
+    let mut header = [0u8; 4];
+    reader.read_exact(&mut header)?;
+
+    let encoded = (8..0x8000).contains(&width) && header[0] == 2 && header[1] == 2;
+    if !encoded || header[2] & 0x80 != 0 {
+        scanline[0] = header;
+        for pixel in scanline.iter_mut().skip(1) {
+            reader.read_exact(pixel)?;
+        }
+        return Ok(());
+    }
+
+    if ((header[2] as usize) << 8 | header[3] as usize) != width {
+        return Err(invalid_data("HDR scanline width mismatch"));
+    }
+
+    let mut byte = [0u8; 1];
+    for channel in 0..4 {
+        let mut x = 0;
+
+        while x < width {
+            reader.read_exact(&mut byte)?;
+            let (count, run) = if byte[0] > 128 {
+                ((byte[0] - 128) as usize, true)
+            } else {
+                (byte[0] as usize, false)
+            };
+
+            if count == 0 || x + count > width {
+                return Err(invalid_data("corrupt HDR scanline"));
+            }
+
+            if run {
+                reader.read_exact(&mut byte)?;
+                for pixel in scanline[x..x + count].iter_mut() {
+                    pixel[channel] = byte[0];
+                }
+            } else {
+                for pixel in scanline[x..x + count].iter_mut() {
+                    reader.read_exact(&mut byte)?;
+                    pixel[channel] = byte[0];
+                }
+            }
+
+            x += count;
+        }
+    }
+
+    Ok(())
+}
+
+/// Number of values in a `width` by `height` image with `channels` values
+/// per pixel, or an error when the header asks for more than can exist.
+fn sample_count(width: usize, height: usize, channels: usize) -> io::Result<usize> {
+    width
+        .checked_mul(height)
+        .and_then(|pixels| pixels.checked_mul(channels))
+        .ok_or_else(|| invalid_data(format!("image size {}x{} is too large", width, height)))
+}
+
+/// Reads `count` bytes of image data. The buffer grows as the data arrives,
+/// so a header claiming a huge image fails at the end of the file instead of
+/// allocating it all up front.
+fn read_bytes<R: Read>(reader: &mut R, count: usize) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    reader.take(count as u64).read_to_end(&mut bytes)?;
+
+    if bytes.len() < count {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "unexpected end of image data",
+        ));
+    }
+    Ok(bytes)
+}
+
+fn parse_ppm_header<T: std::str::FromStr>(token: &str) -> io::Result<T> {
+    token
+        .parse()
+        .map_err(|_| invalid_data(format!("invalid PPM value {:?}", token)))
+}
+
+/// Reads a whitespace separated PPM token, skipping `#` comments. Exactly one
+/// whitespace byte after the token is consumed, as required before P6 data.
+fn read_ppm_token<R: BufRead>(reader: &mut R) -> io::Result<String> {
+    let mut token = String::new();
+    let mut byte = [0u8; 1];
+
+    loop {
+        if reader.read(&mut byte)? == 0 {
+            break;
+        }
+
+        match byte[0] {
+            b'#' if token.is_empty() => {
+                let mut comment = Vec::new();
+                reader.read_until(b'\n', &mut comment)?;
+            }
+            b if b.is_ascii_whitespace() => {
+                if !token.is_empty() {
+                    break;
+                }
+            }
+            b => token.push(b as char),
+        }
+    }
+
+    if token.is_empty() {
+        Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "unexpected end of PPM file",
+        ))
+    } else {
+        Ok(token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Loads `contents` written to a temporary file named `name`.
+    fn load_bytes(name: &str, contents: &[u8]) -> io::Result<Image> {
+        let path =
+            std::env::temp_dir().join(format!("ray-tracing-{}-{}", std::process::id(), name));
+        std::fs::write(&path, contents)?;
+        let image = Image::load(&path, false);
+        std::fs::remove_file(&path)?;
+
+        image
+    }
+
+    #[test]
+    fn oversized_headers_are_invalid_data() {
+        let huge = format!("{}", usize::MAX / 2);
+        let files = [
+            ("huge.ppm", format!("P6 {} 3 255\n", huge)),
+            ("huge.pfm", format!("PF {} 3 -1.0\n", huge)),
+            ("large.ppm", String::from("P6 100000 100000 255\n")),
+            ("large.pfm", String::from("Pf 100000 100000 -1.0\n")),
+        ];
+
+        for (name, header) in files {
+            let error = load_bytes(name, header.as_bytes()).err().unwrap();
+            assert!(
+                matches!(
+                    error.kind(),
+                    io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof
+                ),
+                "{}: {}",
+                name,
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn ppm_samples_above_the_max_value_are_invalid() {
+        let error = load_bytes("bright.ppm", b"P3 1 1 15\n15 16 0\n")
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let image = load_bytes("fine.ppm", b"P3 1 1 15\n15 3 0\n").unwrap();
+        assert_eq!(image.pixels[0], Color::new(1.0, 0.2, 0.0));
+    }
+}
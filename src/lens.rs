@@ -0,0 +1,228 @@
+use crate::color::luminance;
+use crate::image::Image;
+use crate::sampler::{concentric_disk, remap};
+use crate::texture::{ImageTexture, Texture};
+use crate::utils::degrees_to_radians;
+use crate::vec3::{Point3, Vec3};
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+/// Shape of the lens opening, which is also the shape of out of focus
+/// highlights. Samples are returned in the unit disk.
+#[derive(Clone)]
+pub enum ApertureShape {
+    Circle,
+    /// Regular polygon with `blades` corners on the unit circle, rotated by
+    /// `rotation` degrees.
+    Polygon {
+        blades: u32,
+        rotation: f64,
+    },
+    Mask(Arc<ApertureMask>),
+}
+
+impl ApertureShape {
+    /// Point on the aperture for the uniform 2D sample `u`.
+    pub fn sample(&self, u: (f64, f64)) -> Vec3 {
+        match self {
+            ApertureShape::Circle => concentric_disk(u),
+            ApertureShape::Polygon { blades, rotation } => {
+                let blades = (*blades).max(3) as f64;
+                let sector = (u.0 * blades).floor().min(blades - 1.0);
+                let start = degrees_to_radians(*rotation) + sector * 2.0 * PI / blades;
+                let end = start + 2.0 * PI / blades;
+
+                // uniform point in the triangle (center, start corner, end corner)
+                let (mut a, mut b) = (u.0 * blades - sector, u.1);
+                if a + b > 1.0 {
+                    a = 1.0 - a;
+                    b = 1.0 - b;
+                }
+
+                Vec3::new(
+                    a * start.cos() + b * end.cos(),
+                    a * start.sin() + b * end.sin(),
+                    0.0,
+                )
+            }
+            ApertureShape::Mask(mask) => mask.sample(u),
+        }
+    }
+}
+
+/// Grey scale aperture image stretched over the square around the unit disk.
+/// Points are drawn in proportion to the luminance of the image.
+pub struct ApertureMask {
+    pub image: Image,
+    cdf: Vec<f64>,
+}
+
+impl ApertureMask {
+    pub fn new(image: Image) -> Self {
+        let mut total = 0.0;
+        let cdf = image
+            .pixels
+            .iter()
+            .map(|pixel| {
+                total += luminance(pixel).max(0.0);
+                total
+            })
+            .collect();
+
+        Self { image, cdf }
+    }
+
+    pub fn sample(&self, u: (f64, f64)) -> Vec3 {
+        let total = self.cdf.last().copied().unwrap_or(0.0);
+
+        if total <= 0.0 {
+            return Vec3::zero();
+        }
+
+        let target = u.0 * total;
+        let index = self
+            .cdf
+            .partition_point(|c| *c <= target)
+            .min(self.cdf.len() - 1);
+
+        // where the target fell within the chosen pixel places the point
+        // across it
+        let below = if index == 0 { 0.0 } else { self.cdf[index - 1] };
+        let x = (index % self.image.width) as f64 + remap(target, below, self.cdf[index]);
+        let y = (index / self.image.width) as f64 + u.1;
+
+        Vec3::new(
+            2.0 * x / self.image.width as f64 - 1.0,
+            1.0 - 2.0 * y / self.image.height as f64,
+            0.0,
+        )
+    }
+}
+
+/// Real world camera settings. Lengths are in millimetres except
+/// `units_per_meter`, which relates the scene units to metres.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PhysicalLens {
+    pub focal_length: f64,
+    pub sensor_width: f64,
+    pub sensor_height: f64,
+    pub f_number: f64,
+    pub units_per_meter: f64,
+}
+
+impl PhysicalLens {
+    pub fn new(focal_length: f64, sensor_width: f64, sensor_height: f64, f_number: f64) -> Self {
+        Self {
+            focal_length,
+            sensor_width,
+            sensor_height,
+            f_number,
+            units_per_meter: 1.0,
+        }
+    }
+
+    /// 36 x 24 mm sensor.
+    pub fn full_frame(focal_length: f64, f_number: f64) -> Self {
+        Self::new(focal_length, 36.0, 24.0, f_number)
+    }
+
+    /// Vertical field of view in degrees of the largest image with
+    /// `aspect_ratio` that fits on the sensor.
+    pub fn fov(&self, aspect_ratio: f64) -> f64 {
+        let height = self.sensor_height.min(self.sensor_width / aspect_ratio);
+
+        2.0 * (height / (2.0 * self.focal_length)).atan().to_degrees()
+    }
+
+    /// Diameter of the entrance pupil in scene units.
+    pub fn aperture(&self) -> f64 {
+        self.focal_length / self.f_number * 1e-3 * self.units_per_meter
+    }
+}
+
+/// Brown-Conrady lens distortion with three radial (`k1`..`k3`) and two
+/// tangential (`p1`, `p2`) coefficients, as produced by common camera
+/// calibration tools. Coordinates are normalized image coordinates: points
+/// on the image plane at unit distance from the lens, relative to the
+/// optical axis.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BrownConrady {
+    pub k1: f64,
+    pub k2: f64,
+    pub k3: f64,
+    pub p1: f64,
+    pub p2: f64,
+}
+
+impl BrownConrady {
+    const UNDISTORT_ITERATIONS: usize = 20;
+
+    pub fn new(k1: f64, k2: f64, k3: f64, p1: f64, p2: f64) -> Self {
+        Self { k1, k2, k3, p1, p2 }
+    }
+
+    pub fn radial(k1: f64, k2: f64, k3: f64) -> Self {
+        Self::new(k1, k2, k3, 0.0, 0.0)
+    }
+
+    /// Maps an ideal pinhole image point to where the lens images it.
+    pub fn distort(&self, x: f64, y: f64) -> (f64, f64) {
+        let r2 = x * x + y * y;
+        let radial = 1.0 + r2 * (self.k1 + r2 * (self.k2 + r2 * self.k3));
+
+        (
+            x * radial + 2.0 * self.p1 * x * y + self.p2 * (r2 + 2.0 * x * x),
+            y * radial + self.p1 * (r2 + 2.0 * y * y) + 2.0 * self.p2 * x * y,
+        )
+    }
+
+    /// Inverse of `distort`, by fixed point iteration. This is what maps the
+    /// pixels of a plate shot through a real lens back to pinhole rays.
+    pub fn undistort(&self, xd: f64, yd: f64) -> (f64, f64) {
+        let (mut x, mut y) = (xd, yd);
+
+        for _ in 0..Self::UNDISTORT_ITERATIONS {
+            let r2 = x * x + y * y;
+            let radial = 1.0 + r2 * (self.k1 + r2 * (self.k2 + r2 * self.k3));
+            let dx = 2.0 * self.p1 * x * y + self.p2 * (r2 + 2.0 * x * x);
+            let dy = self.p1 * (r2 + 2.0 * y * y) + 2.0 * self.p2 * x * y;
+
+            x = (xd - dx) / radial;
+            y = (yd - dy) / radial;
+        }
+
+        (x, y)
+    }
+
+    /// Resamples a distorted plate into an undistorted image of the same
+    /// size. `half_width` and `half_height` are the normalized coordinates of
+    /// the plate edges, i.e. the tangents of half the horizontal and vertical
+    /// fields of view.
+    pub fn undistort_image(
+        &self,
+        plate: &ImageTexture,
+        half_width: f64,
+        half_height: f64,
+    ) -> Image {
+        let (width, height) = (plate.image.width, plate.image.height);
+        let mut pixels = Vec::with_capacity(width * height);
+
+        for row in 0..height {
+            for column in 0..width {
+                let u = (column as f64 + 0.5) / width as f64;
+                let v = 1.0 - (row as f64 + 0.5) / height as f64;
+
+                let (xd, yd) =
+                    self.distort((2.0 * u - 1.0) * half_width, (2.0 * v - 1.0) * half_height);
+
+                pixels.push(plate.color_value(
+                    0.5 + xd / (2.0 * half_width),
+                    0.5 + yd / (2.0 * half_height),
+                    &Point3::zero(),
+                ));
+            }
+        }
+
+        Image::new(width, height, pixels)
+    }
+}
@@ -0,0 +1,630 @@
+use crate::animation::Quaternion;
+use crate::color::luminance;
+use crate::distribution::Distribution2D;
+use crate::ies::IesProfile;
+use crate::image::Image;
+use crate::light_tree::LightBounds;
+use crate::math_traits::InnerProduct;
+use crate::objects::{HitRecord, Hittable};
+use crate::ray::Ray;
+use crate::rng::Rng;
+use crate::scene_writer::{Entry, SceneWriter};
+use crate::sky::Sky;
+use crate::utils::degrees_to_radians;
+use crate::vec3::{Color, Point3, Vec3};
+use crate::{WorldElementType, WorldType};
+use std::f64::consts::PI;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Incident light at a shading point from one light sample.
+pub struct LightSample {
+    /// Unit direction from the shading point towards the light.
+    pub direction: Vec3,
+    /// Distance to the light along `direction`, infinite for distant lights.
+    pub distance: f64,
+    /// Incident radiance divided by the probability density of the sample,
+    /// i.e. the irradiance on a surface facing the light for delta lights.
+    pub radiance: Color,
+    /// Solid angle density of `direction`, or None for lights that scattered
+    /// rays can never hit.
+    pub pdf: Option<f64>,
+}
+
+/// Lights that are not part of the world geometry and can only be reached
+/// by sampling them explicitly from a shading point.
+pub trait Light {
+    /// Direction towards the light from `p` for the uniform 2D sample `u`,
+    /// lighting a ray at `time`.
+    fn sample(&self, p: &Point3, u: (f64, f64), time: f64) -> Option<LightSample>;
+
+    /// Rough total emitted power, used to weigh lights against each other.
+    fn power(&self) -> f64;
+
+    /// Solid angle density with which `sample` picks unit `direction` from
+    /// `p` at `time`. Zero for lights that scattered rays can never hit.
+    fn pdf(&self, _p: &Point3, _direction: &Vec3, _time: f64) -> f64 {
+        0.0
+    }
+
+    /// Where the light is and where it shines, for lights that can be put
+    /// in the light tree. Lights infinitely far away have no bounds.
+    fn bounds(&self) -> Option<LightBounds> {
+        None
+    }
+
+    /// The light as a block of a scene file, or None for lights that are
+    /// written as part of an object.
+    fn export(&self, writer: &mut SceneWriter) -> Result<Option<Entry>, String>;
+}
+
+pub type WrappedLightType = Arc<dyn Light + Sync + Send>;
+pub type LightList = Vec<WrappedLightType>;
+
+/// Isotropic point light. `intensity` is the radiant intensity (W/sr) of
+/// each colour channel, which falls off with the inverse square distance.
+pub struct PointLight {
+    pub position: Point3,
+    pub intensity: Color,
+    /// Photometric distribution and the unit axis it is aimed along. The
+    /// intensity is then the one of the brightest direction.
+    pub profile: Option<(Arc<IesProfile>, Vec3)>,
+}
+
+impl PointLight {
+    pub fn new(position: Point3, color: Color, intensity: f64) -> Self {
+        Self {
+            position,
+            intensity: intensity * color,
+            profile: None,
+        }
+    }
+
+    pub fn with_profile(mut self, profile: Arc<IesProfile>, axis: Vec3) -> Self {
+        self.profile = Some((profile, axis.unit_direction()));
+        self
+    }
+}
+
+impl Light for PointLight {
+    fn sample(&self, p: &Point3, _u: (f64, f64), _time: f64) -> Option<LightSample> {
+        let to_light = self.position - *p;
+        let distance_squared = to_light.length_squared();
+        let distance = distance_squared.sqrt();
+        let direction = to_light / distance;
+
+        let scale = match &self.profile {
+            None => 1.0,
+            Some((profile, axis)) => {
+                let (tangent, bitangent) = HitRecord::default_frame(axis);
+                let out = -direction;
+
+                profile.relative(&Vec3::new(
+                    out.dot(&tangent),
+                    out.dot(&bitangent),
+                    out.dot(axis),
+                ))
+            }
+        };
+        if scale <= 0.0 {
+            return None;
+        }
+
+        Some(LightSample {
+            direction,
+            distance,
+            radiance: scale * self.intensity / distance_squared,
+            pdf: None,
+        })
+    }
+
+    fn power(&self) -> f64 {
+        4.0 * PI * luminance(&self.intensity)
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        Some(LightBounds::omnidirectional(
+            self.position,
+            self.position,
+            self.power(),
+        ))
+    }
+
+    fn export(&self, writer: &mut SceneWriter) -> Result<Option<Entry>, String> {
+        let entry = Entry::new("point")
+            .with("position", self.position)
+            .with("color", self.intensity);
+
+        match &self.profile {
+            None => Ok(Some(entry)),
+            Some((profile, axis)) => match &profile.path {
+                Some(path) => Ok(Some(
+                    entry.with("profile", writer.path(path)).with("axis", *axis),
+                )),
+                None => Err(String::from(
+                    "light profiles that were not loaded from a file cannot be exported",
+                )),
+            },
+        }
+    }
+}
+
+/// Point light restricted to a cone around `direction`. The intensity is
+/// full inside `inner_angle` and fades smoothly to zero at `outer_angle`,
+/// both measured from the axis in degrees.
+pub struct SpotLight {
+    pub position: Point3,
+    pub direction: Vec3,
+    pub intensity: Color,
+    pub inner_angle: f64,
+    pub outer_angle: f64,
+    pub cos_inner: f64,
+    pub cos_outer: f64,
+}
+
+impl SpotLight {
+    pub fn new(
+        position: Point3,
+        direction: Vec3,
+        color: Color,
+        intensity: f64,
+        inner_angle: f64,
+        outer_angle: f64,
+    ) -> Self {
+        Self {
+            position,
+            direction: direction.unit_direction(),
+            intensity: intensity * color,
+            inner_angle,
+            outer_angle,
+            cos_inner: degrees_to_radians(inner_angle.min(outer_angle)).cos(),
+            cos_outer: degrees_to_radians(outer_angle).cos(),
+        }
+    }
+
+    fn falloff(&self, cos_theta: f64) -> f64 {
+        if cos_theta >= self.cos_inner {
+            1.0
+        } else if cos_theta <= self.cos_outer {
+            0.0
+        } else {
+            let t = (cos_theta - self.cos_outer) / (self.cos_inner - self.cos_outer);
+            t * t * (3.0 - 2.0 * t)
+        }
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, p: &Point3, _u: (f64, f64), _time: f64) -> Option<LightSample> {
+        let to_light = self.position - *p;
+        let distance_squared = to_light.length_squared();
+        let distance = distance_squared.sqrt();
+        let direction = to_light / distance;
+
+        let falloff = self.falloff((-direction).dot(&self.direction));
+        if falloff <= 0.0 {
+            return None;
+        }
+
+        Some(LightSample {
+            direction,
+            distance,
+            radiance: falloff * self.intensity / distance_squared,
+            pdf: None,
+        })
+    }
+
+    fn power(&self) -> f64 {
+        // solid angle of the cone halfway between the inner and outer edges
+        let cos_mid = 0.5 * (self.cos_inner + self.cos_outer);
+        2.0 * PI * (1.0 - cos_mid) * luminance(&self.intensity)
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let theta_inner = self.cos_inner.clamp(-1.0, 1.0).acos();
+        let theta_outer = self.cos_outer.clamp(-1.0, 1.0).acos();
+
+        Some(LightBounds {
+            min: self.position,
+            max: self.position,
+            axis: self.direction,
+            cos_theta_o: self.cos_inner,
+            cos_theta_e: (theta_outer - theta_inner).cos(),
+            two_sided: false,
+            power: self.power(),
+        })
+    }
+
+    fn export(&self, _writer: &mut SceneWriter) -> Result<Option<Entry>, String> {
+        Ok(Some(
+            Entry::new("spot")
+                .with("position", self.position)
+                .with("direction", self.direction)
+                .with("color", self.intensity)
+                .with("inner_angle", self.inner_angle)
+                .with("outer_angle", self.outer_angle),
+        ))
+    }
+}
+
+/// Distant light such as the sun. `irradiance` (W/m^2) arrives on a surface
+/// facing the light from a disk of `angular_diameter` degrees around
+/// `direction`, which points towards the light. A non-zero diameter gives
+/// soft shadows.
+pub struct DirectionalLight {
+    pub direction: Vec3,
+    pub irradiance: Color,
+    pub angular_diameter: f64,
+    pub cos_half_angle: f64,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vec3, color: Color, irradiance: f64, angular_diameter: f64) -> Self {
+        Self {
+            direction: direction.unit_direction(),
+            irradiance: irradiance * color,
+            angular_diameter,
+            cos_half_angle: degrees_to_radians(angular_diameter / 2.0).cos(),
+        }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, _p: &Point3, u: (f64, f64), _time: f64) -> Option<LightSample> {
+        Some(LightSample {
+            direction: sample_cone(&self.direction, self.cos_half_angle, u),
+            distance: f64::INFINITY,
+            radiance: self.irradiance,
+            pdf: None,
+        })
+    }
+
+    fn power(&self) -> f64 {
+        // distant lights have no finite power; weigh them as a bright light
+        // covering a large scene
+        1e3 * luminance(&self.irradiance)
+    }
+
+    fn export(&self, _writer: &mut SceneWriter) -> Result<Option<Entry>, String> {
+        Ok(Some(
+            Entry::new("directional")
+                .with("direction", self.direction)
+                .with("color", self.irradiance)
+                .with("angular_diameter", self.angular_diameter),
+        ))
+    }
+}
+
+/// Light arriving from infinitely far away in every direction, stored as an
+/// equirectangular image: the center of the image looks down -z, the top row
+/// is straight up, and longitude increases towards +x. `rotation` turns the
+/// map about the y axis in degrees. Directions are sampled in proportion to
+/// the luminance of the map, so small bright features such as the sun are
+/// found by explicit light sampling rather than by chance.
+pub struct EnvironmentLight {
+    pub image: Image,
+    pub intensity: f64,
+    /// `rotation` in degrees.
+    pub rotation_angle: f64,
+    /// The sky the map was baked from, if any.
+    pub sky: Option<Sky>,
+    rotation: Quaternion,
+    distribution: Distribution2D,
+}
+
+impl EnvironmentLight {
+    pub fn new(image: Image, rotation: f64, intensity: f64) -> Self {
+        // rows near the poles cover less solid angle
+        let weights: Vec<f64> = image
+            .pixels
+            .iter()
+            .enumerate()
+            .map(|(index, pixel)| {
+                let row = index / image.width;
+                let theta = PI * (row as f64 + 0.5) / image.height as f64;
+
+                luminance(pixel) * theta.sin()
+            })
+            .collect();
+        let distribution = Distribution2D::new(&weights, image.width, image.height);
+
+        Self {
+            image,
+            intensity,
+            rotation_angle: rotation,
+            sky: None,
+            rotation: Quaternion::from_axis_angle(&Vec3::new(0.0, 1.0, 0.0), rotation),
+            distribution,
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P, rotation: f64, intensity: f64) -> io::Result<Self> {
+        Ok(Self::new(Image::load(path, false)?, rotation, intensity))
+    }
+
+    /// Tabulates a procedural environment at the center of every pixel of a
+    /// `width` by `height` map.
+    pub fn bake<F>(width: usize, height: usize, intensity: f64, radiance: F) -> Self
+    where
+        F: Fn(&Vec3) -> Color,
+    {
+        let mut pixels = Vec::with_capacity(width * height);
+
+        for row in 0..height {
+            for column in 0..width {
+                pixels.push(radiance(&equirectangular_direction(
+                    (column as f64 + 0.5) / width as f64,
+                    (row as f64 + 0.5) / height as f64,
+                )));
+            }
+        }
+
+        Self::new(Image::new(width, height, pixels), 0.0, intensity)
+    }
+
+    /// Image coordinates of a world direction, both in `[0, 1)`.
+    #[inline(always)]
+    fn image_coordinates(&self, direction: &Vec3) -> (f64, f64) {
+        let d = self.rotation.conjugate().rotate(&direction.unit());
+        let phi = d.x().atan2(-d.z());
+        let latitude = d.y().clamp(-1.0, 1.0).asin();
+
+        (
+            (0.5 + phi / (2.0 * PI)).rem_euclid(1.0),
+            (0.5 - latitude / PI).clamp(0.0, 1.0 - f64::EPSILON),
+        )
+    }
+
+    #[inline(always)]
+    fn direction_at(&self, x: f64, y: f64) -> Vec3 {
+        self.rotation.rotate(&equirectangular_direction(x, y))
+    }
+
+    /// Radiance arriving along the reverse of `direction`, i.e. what a ray
+    /// travelling along `direction` sees.
+    pub fn radiance(&self, direction: &Vec3) -> Color {
+        let (x, y) = self.image_coordinates(direction);
+        let column = ((x * self.image.width as f64) as usize).min(self.image.width - 1);
+        let row = ((y * self.image.height as f64) as usize).min(self.image.height - 1);
+
+        self.intensity * self.image.get(column, row)
+    }
+
+    fn direction_pdf(&self, direction: &Vec3) -> f64 {
+        let (x, y) = self.image_coordinates(direction);
+        let sin_theta = (PI * y).sin();
+
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+
+        self.distribution.pdf(x, y) / (2.0 * PI * PI * sin_theta)
+    }
+}
+
+impl Light for EnvironmentLight {
+    fn sample(&self, _p: &Point3, u: (f64, f64), _time: f64) -> Option<LightSample> {
+        let ((x, y), image_pdf) = self.distribution.sample(u.0, u.1);
+        let sin_theta = (PI * y).sin();
+
+        if image_pdf <= 0.0 || sin_theta <= 0.0 {
+            return None;
+        }
+
+        let direction = self.direction_at(x, y);
+        let pdf = image_pdf / (2.0 * PI * PI * sin_theta);
+
+        Some(LightSample {
+            direction,
+            distance: f64::INFINITY,
+            radiance: self.radiance(&direction) / pdf,
+            pdf: Some(pdf),
+        })
+    }
+
+    fn power(&self) -> f64 {
+        let average = self.image.pixels.iter().map(luminance).sum::<f64>()
+            / self.image.pixels.len().max(1) as f64;
+
+        // same scale as the directional light
+        1e3 * PI * self.intensity * average
+    }
+
+    fn pdf(&self, _p: &Point3, direction: &Vec3, _time: f64) -> f64 {
+        self.direction_pdf(direction)
+    }
+
+    fn export(&self, writer: &mut SceneWriter) -> Result<Option<Entry>, String> {
+        if let Some(sky) = &self.sky {
+            // the sun is a light of its own, written separately
+            return Ok(Some(
+                Entry::new("sky")
+                    .with("elevation", sky.elevation)
+                    .with("azimuth", sky.azimuth)
+                    .with("turbidity", sky.turbidity)
+                    .with("intensity", sky.intensity)
+                    .with("ground_albedo", sky.ground_albedo)
+                    .with("resolution", self.image.width)
+                    .with("sun", false),
+            ));
+        }
+
+        match &self.image.source {
+            Some(source) => Ok(Some(
+                Entry::new("environment")
+                    .with("path", writer.path(&source.path))
+                    .with("rotation", self.rotation_angle)
+                    .with("intensity", self.intensity),
+            )),
+            None => Err(String::from(
+                "environments that were not loaded from a file or made by the sky model cannot be exported",
+            )),
+        }
+    }
+}
+
+/// Emissive object of the world sampled as a light. The object has to
+/// support `Hittable::random` and `Hittable::pdf_value`, as every object
+/// the scene format has does.
+pub struct AreaLight {
+    pub object: WorldElementType,
+    power: f64,
+}
+
+impl AreaLight {
+    pub fn new(object: WorldElementType) -> Self {
+        let power = Self::estimate_power(&object);
+
+        Self { object, power }
+    }
+
+    /// Emitted power from the area and the average radiance seen from
+    /// along each axis on both sides, which halves it for one sided flat
+    /// lights. The directions are drawn from a fixed seed, so the estimate
+    /// is the same in every render, and the object is taken where it is at
+    /// time zero.
+    fn estimate_power(object: &WorldElementType) -> f64 {
+        let bounding_box = match object.bounding_box(0.0, 1.0) {
+            Some(bounding_box) => bounding_box,
+            None => return 0.0,
+        };
+        let center = 0.5 * (*bounding_box.min() + *bounding_box.max());
+        let distance = (*bounding_box.max() - *bounding_box.min()).length() + 1.0;
+
+        let mut rng = Rng::new(0);
+        let mut radiance = 0.0;
+        let mut hits = 0;
+        let axes = [
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        ];
+        for axis in axes {
+            for side in [-1.0, 1.0] {
+                let origin = center + side * distance * axis;
+                let u = (rng.random(), rng.random());
+                let ray = Ray::new(origin, object.random(&origin, u, 0.0));
+                if let Some(record) = object.hit(&ray, 0.001, f64::INFINITY) {
+                    radiance += luminance(&record.material.emit(&ray, &record));
+                    hits += 1;
+                }
+            }
+        }
+
+        if hits == 0 {
+            return 0.0;
+        }
+
+        let sides = if object.flat_normal().is_some() {
+            2.0
+        } else {
+            1.0
+        };
+        sides * PI * object.area() * radiance / hits as f64
+    }
+}
+
+impl Light for AreaLight {
+    fn sample(&self, p: &Point3, u: (f64, f64), time: f64) -> Option<LightSample> {
+        let direction = self.object.random(p, u, time).unit();
+        let pdf = self.object.pdf_value(p, &direction, time);
+        if pdf <= 0.0 {
+            return None;
+        }
+
+        let ray = Ray::with_timing(*p, direction, time);
+        let record = self.object.hit(&ray, 0.001, f64::INFINITY)?;
+
+        Some(LightSample {
+            direction,
+            distance: record.t,
+            radiance: record.material.emit(&ray, &record) / pdf,
+            pdf: Some(pdf),
+        })
+    }
+
+    fn power(&self) -> f64 {
+        self.power
+    }
+
+    fn pdf(&self, p: &Point3, direction: &Vec3, time: f64) -> f64 {
+        self.object.pdf_value(p, direction, time)
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let bounding_box = self.object.bounding_box(0.0, 1.0)?;
+        let mut bounds =
+            LightBounds::omnidirectional(*bounding_box.min(), *bounding_box.max(), self.power);
+
+        if let Some(normal) = self.object.flat_normal() {
+            bounds.axis = normal;
+            bounds.cos_theta_o = 1.0;
+            bounds.two_sided = true;
+        }
+
+        Some(bounds)
+    }
+
+    /// Area lights are written as objects marked `light = true`.
+    fn export(&self, _writer: &mut SceneWriter) -> Result<Option<Entry>, String> {
+        Ok(None)
+    }
+}
+
+/// Direction at image coordinates `(x, y)` of an unrotated environment map.
+#[inline(always)]
+pub fn equirectangular_direction(x: f64, y: f64) -> Vec3 {
+    let phi = (x - 0.5) * 2.0 * PI;
+    let latitude = (0.5 - y) * PI;
+
+    Vec3::new(
+        latitude.cos() * phi.sin(),
+        latitude.sin(),
+        -latitude.cos() * phi.cos(),
+    )
+}
+
+/// Weight of a sample with density `pdf` against another strategy that
+/// could have produced it with density `other`.
+#[inline(always)]
+pub fn power_heuristic(pdf: f64, other: f64) -> f64 {
+    let (a, b) = (pdf * pdf, other * other);
+
+    if a + b <= 0.0 {
+        0.0
+    } else {
+        a / (a + b)
+    }
+}
+
+/// Uniform direction inside the cone of directions around unit `axis` whose
+/// cosine to it is at least `cos_max`.
+pub fn sample_cone(axis: &Vec3, cos_max: f64, u: (f64, f64)) -> Vec3 {
+    if cos_max >= 1.0 {
+        return *axis;
+    }
+
+    let cos_theta = 1.0 - u.0 * (1.0 - cos_max);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.1;
+
+    let (tangent, bitangent) = HitRecord::default_frame(axis);
+
+    (sin_theta * phi.cos()) * tangent + (sin_theta * phi.sin()) * bitangent + cos_theta * *axis
+}
+
+/// Whether nothing in `world` blocks the segment from `p` along the light
+/// sample.
+pub fn visible(world: &WorldType, p: &Point3, sample: &LightSample, time: f64) -> bool {
+    const SHADOW_EPSILON: f64 = 0.001;
+
+    let ray = Ray::with_timing(*p, sample.direction, time);
+    let max = if sample.distance.is_finite() {
+        sample.distance * (1.0 - 1e-6) - SHADOW_EPSILON
+    } else {
+        f64::INFINITY
+    };
+
+    max <= SHADOW_EPSILON || world.hit(&ray, SHADOW_EPSILON, max).is_none()
+}
@@ -0,0 +1,382 @@
+use crate::animation::Quaternion;
+use crate::distribution::Distribution1D;
+use crate::light::LightList;
+use crate::math_traits::{CrossProduct, InnerProduct};
+use crate::sampler::remap;
+use crate::vec3::{Point3, Vec3};
+use std::f64::consts::PI;
+
+/// Where a light is and which way it shines: a box around the emitters, a
+/// cone of `cos_theta_o` around `axis` holding the surface normals (or
+/// emission axes), and the extra angle `cos_theta_e` that light leaves each
+/// of them at. `power` steers how much the light is sampled.
+#[derive(Debug, Copy, Clone)]
+pub struct LightBounds {
+    pub min: Point3,
+    pub max: Point3,
+    pub axis: Vec3,
+    pub cos_theta_o: f64,
+    pub cos_theta_e: f64,
+    pub two_sided: bool,
+    pub power: f64,
+}
+
+impl LightBounds {
+    /// Bounds of a light emitting in every direction from a box.
+    pub fn omnidirectional(min: Point3, max: Point3, power: f64) -> Self {
+        Self {
+            min,
+            max,
+            axis: Vec3::new(0.0, 0.0, 1.0),
+            cos_theta_o: -1.0,
+            cos_theta_e: 0.0,
+            two_sided: false,
+            power,
+        }
+    }
+
+    fn centroid(&self) -> Point3 {
+        0.5 * (self.min + self.max)
+    }
+
+    fn union(&self, other: &Self) -> Self {
+        if self.power <= 0.0 {
+            return *other;
+        }
+        if other.power <= 0.0 {
+            return *self;
+        }
+
+        let (axis, cos_theta_o) =
+            cone_union(&self.axis, self.cos_theta_o, &other.axis, other.cos_theta_o);
+
+        Self {
+            min: self.min.merge_min(&other.min),
+            max: self.max.merge_max(&other.max),
+            axis,
+            cos_theta_o,
+            cos_theta_e: self.cos_theta_e.min(other.cos_theta_e),
+            two_sided: self.two_sided || other.two_sided,
+            power: self.power + other.power,
+        }
+    }
+
+    /// Conservative estimate of the light arriving at `p` on a surface with
+    /// unit `normal`, following the light tree of Conty and Kulla as
+    /// refined in pbrt-v4. The bounds stand in for every emitter they hold,
+    /// each angle is widened by the angle the box subtends, and the
+    /// distance is kept above half the box diagonal.
+    pub fn importance(&self, p: &Point3, normal: &Vec3) -> f64 {
+        let center = self.centroid();
+        let half_diagonal = 0.5 * (self.max - self.min).length();
+        let distance_squared = (*p - center).length_squared().max(half_diagonal);
+
+        let to_point = (*p - center).unit();
+        let mut cos_theta_w = self.axis.dot(&to_point);
+        if self.two_sided {
+            cos_theta_w = cos_theta_w.abs();
+        }
+        let sin_theta_w = safe_sin(cos_theta_w);
+
+        // angle of the box as seen from p
+        let cos_theta_b = if self.contains(p) {
+            -1.0
+        } else {
+            let sin2 = half_diagonal * half_diagonal / (*p - center).length_squared();
+            (1.0 - sin2).max(0.0).sqrt()
+        };
+        let sin_theta_b = safe_sin(cos_theta_b);
+
+        // smallest angle between the emission cone and the direction to p
+        let sin_theta_o = safe_sin(self.cos_theta_o);
+        let cos_theta_x = cos_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+        let sin_theta_x = sin_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+        let cos_theta_p = cos_sub_clamped(sin_theta_x, cos_theta_x, sin_theta_b, cos_theta_b);
+        if cos_theta_p <= self.cos_theta_e {
+            return 0.0;
+        }
+
+        let mut importance = self.power * cos_theta_p / distance_squared;
+
+        if !normal.near_zero() {
+            let cos_theta_i = to_point.dot(normal).abs();
+            let sin_theta_i = safe_sin(cos_theta_i);
+            importance *= cos_sub_clamped(sin_theta_i, cos_theta_i, sin_theta_b, cos_theta_b);
+        }
+
+        importance.max(0.0)
+    }
+
+    fn contains(&self, p: &Point3) -> bool {
+        (0..3).all(|i| p[i] >= self.min[i] && p[i] <= self.max[i])
+    }
+}
+
+#[inline(always)]
+fn safe_sin(cos: f64) -> f64 {
+    (1.0 - cos * cos).max(0.0).sqrt()
+}
+
+/// cos(max(0, a - b)) from the sines and cosines of `a` and `b`.
+#[inline(always)]
+fn cos_sub_clamped(sin_a: f64, cos_a: f64, sin_b: f64, cos_b: f64) -> f64 {
+    if cos_a > cos_b {
+        1.0
+    } else {
+        cos_a * cos_b + sin_a * sin_b
+    }
+}
+
+/// sin(max(0, a - b)) from the sines and cosines of `a` and `b`.
+#[inline(always)]
+fn sin_sub_clamped(sin_a: f64, cos_a: f64, sin_b: f64, cos_b: f64) -> f64 {
+    if cos_a > cos_b {
+        0.0
+    } else {
+        sin_a * cos_b - cos_a * sin_b
+    }
+}
+
+/// Smallest cone holding the cones `(a, cos_a)` and `(b, cos_b)`.
+fn cone_union(a: &Vec3, cos_a: f64, b: &Vec3, cos_b: f64) -> (Vec3, f64) {
+    let everything = (Vec3::new(0.0, 0.0, 1.0), -1.0);
+
+    let theta_a = cos_a.clamp(-1.0, 1.0).acos();
+    let theta_b = cos_b.clamp(-1.0, 1.0).acos();
+    let theta_d = a.dot(b).clamp(-1.0, 1.0).acos();
+
+    if (theta_d + theta_b).min(PI) <= theta_a {
+        return (*a, cos_a);
+    }
+    if (theta_d + theta_a).min(PI) <= theta_b {
+        return (*b, cos_b);
+    }
+
+    let theta_o = (theta_a + theta_d + theta_b) / 2.0;
+    if theta_o >= PI {
+        return everything;
+    }
+
+    let rotation_axis = a.cross(b);
+    if rotation_axis.near_zero() {
+        return everything;
+    }
+
+    let axis =
+        Quaternion::from_axis_angle(&rotation_axis, (theta_o - theta_a).to_degrees()).rotate(a);
+    (axis, theta_o.cos())
+}
+
+enum LightNode {
+    Leaf {
+        light: usize,
+        bounds: LightBounds,
+    },
+    Interior {
+        bounds: LightBounds,
+        children: Box<[LightNode; 2]>,
+    },
+}
+
+impl LightNode {
+    fn bounds(&self) -> &LightBounds {
+        match self {
+            LightNode::Leaf { bounds, .. } => bounds,
+            LightNode::Interior { bounds, .. } => bounds,
+        }
+    }
+
+    fn build(
+        lights: &mut [(usize, LightBounds)],
+        trail: u64,
+        depth: u32,
+        trails: &mut [(u64, u32)],
+    ) -> Self {
+        if lights.len() == 1 {
+            let (light, bounds) = lights[0];
+            trails[light] = (trail, depth);
+
+            return LightNode::Leaf { light, bounds };
+        }
+
+        // split at the median along the longest extent of the centroids
+        let (mut low, mut high) = (lights[0].1.centroid(), lights[0].1.centroid());
+        for (_, bounds) in lights.iter() {
+            low = low.merge_min(&bounds.centroid());
+            high = high.merge_max(&bounds.centroid());
+        }
+        let extent = high - low;
+        let axis = (0..3)
+            .max_by(|a, b| extent[*a].total_cmp(&extent[*b]))
+            .unwrap_or(0);
+        lights.sort_by(|a, b| a.1.centroid()[axis].total_cmp(&b.1.centroid()[axis]));
+
+        let (left, right) = lights.split_at_mut(lights.len() / 2);
+        let left = Self::build(left, trail, depth + 1, trails);
+        let right = Self::build(right, trail | (1u64 << depth), depth + 1, trails);
+
+        LightNode::Interior {
+            bounds: left.bounds().union(right.bounds()),
+            children: Box::new([left, right]),
+        }
+    }
+}
+
+/// Probability of the second child, by importance at the shading point or
+/// by power where both importances vanish.
+fn right_probability(children: &[LightNode; 2], p: &Point3, normal: &Vec3) -> f64 {
+    let left = children[0].bounds().importance(p, normal);
+    let right = children[1].bounds().importance(p, normal);
+
+    if left + right > 0.0 {
+        return right / (left + right);
+    }
+
+    let (left, right) = (children[0].bounds().power, children[1].bounds().power);
+    if left + right > 0.0 {
+        right / (left + right)
+    } else {
+        0.5
+    }
+}
+
+/// Picks one light per shading point. Lights with bounds are organised in a
+/// light tree and chosen in proportion to their estimated contribution;
+/// lights without bounds, such as distant lights and environments, are
+/// chosen by power.
+pub struct LightSampler {
+    root: Option<LightNode>,
+    /// Bit trail and depth of the leaf of every bounded light.
+    trails: Vec<(u64, u32)>,
+    infinite: Vec<usize>,
+    infinite_distribution: Distribution1D,
+    /// Index of every light into `infinite`, if it is there.
+    infinite_index: Vec<Option<usize>>,
+}
+
+impl LightSampler {
+    pub fn new(lights: &LightList) -> Self {
+        let mut bounded = vec![];
+        let mut infinite = vec![];
+        let mut infinite_index = vec![None; lights.len()];
+
+        for (index, light) in lights.iter().enumerate() {
+            match light.bounds() {
+                Some(bounds) if bounds.power > 0.0 => bounded.push((index, bounds)),
+                Some(_) => {}
+                None => {
+                    infinite_index[index] = Some(infinite.len());
+                    infinite.push(index);
+                }
+            }
+        }
+
+        let mut trails = vec![(0, 0); lights.len()];
+        let root = if bounded.is_empty() {
+            None
+        } else {
+            Some(LightNode::build(&mut bounded, 0, 0, &mut trails))
+        };
+        let infinite_distribution =
+            Distribution1D::new(infinite.iter().map(|i| lights[*i].power()).collect());
+
+        Self {
+            root,
+            trails,
+            infinite,
+            infinite_distribution,
+            infinite_index,
+        }
+    }
+
+    /// The infinite lights together get the same share as the whole tree.
+    fn infinite_probability(&self) -> f64 {
+        match (self.infinite.is_empty(), &self.root) {
+            (true, _) => 0.0,
+            (false, None) => 1.0,
+            (false, Some(_)) => self.infinite.len() as f64 / (self.infinite.len() + 1) as f64,
+        }
+    }
+
+    /// Picks a light for shading `p` with unit `normal`, which may be zero
+    /// for points in a volume, with the uniform sample `u`. Returns its index
+    /// and probability.
+    pub fn sample(&self, p: &Point3, normal: &Vec3, u: f64) -> Option<(usize, f64)> {
+        let p_infinite = self.infinite_probability();
+
+        if u < p_infinite {
+            let u = remap(u, 0.0, p_infinite);
+            let (_, pmf, index) = self.infinite_distribution.sample(u);
+            let pmf = pmf / self.infinite.len() as f64;
+
+            return Some((self.infinite[index], p_infinite * pmf));
+        }
+
+        let mut node = self.root.as_ref()?;
+        let mut pmf = 1.0 - p_infinite;
+        let mut u = remap(u, p_infinite, 1.0);
+
+        loop {
+            match node {
+                LightNode::Leaf { light, .. } => {
+                    return if pmf > 0.0 { Some((*light, pmf)) } else { None };
+                }
+                LightNode::Interior { children, .. } => {
+                    let right = right_probability(children, p, normal);
+
+                    if u < right {
+                        u = remap(u, 0.0, right);
+                        pmf *= right;
+                        node = &children[1];
+                    } else {
+                        u = remap(u, right, 1.0);
+                        pmf *= 1.0 - right;
+                        node = &children[0];
+                    }
+                }
+            }
+        }
+    }
+
+    /// Probability that `sample` picks `light` at `p`.
+    pub fn pmf(&self, p: &Point3, normal: &Vec3, light: usize) -> f64 {
+        let p_infinite = self.infinite_probability();
+
+        if let Some(index) = self.infinite_index.get(light).copied().flatten() {
+            let pmf = self
+                .infinite_distribution
+                .pdf((index as f64 + 0.5) / self.infinite.len() as f64)
+                / self.infinite.len() as f64;
+            return p_infinite * pmf;
+        }
+
+        let mut node = match &self.root {
+            Some(root) => root,
+            None => return 0.0,
+        };
+        let (trail, depth) = self.trails[light];
+        let mut pmf = 1.0 - p_infinite;
+
+        for level in 0..depth {
+            match node {
+                LightNode::Leaf { .. } => break,
+                LightNode::Interior { children, .. } => {
+                    let right = right_probability(children, p, normal);
+
+                    if trail & (1u64 << level) != 0 {
+                        pmf *= right;
+                        node = &children[1];
+                    } else {
+                        pmf *= 1.0 - right;
+                        node = &children[0];
+                    }
+                }
+            }
+        }
+
+        match node {
+            LightNode::Leaf { light: found, .. } if *found == light => pmf,
+            _ => 0.0,
+        }
+    }
+}
@@ -1,83 +1,937 @@
+mod animation;
 mod bb;
 mod camera;
 mod color;
+mod distribution;
+mod film;
+mod ies;
+mod image;
+mod lens;
+mod light;
+mod light_tree;
 mod material;
 mod math_traits;
 mod noise;
 mod objects;
+mod pattern;
 mod ray;
+mod render;
+mod rng;
+mod sampler;
 mod scene;
+mod scene_file;
+mod scene_loader;
+mod scene_writer;
+mod sky;
 mod texture;
+mod texture_graph;
 mod utils;
 mod vec3;
 
+use math_traits::InnerProduct;
 use objects::Hittable;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::thread;
 
 const ASPECT_RATIO: f64 = 1.0;
 const IMAGE_WIDTH: u32 = 600;
-const IMAGE_HEIGHT: u32 = (IMAGE_WIDTH as f64 / ASPECT_RATIO) as u32;
 const SAMPLES_PER_PIXEL: u32 = 50;
 const MAX_ITER: u32 = 120;
+/// Default --eye-separation, as a fraction of the distance from the camera
+/// to the point it looks at.
+const EYE_SEPARATION_RATIO: f64 = 1.0 / 30.0;
+const DEFAULT_FPS: f64 = 24.0;
+const DEFAULT_SHUTTER: f64 = 0.5;
+/// Image width and sample counts of --convergence. Every sampler is compared
+/// against one reference rendered with many more samples.
+const CONVERGENCE_WIDTH: usize = 48;
+const CONVERGENCE_SAMPLES: [u32; 5] = [1, 4, 16, 64, 256];
+const REFERENCE_SAMPLES: u32 = 4096;
+
+const USAGE: &str = "usage: ray-tracing [--scene FILE | --builtin NAME]
+                   [--frames FIRST-LAST [--fps FPS] [--shutter FRACTION] [--output PREFIX]]
+                   [--seed SEED] [--sampler SAMPLER] [--filter FILTER] [--filter-radius RADIUS]
+                   [--adaptive THRESHOLD] [--heat-map FILE]
+                   [--stereo MODE [--eye-separation DISTANCE] [--stereo-convergence DEPTH]]
+                   [--export FILE | --check-export | --convergence | --undistort PLATE]
+
+Renders the scene described in FILE, or the built-in scene NAME (one of
+cornell, simple, random, lights, sky and many_lights; cornell by default).
+Without --frames, a single image is written to stdout as PPM. With it, each
+frame is written to PREFIX_NNNN.png (PREFIX defaults to \"out\").
+Renders with the same SEED and settings are identical; SEED overrides the one
+of the scene and lays out the random built-in scenes. It is at most 2^53 - 1,
+the largest whole number scene files hold exactly. SAMPLER (independent,
+stratified, halton or sobol) overrides the sampler of the scene, and FILTER
+(box, tent, gaussian, mitchell or lanczos) its pixel filter. RADIUS is in
+pixels and defaults to one that suits the filter.
+--adaptive spends the samples where the image is noisy, until the relative
+error of every pixel is below THRESHOLD (e.g. 0.05) or the samples run out.
+--heat-map writes the number of samples taken in each pixel to FILE as PNG.
+--stereo renders both eyes into one image. MODE side-by-side or top-bottom
+packs a perspective pair whose views meet at DEPTH in front of the camera,
+the focus distance by default, and ods an omni-directional stereo panorama
+with the left eye on top. DISTANCE between the eyes defaults to a thirtieth
+of the distance the camera looks at.
+
+--export writes the scene to FILE in the scene format instead of rendering it.
+--check-export exports the scene, loads it back and checks that it exports the
+same again.
+--convergence renders the scene small with every sampler at increasing sample
+counts and prints the RMSE of each against a high sample count reference.
+--undistort resamples PLATE, an image shot through the lens distortion of the
+perspective camera of the scene, into the undistorted image that renders
+without the distortion match, and writes it to stdout as PPM.";
 
 pub type WorldElementType = Arc<dyn Hittable + Sync + Send>;
 pub type WorldType = Vec<WorldElementType>;
 
-fn make_camera() -> camera::Camera {
-    let from = vec3::Point3::new(278.0, 278.0, -800.0);
-    let at = vec3::Point3::new(278.0, 278.0, 0.0);
-    let up = vec3::Vec3::new(0.0, 1.0, 0.0);
-    let fov = 40.0;
-    let aperture = 0.1;
-    let distance_to_focus = 10.0;
-
-    let camera = camera::Camera::with_timing(
-        from,
-        at,
-        up,
-        fov,
-        ASPECT_RATIO,
-        aperture,
-        distance_to_focus,
+/// Names accepted by --builtin; the first is rendered when no scene is
+/// given.
+const BUILTIN_SCENES: [&str; 6] = [
+    "cornell",
+    "simple",
+    "random",
+    "lights",
+    "sky",
+    "many_lights",
+];
+
+/// One of the scenes of the `scene` module, with a camera framing it. The
+/// random scenes are laid out from `seed`, which also seeds the render.
+fn builtin_scene(name: &str, seed: u64) -> Option<scene_loader::SceneDescription> {
+    let mut rng = rng::Rng::new(seed);
+    let sky_blue = vec3::Color::new(0.7, 0.8, 1.0);
+    let (scene, width, background) = match name {
+        "cornell" => (scene::cornell_scene(), IMAGE_WIDTH, vec3::Color::zero()),
+        "simple" => (scene::Scene::new(scene::simple_world()), 400, sky_blue),
+        "random" => (
+            scene::Scene::new(scene::random_world(&mut rng)),
+            600,
+            sky_blue,
+        ),
+        "lights" => (scene::lights_world(), 600, vec3::Color::zero()),
+        "sky" => (scene::sky_world(&mut rng), 600, vec3::Color::zero()),
+        "many_lights" => (scene::many_lights_scene(&mut rng), 600, vec3::Color::zero()),
+        _ => return None,
+    };
+    let (lookfrom, lookat, fov, aperture, focus_dist, aspect_ratio) = match name {
+        "cornell" => (
+            (278.0, 278.0, -800.0),
+            (278.0, 278.0, 0.0),
+            40.0,
+            0.1,
+            10.0,
+            ASPECT_RATIO,
+        ),
+        "simple" => (
+            (3.0, 2.0, 3.0),
+            (0.0, 0.3, -1.0),
+            40.0,
+            0.0,
+            4.5,
+            16.0 / 9.0,
+        ),
+        "lights" => ((0.0, 3.0, 9.0), (0.0, 1.0, 0.0), 40.0, 0.0, 9.2, 1.5),
+        "many_lights" => ((0.0, 6.0, 14.0), (0.0, 1.0, 0.0), 45.0, 0.0, 14.9, 1.5),
+        _ => ((13.0, 2.0, 3.0), (0.0, 0.0, 0.0), 20.0, 0.1, 10.0, 1.5),
+    };
+    let point = |(x, y, z)| vec3::Point3::new(x, y, z);
+
+    Some(scene_loader::SceneDescription {
+        scene,
+        settings: render::RenderSettings {
+            width: width as usize,
+            height: (width as f64 / aspect_ratio) as usize,
+            samples_per_pixel: SAMPLES_PER_PIXEL,
+            max_depth: MAX_ITER,
+            background,
+            seed,
+            sampler: sampler::SamplerType::Independent,
+            filter: film::Filter::default(),
+            adaptive: None,
+        },
+        camera: camera::CameraSettings::new(
+            point(lookfrom),
+            point(lookat),
+            vec3::Vec3::new(0.0, 1.0, 0.0),
+            camera::Projection::Perspective {
+                fov,
+                aperture,
+                focus_dist,
+            },
+        ),
+    })
+}
+
+/// Writes `description` to `path`, with file names relative to it.
+fn export(description: &scene_loader::SceneDescription, path: &Path) -> Result<(), String> {
+    let directory = path.parent().unwrap_or(Path::new(""));
+    let text = scene_writer::SceneWriter::new()
+        .with_directory(if directory.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            directory
+        })
+        .write(description)?;
+
+    fs::write(path, text).map_err(|error| format!("cannot write {}: {}", path.display(), error))
+}
+
+/// Exports `description`, loads the file back and exports that again, and
+/// reports the render settings if they changed on the way, or else the first
+/// line on which the two exports differ. Returns the loaded scene when they
+/// are the same.
+fn check_export(
+    description: &scene_loader::SceneDescription,
+) -> Result<scene_loader::SceneDescription, String> {
+    // numbered, so that checks running at the same time don't share a file
+    static CHECKS: AtomicUsize = AtomicUsize::new(0);
+    let path = std::env::temp_dir().join(format!(
+        "ray-tracing-{}-{}.scene",
+        std::process::id(),
+        CHECKS.fetch_add(1, Ordering::Relaxed)
+    ));
+    export(description, &path)?;
+    let written = fs::read_to_string(&path).map_err(|error| error.to_string())?;
+
+    let loaded = scene_loader::load(&path).map_err(|errors| {
+        let errors: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+        errors.join("\n")
+    });
+    let _ = fs::remove_file(&path);
+    let (loaded, _) = loaded?;
+
+    if loaded.settings != description.settings {
+        return Err(format!(
+            "the render settings differ after loading:\n  {:?}\n  {:?}",
+            description.settings, loaded.settings
+        ));
+    }
+
+    let rewritten = scene_writer::SceneWriter::new()
+        .with_directory(path.parent().unwrap_or(Path::new("")))
+        .write(&loaded)?;
+
+    let mismatch = written
+        .lines()
+        .zip(rewritten.lines())
+        .enumerate()
+        .find(|(_, (first, second))| first != second);
+    match mismatch {
+        Some((line, (first, second))) => Err(format!(
+            "line {} differs after loading:\n  {}\n  {}",
+            line + 1,
+            first,
+            second
+        )),
+        None if written.lines().count() != rewritten.lines().count() => Err(String::from(
+            "the export has a different number of lines after loading",
+        )),
+        None => Ok(loaded),
+    }
+}
+
+/// Prints how fast each sampler converges on `description`: the RMSE of
+/// renders at increasing sample counts against a reference. Better samplers
+/// reach a lower error with the same samples.
+fn convergence(description: &scene_loader::SceneDescription) {
+    let errors = convergence_errors(
+        description,
+        CONVERGENCE_WIDTH,
+        &CONVERGENCE_SAMPLES,
+        REFERENCE_SAMPLES,
+        1,
+    );
+
+    print!("{:>12}", "samples");
+    for (name, _) in sampler::SamplerType::NAMES {
+        print!("{:>12}", name);
+    }
+    println!();
+
+    for (samples, row) in CONVERGENCE_SAMPLES.iter().zip(errors) {
+        print!("{:>12}", samples);
+        for error in row {
+            print!("{:>12.6}", error);
+        }
+        println!();
+    }
+}
+
+/// Renders `description` `width` pixels wide with every sampler at each of
+/// `sample_counts`, and returns the RMSE of each render against a reference
+/// with `reference_samples`, averaged over `runs` renders with consecutive
+/// seeds. Rows follow `sample_counts`, columns follow `SamplerType::NAMES`.
+fn convergence_errors(
+    description: &scene_loader::SceneDescription,
+    width: usize,
+    sample_counts: &[u32],
+    reference_samples: u32,
+    runs: u64,
+) -> Vec<Vec<f64>> {
+    let aspect_ratio = description.aspect_ratio();
+    let camera = description.camera.build(aspect_ratio, 0.0, 1.0);
+    let settings = render::RenderSettings {
+        width,
+        height: ((width as f64 / aspect_ratio) as usize).max(2),
+        ..description.settings
+    };
+    let scene = &description.scene;
+    let scene = Arc::new(scene.with_world(bb::BvhNode::build(
+        &scene.world,
         0.0,
         1.0,
+        &mut rng::Rng::new(settings.seed),
+    )));
+
+    // a seed none of the runs use, so the reference is not correlated with
+    // the renders
+    let reference = render::render(
+        &camera,
+        &scene,
+        &render::RenderSettings {
+            samples_per_pixel: reference_samples,
+            seed: settings.seed.wrapping_add(runs),
+            sampler: sampler::SamplerType::Sobol,
+            adaptive: None,
+            ..settings
+        },
+    );
+
+    sample_counts
+        .iter()
+        .map(|&samples| {
+            sampler::SamplerType::NAMES
+                .iter()
+                .map(|&(_, sampler)| {
+                    let total: f64 = (0..runs)
+                        .map(|run| {
+                            render::render(
+                                &camera,
+                                &scene,
+                                &render::RenderSettings {
+                                    samples_per_pixel: samples,
+                                    seed: settings.seed.wrapping_add(run),
+                                    sampler,
+                                    ..settings
+                                },
+                            )
+                            .rmse(&reference)
+                        })
+                        .sum();
+
+                    total / runs as f64
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Resamples the plate at `path`, shot through the lens distortion of
+/// `camera_settings`, into the undistorted image that renders without the
+/// distortion match. The plate keeps its size.
+fn undistort(
+    camera_settings: &camera::CameraSettings,
+    path: &Path,
+) -> Result<image::Image, String> {
+    let distortion = camera_settings
+        .distortion
+        .ok_or_else(|| String::from("the camera of the scene has no distortion"))?;
+    let mut plate = image::Image::load(path, false)
+        .map_err(|error| format!("cannot load {}: {}", path.display(), error))?;
+    if plate.width == 0 || plate.height == 0 {
+        return Err(format!("{} is empty", path.display()));
+    }
+
+    // 8 bit plates hold gamma encoded values like the renders, so decode
+    // them with the gamma 2 that write_ppm encodes with
+    let extension = path.extension().and_then(|e| e.to_str());
+    if matches!(
+        extension.map(|e| e.to_ascii_lowercase()).as_deref(),
+        Some("ppm" | "png")
+    ) {
+        for pixel in plate.pixels.iter_mut() {
+            *pixel = *pixel * *pixel;
+        }
+    }
+
+    let lens = camera_settings
+        .thin_lens(plate.width as f64 / plate.height as f64, 0.0, 1.0)
+        .ok_or_else(|| String::from("--undistort needs a perspective camera"))?;
+    // the normalized image coordinates of the plate edges, as the camera
+    // distorts them
+    let half_width = lens.horizontal.length() / (2.0 * lens.focus_dist);
+    let half_height = lens.vertical.length() / (2.0 * lens.focus_dist);
+    let plate = texture::ImageTexture::new(
+        plate,
+        texture::WrapMode::Clamp,
+        texture::FilterMode::Bilinear,
     );
-    camera
+
+    Ok(distortion.undistort_image(&plate, half_width, half_height))
+}
+
+struct Options {
+    scene_file: Option<String>,
+    builtin: String,
+    export: Option<String>,
+    check_export: bool,
+    convergence: bool,
+    seed: Option<u64>,
+    sampler: Option<sampler::SamplerType>,
+    filter: Option<film::FilterType>,
+    filter_radius: Option<f64>,
+    adaptive: Option<f64>,
+    heat_map: Option<String>,
+    stereo: Option<render::StereoMode>,
+    eye_separation: Option<f64>,
+    stereo_convergence: Option<f64>,
+    undistort: Option<String>,
+    sequence: Option<render::SequenceSettings>,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut scene_file = None;
+    let mut builtin = String::from(BUILTIN_SCENES[0]);
+    let mut export = None;
+    let mut check_export = false;
+    let mut convergence = false;
+    let mut seed = None;
+    let mut sampler = None;
+    let mut filter = None;
+    let mut filter_radius = None;
+    let mut adaptive = None;
+    let mut heat_map = None;
+    let mut stereo = None;
+    let mut eye_separation = None;
+    let mut stereo_convergence = None;
+    let mut undistort = None;
+    let mut frames = None;
+    let mut sequence = render::SequenceSettings {
+        first_frame: 1,
+        last_frame: 1,
+        fps: DEFAULT_FPS,
+        shutter: DEFAULT_SHUTTER,
+        output: String::from("out"),
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("missing value for {}", arg))
+        };
+
+        match arg.as_str() {
+            "--frames" => {
+                let range = value()?;
+                let (first, last) = range.split_once('-').unwrap_or((range, range));
+                let parse = |frame: &str| {
+                    frame
+                        .parse::<u32>()
+                        .map_err(|_| format!("invalid frame range {}", range))
+                };
+
+                frames = Some((parse(first)?, parse(last)?));
+            }
+            "--fps" => {
+                sequence.fps = value()?
+                    .parse()
+                    .map_err(|_| String::from("invalid --fps"))?
+            }
+            "--shutter" => {
+                sequence.shutter = value()?
+                    .parse()
+                    .map_err(|_| String::from("invalid --shutter"))?
+            }
+            "--output" => sequence.output = value()?.clone(),
+            "--scene" => scene_file = Some(value()?.clone()),
+            "--builtin" => {
+                builtin = value()?.clone();
+                if !BUILTIN_SCENES.contains(&builtin.as_str()) {
+                    return Err(format!(
+                        "unknown built-in scene {}, expected one of {}",
+                        builtin,
+                        BUILTIN_SCENES.join(", ")
+                    ));
+                }
+            }
+            "--export" => export = Some(value()?.clone()),
+            "--check-export" => check_export = true,
+            "--convergence" => convergence = true,
+            "--undistort" => undistort = Some(value()?.clone()),
+            "--seed" => {
+                let value: u64 = value()?
+                    .parse()
+                    .map_err(|_| String::from("invalid --seed"))?;
+                if value > scene_loader::MAX_SEED {
+                    return Err(format!(
+                        "--seed should be at most {}, so that scene files keep it exactly",
+                        scene_loader::MAX_SEED
+                    ));
+                }
+                seed = Some(value);
+            }
+            "--sampler" => {
+                let name = value()?;
+                sampler = Some(sampler::SamplerType::from_name(name).ok_or_else(|| {
+                    let names: Vec<&str> = sampler::SamplerType::NAMES
+                        .iter()
+                        .map(|(name, _)| *name)
+                        .collect();
+                    format!(
+                        "unknown sampler {}, expected one of {}",
+                        name,
+                        names.join(", ")
+                    )
+                })?);
+            }
+            "--filter" => {
+                let name = value()?;
+                filter = Some(film::FilterType::from_name(name).ok_or_else(|| {
+                    let names: Vec<&str> = film::FilterType::NAMES
+                        .iter()
+                        .map(|(name, _)| *name)
+                        .collect();
+                    format!(
+                        "unknown filter {}, expected one of {}",
+                        name,
+                        names.join(", ")
+                    )
+                })?);
+            }
+            "--filter-radius" => {
+                filter_radius = Some(
+                    value()?
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|radius| *radius > 0.0)
+                        .ok_or_else(|| String::from("invalid --filter-radius"))?,
+                )
+            }
+            "--adaptive" => {
+                adaptive = Some(
+                    value()?
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|threshold| *threshold > 0.0)
+                        .ok_or_else(|| String::from("invalid --adaptive"))?,
+                )
+            }
+            "--heat-map" => heat_map = Some(value()?.clone()),
+            "--stereo" => {
+                let name = value()?;
+                stereo = Some(render::StereoMode::from_name(name).ok_or_else(|| {
+                    let names: Vec<&str> = render::StereoMode::NAMES
+                        .iter()
+                        .map(|(name, _)| *name)
+                        .collect();
+                    format!(
+                        "unknown stereo mode {}, expected one of {}",
+                        name,
+                        names.join(", ")
+                    )
+                })?);
+            }
+            "--eye-separation" => {
+                eye_separation = Some(
+                    value()?
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|distance| *distance > 0.0)
+                        .ok_or_else(|| String::from("invalid --eye-separation"))?,
+                )
+            }
+            "--stereo-convergence" => {
+                stereo_convergence = Some(
+                    value()?
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|depth| *depth > 0.0)
+                        .ok_or_else(|| String::from("invalid --stereo-convergence"))?,
+                )
+            }
+            _ => return Err(format!("unknown argument {}", arg)),
+        }
+    }
+
+    let sequence = match frames {
+        None => None,
+        Some((first, last)) if first <= last && sequence.fps > 0.0 => {
+            sequence.first_frame = first;
+            sequence.last_frame = last;
+            Some(sequence)
+        }
+        Some(_) => return Err(String::from("invalid frame range or fps")),
+    };
+    if heat_map.is_some() && (sequence.is_some() || stereo.is_some()) {
+        return Err(String::from("--heat-map only works for single images"));
+    }
+    if stereo.is_some() && sequence.is_some() {
+        return Err(String::from("--stereo only works for single images"));
+    }
+    if eye_separation.is_some() && stereo.is_none() {
+        return Err(String::from("--eye-separation needs --stereo"));
+    }
+    if stereo_convergence.is_some()
+        && matches!(stereo, None | Some(render::StereoMode::Omnidirectional))
+    {
+        return Err(String::from(
+            "--stereo-convergence needs --stereo side-by-side or top-bottom",
+        ));
+    }
+    if undistort.is_some() && (sequence.is_some() || stereo.is_some() || heat_map.is_some()) {
+        return Err(String::from(
+            "--undistort does not render, so it takes no --frames, --stereo or --heat-map",
+        ));
+    }
+
+    Ok(Options {
+        scene_file,
+        builtin,
+        export,
+        check_export,
+        convergence,
+        seed,
+        sampler,
+        filter,
+        filter_radius,
+        adaptive,
+        heat_map,
+        stereo,
+        eye_separation,
+        stereo_convergence,
+        undistort,
+        sequence,
+    })
 }
 
 fn main() {
-    println!("P3\n{} {}\n255", IMAGE_WIDTH, IMAGE_HEIGHT);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            std::process::exit(1);
+        }
+    };
+
+    let mut description = match &options.scene_file {
+        None => builtin_scene(&options.builtin, options.seed.unwrap_or(0)).unwrap(),
+        Some(path) => match scene_loader::load(path) {
+            Ok((description, warnings)) => {
+                for warning in warnings {
+                    eprintln!("{}", warning);
+                }
+                description
+            }
+            Err(errors) => {
+                for error in errors {
+                    eprintln!("{}", error);
+                }
+                std::process::exit(1);
+            }
+        },
+    };
+    if let Some(seed) = options.seed {
+        description.settings.seed = seed;
+    }
+    if let Some(sampler) = options.sampler {
+        description.settings.sampler = sampler;
+    }
+    if let Some(filter) = options.filter {
+        description.settings.filter = film::Filter::new(filter);
+    }
+    if let Some(radius) = options.filter_radius {
+        description.settings.filter.radius = radius;
+    }
+    if let Some(threshold) = options.adaptive {
+        description.settings.adaptive = Some(match description.settings.adaptive {
+            Some(adaptive) => render::AdaptiveSettings {
+                threshold,
+                ..adaptive
+            },
+            None => {
+                render::AdaptiveSettings::new(threshold, description.settings.samples_per_pixel)
+            }
+        });
+    }
+
+    if let Some(path) = &options.export {
+        if let Err(error) = export(&description, Path::new(path)) {
+            eprintln!("failed to export the scene: {}", error);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if options.check_export {
+        if let Err(error) = check_export(&description) {
+            eprintln!("export check failed: {}", error);
+            std::process::exit(1);
+        }
+        eprintln!("the scene exports the same after loading");
+        return;
+    }
 
-    let camera = Arc::new(make_camera());
-    let world = Arc::new(scene::cornell_world());
+    if options.convergence {
+        convergence(&description);
+        return;
+    }
+    if let Some(path) = &options.undistort {
+        match undistort(&description.camera, Path::new(path)) {
+            Ok(image) => image.write_ppm(&mut std::io::stdout()),
+            Err(error) => {
+                eprintln!("failed to undistort the plate: {}", error);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
 
-    for j in (0..IMAGE_HEIGHT).rev() {
-        eprintln!("Output remaining {}", j + 1);
-        let mut handlers = vec![];
+    let aspect_ratio = description.aspect_ratio();
+    let (scene, settings, camera_settings) =
+        (description.scene, description.settings, description.camera);
+
+    if let Some(sequence) = options.sequence {
+        if let Err(error) = render::render_sequence(&camera_settings, &scene, &settings, &sequence)
+        {
+            eprintln!("failed to write frame: {}", error);
+            std::process::exit(1);
+        }
+        return;
+    }
 
-        for i in 0..IMAGE_WIDTH {
-            let world = world.clone();
-            let camera = camera.clone();
+    let scene = Arc::new(scene.with_world(bb::BvhNode::build(
+        &scene.world,
+        0.0,
+        1.0,
+        &mut rng::Rng::new(settings.seed),
+    )));
 
-            handlers.push(thread::spawn(move || {
-                let mut color = vec3::Color::zero();
+    let image = match options.stereo {
+        None => {
+            let mut film = film::Film::new(settings.width, settings.height, settings.filter);
+            render::render_film(
+                &camera_settings.build(aspect_ratio, 0.0, 1.0),
+                &scene,
+                &settings,
+                &mut film,
+            );
 
-                for _ in 0..SAMPLES_PER_PIXEL {
-                    let u = (i as f64 + utils::random()) / (IMAGE_WIDTH - 1) as f64;
-                    let v = (j as f64 + utils::random()) / (IMAGE_HEIGHT - 1) as f64;
-                    let ray = camera.get_ray(u, v);
+            if let Some(path) = &options.heat_map {
+                if let Err(error) = film.heat_map().write_png(path) {
+                    eprintln!("failed to write the heat map: {}", error);
+                    std::process::exit(1);
+                }
+            }
+            film.image()
+        }
+        Some(mode) => {
+            let eye_separation = options.eye_separation.unwrap_or(
+                EYE_SEPARATION_RATIO * (camera_settings.lookat - camera_settings.lookfrom).length(),
+            );
+            let camera = match mode {
+                render::StereoMode::Omnidirectional => {
+                    let mut panorama = camera::EquirectangularCamera::new(
+                        camera_settings.lookfrom,
+                        camera_settings.lookat,
+                        camera_settings.vup,
+                    );
+                    panorama.end_time = 1.0;
 
-                    color += ray::ray_color(&ray, &world, MAX_ITER, &vec3::Color::zero());
+                    camera::StereoCamera::omnidirectional(&panorama, eye_separation)
                 }
+                _ => match camera_settings.thin_lens(aspect_ratio, 0.0, 1.0) {
+                    Some(lens) => camera::StereoCamera::perspective(
+                        &lens,
+                        eye_separation,
+                        options.stereo_convergence.unwrap_or(lens.focus_dist),
+                    ),
+                    None => {
+                        eprintln!(
+                            "stereo pairs need a perspective camera; --stereo ods works with any"
+                        );
+                        std::process::exit(1);
+                    }
+                },
+            };
+            let camera = camera::StereoCamera {
+                left: camera_settings.animate(camera.left),
+                right: camera_settings.animate(camera.right),
+            };
+
+            render::render_stereo(&camera, &scene, &settings, mode.layout())
+        }
+    };
+
+    image.write_ppm(&mut std::io::stdout());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Size and samples of the renders compared after a round trip.
+    const TEST_WIDTH: usize = 16;
+    const TEST_SAMPLES: u32 = 4;
+    const TEST_DEPTH: u32 = 8;
+
+    fn small_render(description: &scene_loader::SceneDescription) -> image::Image {
+        let aspect_ratio = description.aspect_ratio();
+        let settings = render::RenderSettings {
+            width: TEST_WIDTH,
+            height: ((TEST_WIDTH as f64 / aspect_ratio) as usize).max(2),
+            samples_per_pixel: TEST_SAMPLES,
+            max_depth: TEST_DEPTH,
+            seed: 1,
+            adaptive: None,
+            ..description.settings
+        };
+        let scene = &description.scene;
+        let scene = Arc::new(scene.with_world(bb::BvhNode::build(
+            &scene.world,
+            0.0,
+            1.0,
+            &mut rng::Rng::new(settings.seed),
+        )));
+
+        render::render(
+            &description.camera.build(aspect_ratio, 0.0, 1.0),
+            &scene,
+            &settings,
+        )
+    }
+
+    /// Saves and loads `description`, and checks that the loaded scene
+    /// exports and renders the same.
+    fn assert_round_trip(name: &str, description: &scene_loader::SceneDescription) {
+        let loaded = check_export(description)
+            .unwrap_or_else(|error| panic!("{} does not round trip: {}", name, error));
+
+        let (original, loaded) = (small_render(description), small_render(&loaded));
+        assert_eq!(
+            original.pixels, loaded.pixels,
+            "{} renders differently after loading",
+            name
+        );
+    }
+
+    #[test]
+    fn builtin_scenes_round_trip() {
+        for name in BUILTIN_SCENES {
+            assert_round_trip(name, &builtin_scene(name, 0).unwrap());
+        }
+    }
+
+    #[test]
+    fn seeds_scene_files_cannot_hold_are_not_exported() {
+        let mut description = builtin_scene("simple", 0).unwrap();
+
+        description.settings.seed = scene_loader::MAX_SEED;
+        assert_round_trip("the largest seed", &description);
 
-                color
-            }));
+        description.settings.seed = scene_loader::MAX_SEED + 2;
+        assert!(check_export(&description).is_err());
+    }
+
+    #[test]
+    fn undistorting_a_plate_inverts_the_camera_distortion() {
+        let mut camera = builtin_scene("simple", 0).unwrap().camera;
+        camera.distortion = Some(lens::BrownConrady::new(-0.3, 0.1, 0.0, 0.01, -0.02));
+        let (width, height) = (64, 36);
+        let lens = camera
+            .thin_lens(width as f64 / height as f64, 0.0, 1.0)
+            .unwrap();
+        let half_width = lens.horizontal.length() / (2.0 * lens.focus_dist);
+        let half_height = lens.vertical.length() / (2.0 * lens.focus_dist);
+
+        // a smooth pattern over the undistorted image plane, and the plate the
+        // camera renders of it
+        let pattern = |x: f64, y: f64| {
+            vec3::Color::new(0.5 + 0.4 * x / half_width, 0.5 + 0.4 * y / half_height, 0.5)
+        };
+        let pixel_point = |column: usize, row: usize| {
+            (
+                (2.0 * (column as f64 + 0.5) / width as f64 - 1.0) * half_width,
+                (1.0 - 2.0 * (row as f64 + 0.5) / height as f64) * half_height,
+            )
+        };
+        let mut pixels = Vec::new();
+        for row in 0..height {
+            for column in 0..width {
+                let (x, y) = pixel_point(column, row);
+                let (x, y) = camera.distortion.unwrap().undistort(x, y);
+                pixels.push(pattern(x, y));
+            }
+        }
+        let path =
+            std::env::temp_dir().join(format!("ray-tracing-{}-plate.ppm", std::process::id()));
+        image::Image::new(width, height, pixels).write_ppm(&mut fs::File::create(&path).unwrap());
+
+        let image = undistort(&camera, &path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        // away from the edges, which the distortion pulls in from outside the
+        // plate
+        for row in 4..height - 4 {
+            for column in 4..width - 4 {
+                let (x, y) = pixel_point(column, row);
+                let error = (image.get(column, row) - pattern(x, y)).length();
+                assert!(error < 0.02, "pixel {} {} is off by {}", column, row, error);
+            }
+        }
+    }
+
+    #[test]
+    fn scene_files_round_trip() {
+        let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes");
+
+        // cornell_box.scene only holds the room that cornell.scene includes
+        for name in ["cornell.scene", "random.scene", "simple.scene"] {
+            let (description, _) = scene_loader::load(directory.join(name))
+                .unwrap_or_else(|_| panic!("{} does not load", name));
+            assert_round_trip(name, &description);
         }
+    }
+
+    /// Takes a few minutes in release builds:
+    /// `cargo test --release -- --ignored`.
+    #[test]
+    #[ignore = "slow; run with --release"]
+    fn low_discrepancy_samplers_converge_at_least_as_fast() {
+        let mut description = builtin_scene("cornell", 0).unwrap();
+        // the samplers differ in the first few bounces
+        description.settings.max_depth = 4;
+
+        // most of the error of a single 32 pixel render is on the few pixels
+        // along the edge of the light, which makes any one render a matter
+        // of luck: over ten seeds, the summed errors of the stratified
+        // sampler ranged from 17% below to 39% above their mean. The mean
+        // over the seeds ranks the samplers reliably.
+        let errors = convergence_errors(&description, 32, &[16, 64, 256], 4096, 10);
+
+        // summed over the sample counts
+        let total = |name: &str| -> f64 {
+            let column = sampler::SamplerType::NAMES
+                .iter()
+                .position(|(option, _)| *option == name)
+                .unwrap();
+            errors.iter().map(|row| row[column]).sum()
+        };
 
-        for handler in handlers {
-            let color = handler.join().unwrap();
-            color::write_color(&mut std::io::stdout(), &color, SAMPLES_PER_PIXEL);
+        for name in ["halton", "sobol"] {
+            for other in ["independent", "stratified"] {
+                assert!(
+                    total(name) <= total(other),
+                    "{} converges slower than {} samples: {:?}",
+                    name,
+                    other,
+                    errors
+                );
+            }
         }
     }
 }
@@ -1,16 +1,73 @@
-use crate::math_traits::InnerProduct;
-use crate::objects::HitRecord;
+use crate::color::{blackbody, luminance};
+use crate::ies::IesProfile;
+use crate::math_traits::{CrossProduct, InnerProduct};
+use crate::objects::{HitRecord, MaterialArc};
 use crate::ray::Ray;
+use crate::sampler::{remap, uniform_ball, uniform_sphere};
+use crate::scene_writer::{Entry, SceneWriter};
 use crate::texture::{SolidTexture, WrappedTextureType};
-use crate::utils::random;
 use crate::vec3::{Color, Point3, Vec3};
+use std::f64::consts::PI;
 use std::sync::Arc;
 
+/// Outcome of scattering a ray at a hit.
+pub struct ScatterRecord {
+    pub attenuation: Color,
+    pub ray: Ray,
+    /// Set when the direction was not drawn from the scattering covered by
+    /// `Material::eval` and `Material::pdf`, such as mirror reflection.
+    pub specular: bool,
+}
+
+impl ScatterRecord {
+    pub fn diffuse(attenuation: Color, ray: Ray) -> Self {
+        Self {
+            attenuation,
+            ray,
+            specular: false,
+        }
+    }
+
+    pub fn specular(attenuation: Color, ray: Ray) -> Self {
+        Self {
+            attenuation,
+            ray,
+            specular: true,
+        }
+    }
+}
+
 pub trait Material {
-    fn scatter(&self, ray: &Ray, record: &HitRecord) -> Option<(Color, Ray)>;
-    fn emit(&self, _u: f64, _v: f64, _p: &Point3) -> Color {
+    /// Scatters `ray` at the hit, choosing between lobes with the uniform
+    /// sample `uc` and the direction within a lobe with `u`.
+    fn scatter(
+        &self,
+        ray: &Ray,
+        record: &HitRecord,
+        uc: f64,
+        u: (f64, f64),
+    ) -> Option<ScatterRecord>;
+    /// Radiance emitted at the hit back along `ray`.
+    fn emit(&self, _ray: &Ray, _record: &HitRecord) -> Color {
         Color::zero()
     }
+
+    /// BSDF times the cosine term for light arriving from unit `direction`,
+    /// used to shade with explicitly sampled lights. Only non-specular
+    /// scattering can be evaluated, so specular materials return zero.
+    fn eval(&self, _ray: &Ray, _record: &HitRecord, _direction: &Vec3) -> Color {
+        Color::zero()
+    }
+
+    /// Solid angle density with which `scatter` picks unit `direction` when
+    /// the sample is not specular.
+    fn pdf(&self, _ray: &Ray, _record: &HitRecord, _direction: &Vec3) -> f64 {
+        0.0
+    }
+
+    /// The material as a block of a scene file, writing the textures and
+    /// materials it depends on first.
+    fn export(&self, writer: &mut SceneWriter) -> Result<Entry, String>;
 }
 
 pub struct Lambertian {
@@ -30,20 +87,43 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, ray: &Ray, record: &HitRecord) -> Option<(Color, Ray)> {
-        let mut scatter_dir = record.normal + Vec3::random_unit_vector();
+    fn scatter(
+        &self,
+        ray: &Ray,
+        record: &HitRecord,
+        _uc: f64,
+        u: (f64, f64),
+    ) -> Option<ScatterRecord> {
+        let mut scatter_dir = record.normal + uniform_sphere(u);
 
         if scatter_dir.near_zero() {
             scatter_dir = record.normal;
         }
 
         let scatter_ray = Ray::with_timing(record.hit_point, scatter_dir, ray.time);
-        Some((
+        Some(ScatterRecord::diffuse(
             self.albedo
                 .color_value(record.u, record.v, &record.hit_point),
             scatter_ray,
         ))
     }
+
+    fn eval(&self, _ray: &Ray, record: &HitRecord, direction: &Vec3) -> Color {
+        let cosine = record.normal.dot(direction).max(0.0);
+
+        cosine / PI
+            * self
+                .albedo
+                .color_value(record.u, record.v, &record.hit_point)
+    }
+
+    fn pdf(&self, _ray: &Ray, record: &HitRecord, direction: &Vec3) -> f64 {
+        record.normal.dot(direction).max(0.0) / PI
+    }
+
+    fn export(&self, writer: &mut SceneWriter) -> Result<Entry, String> {
+        Ok(Entry::new("lambertian").with("albedo", writer.texture(&self.albedo)?))
+    }
 }
 
 pub struct Metal {
@@ -58,20 +138,32 @@ impl Metal {
 }
 
 impl Material for Metal {
-    fn scatter(&self, ray: &Ray, record: &HitRecord) -> Option<(Color, Ray)> {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        record: &HitRecord,
+        uc: f64,
+        u: (f64, f64),
+    ) -> Option<ScatterRecord> {
         let reflected = ray.direction.unit().reflected(&record.normal);
         let scatter_ray = Ray::with_timing(
             record.hit_point,
-            reflected + self.fuzz * Vec3::random_in_unit_sphere(),
+            reflected + self.fuzz * uniform_ball(u, uc),
             ray.time,
         );
 
         if scatter_ray.direction.dot(&record.normal) > 0.0 {
-            Some((self.albedo, scatter_ray))
+            Some(ScatterRecord::specular(self.albedo, scatter_ray))
         } else {
             None
         }
     }
+
+    fn export(&self, _writer: &mut SceneWriter) -> Result<Entry, String> {
+        Ok(Entry::new("metal")
+            .with("albedo", self.albedo)
+            .with("fuzz", self.fuzz))
+    }
 }
 
 pub struct Dielectric {
@@ -91,7 +183,13 @@ impl Dielectric {
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, ray: &Ray, record: &HitRecord) -> Option<(Color, Ray)> {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        record: &HitRecord,
+        uc: f64,
+        _u: (f64, f64),
+    ) -> Option<ScatterRecord> {
         const attenuation: Color = Color::new(1.0, 1.0, 1.0);
 
         let refraction_ratio = if record.front_face {
@@ -106,42 +204,388 @@ impl Material for Dielectric {
 
         let cannot_refract = refraction_ratio * sin_theta > 1.0;
 
-        let refracted =
-            if cannot_refract || Self::reflectance(cos_theta, refraction_ratio) > random() {
-                ray_unit_dir.reflected(&record.normal)
-            } else {
-                ray_unit_dir.refracted(&record.normal, refraction_ratio)
-            };
+        let refracted = if cannot_refract || Self::reflectance(cos_theta, refraction_ratio) > uc {
+            ray_unit_dir.reflected(&record.normal)
+        } else {
+            ray_unit_dir.refracted(&record.normal, refraction_ratio)
+        };
 
-        return Some((
+        return Some(ScatterRecord::specular(
             attenuation,
             Ray::with_timing(record.hit_point, refracted, ray.time),
         ));
     }
+
+    fn export(&self, _writer: &mut SceneWriter) -> Result<Entry, String> {
+        Ok(Entry::new("dielectric").with("ir", self.ir))
+    }
 }
 
+/// Emitter. By default it emits `texture` uniformly from both sides of the
+/// surface; a one sided light only emits from the front, the side of the
+/// outward normal.
 pub struct DiffuseLight {
     pub texture: WrappedTextureType,
+    pub two_sided: bool,
+    /// Angular distribution around the normal of the emitting side, relative
+    /// to its brightest direction.
+    pub profile: Option<Arc<IesProfile>>,
 }
 
 impl DiffuseLight {
     pub fn new(texture: WrappedTextureType) -> Self {
-        Self { texture }
+        Self {
+            texture,
+            two_sided: true,
+            profile: None,
+        }
     }
 
     pub fn with_color(color: Color) -> Self {
-        Self {
-            texture: Arc::new(SolidTexture::new(color)),
-        }
+        Self::new(Arc::new(SolidTexture::new(color)))
+    }
+
+    /// Black body colour of `kelvin` with luminance `intensity`.
+    pub fn with_temperature(kelvin: f64, intensity: f64) -> Self {
+        Self::with_color(intensity * blackbody(kelvin))
+    }
+
+    pub fn with_two_sided(mut self, two_sided: bool) -> Self {
+        self.two_sided = two_sided;
+        self
+    }
+
+    pub fn with_profile(mut self, profile: Arc<IesProfile>) -> Self {
+        self.profile = Some(profile);
+        self
     }
 }
 
 impl Material for DiffuseLight {
-    fn scatter(&self, ray: &Ray, record: &HitRecord) -> Option<(Color, Ray)> {
+    fn scatter(
+        &self,
+        _ray: &Ray,
+        _record: &HitRecord,
+        _uc: f64,
+        _u: (f64, f64),
+    ) -> Option<ScatterRecord> {
         None
     }
 
-    fn emit(&self, u: f64, v: f64, p: &Point3) -> Color {
-        self.texture.color_value(u, v, p)
+    fn emit(&self, ray: &Ray, record: &HitRecord) -> Color {
+        if !self.two_sided && !record.front_face {
+            return Color::zero();
+        }
+
+        let color = self
+            .texture
+            .color_value(record.u, record.v, &record.hit_point);
+
+        match &self.profile {
+            None => color,
+            Some(profile) => {
+                // the hit normal faces the ray, so it is the side emitting
+                // towards it
+                let out = -ray.direction.unit();
+                let local = Vec3::new(
+                    out.dot(&record.tangent),
+                    out.dot(&record.bitangent),
+                    out.dot(&record.normal),
+                );
+
+                profile.relative(&local) * color
+            }
+        }
+    }
+
+    fn export(&self, writer: &mut SceneWriter) -> Result<Entry, String> {
+        let entry = Entry::new("diffuse_light")
+            .with("emit", writer.texture(&self.texture)?)
+            .with_non_default("two_sided", self.two_sided, true);
+
+        match &self.profile {
+            None => Ok(entry),
+            Some(profile) => match &profile.path {
+                Some(path) => Ok(entry.with("profile", writer.path(path))),
+                None => Err(String::from(
+                    "light profiles that were not loaded from a file cannot be exported",
+                )),
+            },
+        }
+    }
+}
+
+pub enum NormalSource {
+    /// Tangent space normal map, with components stored as `0.5 * n + 0.5`.
+    /// Load it without sRGB conversion.
+    NormalMap(WrappedTextureType),
+    /// Scalar height field (the luminance of the texture), differentiated
+    /// with forward differences of `delta` in uv and along the tangent frame.
+    Height {
+        texture: WrappedTextureType,
+        delta: f64,
+    },
+}
+
+/// Perturbs the shading normal of the hit before handing it to `base`.
+/// `strength` scales the tangential part of the normal map or the slope of
+/// the height field.
+pub struct NormalMapped {
+    pub base: MaterialArc,
+    pub source: NormalSource,
+    pub strength: f64,
+}
+
+impl NormalMapped {
+    /// Step of the height field differences when none is given.
+    pub const HEIGHT_DELTA: f64 = 1e-3;
+
+    pub fn new(base: MaterialArc, source: NormalSource, strength: f64) -> Self {
+        NormalMapped {
+            base,
+            source,
+            strength,
+        }
+    }
+
+    pub fn with_normal_map(base: MaterialArc, map: WrappedTextureType, strength: f64) -> Self {
+        Self::new(base, NormalSource::NormalMap(map), strength)
+    }
+
+    pub fn perturb(&self, record: &HitRecord) -> HitRecord {
+        let (u, v, p) = (record.u, record.v, record.hit_point);
+
+        let normal = match &self.source {
+            NormalSource::NormalMap(map) => {
+                let texel = 2.0 * map.color_value(u, v, &p) - Color::new(1.0, 1.0, 1.0);
+
+                self.strength * texel.x() * record.tangent
+                    + self.strength * texel.y() * record.bitangent
+                    + texel.z() * record.normal
+            }
+            NormalSource::Height { texture, delta } => {
+                let height = |u: f64, v: f64, p: &Point3| luminance(&texture.color_value(u, v, p));
+
+                let h = height(u, v, &p);
+                let dh_du = (height(u + delta, v, &(p + *delta * record.tangent)) - h) / delta;
+                let dh_dv = (height(u, v + delta, &(p + *delta * record.bitangent)) - h) / delta;
+
+                record.normal - self.strength * (dh_du * record.tangent + dh_dv * record.bitangent)
+            }
+        };
+
+        let mut perturbed = record.clone();
+
+        // keep the shading normal on the side the ray arrived from
+        if normal.near_zero() || normal.dot(&record.normal) <= 0.0 {
+            return perturbed;
+        }
+
+        perturbed.normal = normal.unit();
+        perturbed.tangent =
+            (record.tangent - record.tangent.dot(&perturbed.normal) * perturbed.normal).unit();
+        perturbed.bitangent = perturbed.normal.cross(&perturbed.tangent);
+
+        // keep the handedness of the original frame
+        if perturbed.bitangent.dot(&record.bitangent) < 0.0 {
+            perturbed.bitangent = -perturbed.bitangent;
+        }
+
+        perturbed
+    }
+}
+
+impl Material for NormalMapped {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        record: &HitRecord,
+        uc: f64,
+        u: (f64, f64),
+    ) -> Option<ScatterRecord> {
+        self.base.scatter(ray, &self.perturb(record), uc, u)
+    }
+
+    fn emit(&self, ray: &Ray, record: &HitRecord) -> Color {
+        self.base.emit(ray, record)
+    }
+
+    fn eval(&self, ray: &Ray, record: &HitRecord, direction: &Vec3) -> Color {
+        self.base.eval(ray, &self.perturb(record), direction)
+    }
+
+    fn pdf(&self, ray: &Ray, record: &HitRecord, direction: &Vec3) -> f64 {
+        self.base.pdf(ray, &self.perturb(record), direction)
+    }
+
+    fn export(&self, writer: &mut SceneWriter) -> Result<Entry, String> {
+        let entry = Entry::new("normal_mapped")
+            .with("base", writer.material(&self.base)?)
+            .with("strength", self.strength);
+
+        Ok(match &self.source {
+            NormalSource::NormalMap(map) => entry.with("normal_map", writer.texture(map)?),
+            NormalSource::Height { texture, delta } => entry
+                .with("height", writer.texture(texture)?)
+                .with_non_default("delta", *delta, NormalMapped::HEIGHT_DELTA),
+        })
+    }
+}
+
+/// Picks `second` with probability equal to the luminance of `weight` at the
+/// hit, and `first` otherwise.
+pub struct MixMaterial {
+    pub first: MaterialArc,
+    pub second: MaterialArc,
+    pub weight: WrappedTextureType,
+}
+
+impl MixMaterial {
+    pub fn new(first: MaterialArc, second: MaterialArc, weight: WrappedTextureType) -> Self {
+        MixMaterial {
+            first,
+            second,
+            weight,
+        }
+    }
+
+    #[inline(always)]
+    fn weight(&self, u: f64, v: f64, p: &Point3) -> f64 {
+        luminance(&self.weight.color_value(u, v, p))
+    }
+}
+
+impl Material for MixMaterial {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        record: &HitRecord,
+        uc: f64,
+        u: (f64, f64),
+    ) -> Option<ScatterRecord> {
+        let weight = self.weight(record.u, record.v, &record.hit_point);
+
+        if uc < weight {
+            self.second.scatter(ray, record, remap(uc, 0.0, weight), u)
+        } else {
+            self.first.scatter(ray, record, remap(uc, weight, 1.0), u)
+        }
+    }
+
+    fn emit(&self, ray: &Ray, record: &HitRecord) -> Color {
+        let weight = self.weight(record.u, record.v, &record.hit_point);
+
+        (1.0 - weight) * self.first.emit(ray, record) + weight * self.second.emit(ray, record)
+    }
+
+    fn eval(&self, ray: &Ray, record: &HitRecord, direction: &Vec3) -> Color {
+        let weight = self.weight(record.u, record.v, &record.hit_point);
+
+        (1.0 - weight) * self.first.eval(ray, record, direction)
+            + weight * self.second.eval(ray, record, direction)
+    }
+
+    fn pdf(&self, ray: &Ray, record: &HitRecord, direction: &Vec3) -> f64 {
+        let weight = self.weight(record.u, record.v, &record.hit_point);
+
+        (1.0 - weight) * self.first.pdf(ray, record, direction)
+            + weight * self.second.pdf(ray, record, direction)
+    }
+
+    fn export(&self, writer: &mut SceneWriter) -> Result<Entry, String> {
+        Ok(Entry::new("mix")
+            .with("first", writer.material(&self.first)?)
+            .with("second", writer.material(&self.second)?)
+            .with("weight", writer.texture(&self.weight)?))
+    }
+}
+
+/// A thin dielectric clear coat over `base`. Each hit from outside reflects
+/// off the coat with the Fresnel reflectance of the coat, roughened by
+/// `roughness`, and otherwise falls through to the base material.
+pub struct CoatedMaterial {
+    pub base: MaterialArc,
+    pub ir: f64,
+    pub roughness: f64,
+}
+
+impl CoatedMaterial {
+    pub fn new(base: MaterialArc, ir: f64, roughness: f64) -> Self {
+        CoatedMaterial {
+            base,
+            ir,
+            roughness,
+        }
+    }
+
+    /// Fraction of the incoming light reflected by the coat.
+    #[inline(always)]
+    fn reflectance(&self, ray: &Ray, record: &HitRecord) -> f64 {
+        let cos_theta = (-ray.direction.unit()).dot(&record.normal).min(1.0);
+        Dielectric::reflectance(cos_theta, 1.0 / self.ir)
+    }
+}
+
+impl Material for CoatedMaterial {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        record: &HitRecord,
+        uc: f64,
+        u: (f64, f64),
+    ) -> Option<ScatterRecord> {
+        if !record.front_face {
+            return self.base.scatter(ray, record, uc, u);
+        }
+
+        let reflectance = self.reflectance(ray, record);
+        if reflectance <= uc {
+            return self
+                .base
+                .scatter(ray, record, remap(uc, reflectance, 1.0), u);
+        }
+
+        let reflected = ray.direction.unit().reflected(&record.normal)
+            + self.roughness * uniform_ball(u, remap(uc, 0.0, reflectance));
+
+        if reflected.dot(&record.normal) > 0.0 {
+            Some(ScatterRecord::specular(
+                Color::new(1.0, 1.0, 1.0),
+                Ray::with_timing(record.hit_point, reflected, ray.time),
+            ))
+        } else {
+            None
+        }
+    }
+
+    fn emit(&self, ray: &Ray, record: &HitRecord) -> Color {
+        self.base.emit(ray, record)
+    }
+
+    fn eval(&self, ray: &Ray, record: &HitRecord, direction: &Vec3) -> Color {
+        let base = self.base.eval(ray, record, direction);
+
+        if !record.front_face {
+            return base;
+        }
+
+        (1.0 - self.reflectance(ray, record)) * base
+    }
+
+    fn pdf(&self, ray: &Ray, record: &HitRecord, direction: &Vec3) -> f64 {
+        let base = self.base.pdf(ray, record, direction);
+
+        if !record.front_face {
+            return base;
+        }
+
+        (1.0 - self.reflectance(ray, record)) * base
+    }
+
+    fn export(&self, writer: &mut SceneWriter) -> Result<Entry, String> {
+        Ok(Entry::new("coated")
+            .with("base", writer.material(&self.base)?)
+            .with("ir", self.ir)
+            .with("roughness", self.roughness))
     }
 }
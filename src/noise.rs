@@ -1,73 +1,377 @@
-use crate::utils::{random, random_uint};
-use crate::vec3::{Color, Point3};
+use crate::math_traits::InnerProduct;
+use crate::rng::Rng;
+use crate::scene_writer::{choice, Entry, SceneWriter};
+use crate::texture::Texture;
+use crate::vec3::{Color, Point3, Vec3};
+use std::sync::Arc;
 
 pub trait Noise {
     fn noise(&self, p: &Point3) -> f64;
+
+    /// Fractal Brownian motion: sums `octaves` layers of noise, each one at
+    /// double the frequency and half the amplitude of the previous layer.
+    fn fbm(&self, p: &Point3, octaves: u32) -> f64 {
+        let mut accum = 0.0;
+        let mut temp_p = *p;
+        let mut weight = 1.0;
+
+        for _ in 0..octaves {
+            accum += weight * self.noise(&temp_p);
+            weight *= 0.5;
+            temp_p = temp_p * 2.0;
+        }
+
+        accum
+    }
+
+    fn turbulence(&self, p: &Point3, octaves: u32) -> f64 {
+        self.fbm(p, octaves).abs()
+    }
+
+    /// Adds the fields choosing this noise to the entry of a noise texture.
+    fn export(&self, entry: Entry) -> Entry;
 }
 
+pub type WrappedNoiseType = Arc<dyn Noise + Sync + Send>;
+
+#[derive(Clone)]
 pub struct PerlinNoise {
+    /// Seed the tables are drawn from, which recreates them when loading.
+    pub seed: u32,
     pub perm_x: [usize; Self::SIZE],
     pub perm_y: [usize; Self::SIZE],
     pub perm_z: [usize; Self::SIZE],
-    pub randoms: [f64; Self::SIZE],
+    pub ranvec: [Vec3; Self::SIZE],
 }
 
 impl PerlinNoise {
     const SIZE: usize = 256;
 
-    pub fn new() -> Self {
+    pub fn new(rng: &mut Rng) -> Self {
+        Self::with_seed(rng.next_u32())
+    }
+
+    pub fn with_seed(seed: u32) -> Self {
+        let mut rng = Rng::new(seed as u64);
+
         PerlinNoise {
-            perm_x: Self::gen_perm(),
-            perm_y: Self::gen_perm(),
-            perm_z: Self::gen_perm(),
-            randoms: Self::gen_randoms(),
+            seed,
+            perm_x: Self::gen_perm(&mut rng),
+            perm_y: Self::gen_perm(&mut rng),
+            perm_z: Self::gen_perm(&mut rng),
+            ranvec: Self::gen_ranvec(&mut rng),
         }
     }
 
-    fn gen_randoms() -> [f64; Self::SIZE] {
-        let mut randoms = [0.0; Self::SIZE];
+    fn gen_ranvec(rng: &mut Rng) -> [Vec3; Self::SIZE] {
+        let mut ranvec = [Vec3::zero(); Self::SIZE];
 
-        for i in 0..randoms.len() {
-            randoms[i] = random();
+        for vec in ranvec.iter_mut() {
+            *vec = Vec3::random_unit_vector(rng);
         }
 
-        randoms
+        ranvec
     }
 
-    pub fn gen_perm() -> [usize; Self::SIZE] {
+    pub fn gen_perm(rng: &mut Rng) -> [usize; Self::SIZE] {
         let mut perm: [usize; Self::SIZE] = [0; Self::SIZE];
 
-        for i in 0..Self::SIZE {
-            perm[i] = i;
+        for (i, p) in perm.iter_mut().enumerate() {
+            *p = i;
         }
 
-        Self::permute(&mut perm, Self::SIZE);
+        Self::permute(&mut perm, Self::SIZE, rng);
 
         perm
     }
 
-    pub fn permute(arr: &mut [usize; Self::SIZE], size: usize) {
-        for i in 0..size {
-            let exchange = random_uint(0, size - 1);
-            let tmp = arr[i];
-            arr[i] = arr[exchange];
-            arr[exchange] = tmp;
+    pub fn permute(arr: &mut [usize; Self::SIZE], size: usize, rng: &mut Rng) {
+        for i in (1..size).rev() {
+            let exchange = rng.random_uint(0, i);
+            arr.swap(i, exchange);
+        }
+    }
+
+    /// Trilinear interpolation of the gradient contributions at the eight
+    /// lattice corners, with Hermite smoothing of the weights.
+    fn perlin_interp(c: &[[[Vec3; 2]; 2]; 2], u: f64, v: f64, w: f64) -> f64 {
+        let uu = u * u * (3.0 - 2.0 * u);
+        let vv = v * v * (3.0 - 2.0 * v);
+        let ww = w * w * (3.0 - 2.0 * w);
+
+        let mut accum = 0.0;
+
+        for (i, plane) in c.iter().enumerate() {
+            for (j, row) in plane.iter().enumerate() {
+                for (k, gradient) in row.iter().enumerate() {
+                    let (fi, fj, fk) = (i as f64, j as f64, k as f64);
+                    let weight = Vec3::new(u - fi, v - fj, w - fk);
+
+                    accum += (fi * uu + (1.0 - fi) * (1.0 - uu))
+                        * (fj * vv + (1.0 - fj) * (1.0 - vv))
+                        * (fk * ww + (1.0 - fk) * (1.0 - ww))
+                        * gradient.dot(&weight);
+                }
+            }
         }
+
+        accum
     }
 }
 
 impl Noise for PerlinNoise {
     fn noise(&self, p: &Point3) -> f64 {
-        let x = (p.x() * 4.0) as usize & (Self::SIZE - 1);
-        let y = (p.y() * 4.0) as usize & (Self::SIZE - 1);
-        let z = (p.z() * 4.0) as usize & (Self::SIZE - 1);
+        let u = p.x() - p.x().floor();
+        let v = p.y() - p.y().floor();
+        let w = p.z() - p.z().floor();
+
+        let i = p.x().floor() as isize;
+        let j = p.y().floor() as isize;
+        let k = p.z().floor() as isize;
+
+        let mask = Self::SIZE as isize - 1;
+        let mut c = [[[Vec3::zero(); 2]; 2]; 2];
 
-        self.randoms[self.perm_x[x] ^ self.perm_y[y] ^ self.perm_z[z]]
+        for (di, plane) in c.iter_mut().enumerate() {
+            for (dj, row) in plane.iter_mut().enumerate() {
+                for (dk, gradient) in row.iter_mut().enumerate() {
+                    *gradient = self.ranvec[self.perm_x[((i + di as isize) & mask) as usize]
+                        ^ self.perm_y[((j + dj as isize) & mask) as usize]
+                        ^ self.perm_z[((k + dk as isize) & mask) as usize]];
+                }
+            }
+        }
+
+        Self::perlin_interp(&c, u, v, w)
+    }
+
+    fn export(&self, entry: Entry) -> Entry {
+        entry.with("seed", self.seed)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DistanceMetric {
+    Euclidean,
+    Manhattan,
+    Chebyshev,
+}
+
+impl DistanceMetric {
+    #[inline(always)]
+    pub fn distance(&self, d: &Vec3) -> f64 {
+        match self {
+            DistanceMetric::Euclidean => d.length(),
+            DistanceMetric::Manhattan => d.x().abs() + d.y().abs() + d.z().abs(),
+            DistanceMetric::Chebyshev => d.x().abs().max(d.y().abs()).max(d.z().abs()),
+        }
     }
 }
 
-impl crate::texture::Texture for PerlinNoise {
-    fn color_value(&self, _u: f64, _v: f64, p: &Point3) -> crate::vec3::Color {
-        Color::new(1.0, 1.0, 1.0) * self.noise(p)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WorleyOutput {
+    /// Distance to the closest feature point.
+    F1,
+    /// Distance to the second closest feature point.
+    F2,
+    /// Difference of the two, which is zero along the cell borders.
+    F2MinusF1,
+}
+
+pub struct WorleySample {
+    pub f1: f64,
+    pub f2: f64,
+    /// Closest feature point, shared by every point in the same cell.
+    pub feature: Point3,
+    /// Pseudo-random id of the closest cell.
+    pub id: usize,
+}
+
+/// Cellular noise with one jittered feature point per unit lattice cell.
+pub struct WorleyNoise {
+    /// Seed the tables are drawn from, which recreates them when loading.
+    pub seed: u32,
+    pub perm_x: [usize; PerlinNoise::SIZE],
+    pub perm_y: [usize; PerlinNoise::SIZE],
+    pub perm_z: [usize; PerlinNoise::SIZE],
+    pub offsets: [Vec3; PerlinNoise::SIZE],
+    pub jitter: f64,
+    pub metric: DistanceMetric,
+    pub output: WorleyOutput,
+}
+
+impl WorleyNoise {
+    pub fn with_seed(metric: DistanceMetric, output: WorleyOutput, seed: u32) -> Self {
+        let mut rng = Rng::new(seed as u64);
+        let mut offsets = [Vec3::zero(); PerlinNoise::SIZE];
+
+        for offset in offsets.iter_mut() {
+            *offset = Vec3::random(&mut rng);
+        }
+
+        WorleyNoise {
+            seed,
+            perm_x: PerlinNoise::gen_perm(&mut rng),
+            perm_y: PerlinNoise::gen_perm(&mut rng),
+            perm_z: PerlinNoise::gen_perm(&mut rng),
+            offsets,
+            jitter: 1.0,
+            metric,
+            output,
+        }
+    }
+
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    pub fn evaluate(&self, p: &Point3) -> WorleySample {
+        let i = p.x().floor() as isize;
+        let j = p.y().floor() as isize;
+        let k = p.z().floor() as isize;
+
+        let mask = PerlinNoise::SIZE as isize - 1;
+        let mut sample = WorleySample {
+            f1: f64::INFINITY,
+            f2: f64::INFINITY,
+            feature: *p,
+            id: 0,
+        };
+
+        for di in -1..=1 {
+            for dj in -1..=1 {
+                for dk in -1..=1 {
+                    let (ci, cj, ck) = (i + di, j + dj, k + dk);
+                    let id = self.perm_x[(ci & mask) as usize]
+                        ^ self.perm_y[(cj & mask) as usize]
+                        ^ self.perm_z[(ck & mask) as usize];
+
+                    let center = Vec3::new(0.5, 0.5, 0.5);
+                    let feature = Point3::new(ci as f64, cj as f64, ck as f64)
+                        + center
+                        + self.jitter * (self.offsets[id] - center);
+                    let distance = self.metric.distance(&(feature - *p));
+
+                    if distance < sample.f1 {
+                        sample.f2 = sample.f1;
+                        sample.f1 = distance;
+                        sample.feature = feature;
+                        sample.id = id;
+                    } else if distance < sample.f2 {
+                        sample.f2 = distance;
+                    }
+                }
+            }
+        }
+
+        sample
+    }
+}
+
+impl Noise for WorleyNoise {
+    fn noise(&self, p: &Point3) -> f64 {
+        let sample = self.evaluate(p);
+
+        match self.output {
+            WorleyOutput::F1 => sample.f1,
+            WorleyOutput::F2 => sample.f2,
+            WorleyOutput::F2MinusF1 => sample.f2 - sample.f1,
+        }
+    }
+
+    fn export(&self, entry: Entry) -> Entry {
+        let metric = match self.metric {
+            DistanceMetric::Euclidean => "euclidean",
+            DistanceMetric::Manhattan => "manhattan",
+            DistanceMetric::Chebyshev => "chebyshev",
+        };
+        let output = match self.output {
+            WorleyOutput::F1 => "f1",
+            WorleyOutput::F2 => "f2",
+            WorleyOutput::F2MinusF1 => "f2_minus_f1",
+        };
+
+        entry
+            .with("noise", choice("worley"))
+            .with("metric", choice(metric))
+            .with("output", choice(output))
+            .with("jitter", self.jitter)
+            .with("seed", self.seed)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum NoisePhase {
+    /// Raw noise remapped from `[-1, 1]` to `[0, 1]`.
+    Plain,
+    /// Absolute value of the fBm sum.
+    Turbulence,
+    /// Sine bands along the z axis, displaced by turbulence.
+    Marble { distortion: f64 },
+    /// Concentric rings around the y axis, displaced by turbulence.
+    Wood { distortion: f64 },
+}
+
+pub struct NoiseTexture {
+    pub noise: WrappedNoiseType,
+    pub scale: f64,
+    pub octaves: u32,
+    pub phase: NoisePhase,
+}
+
+impl NoiseTexture {
+    pub fn new(noise: WrappedNoiseType, scale: f64, octaves: u32, phase: NoisePhase) -> Self {
+        NoiseTexture {
+            noise,
+            scale,
+            octaves,
+            phase,
+        }
+    }
+
+    pub fn perlin(scale: f64, octaves: u32, phase: NoisePhase, rng: &mut Rng) -> Self {
+        Self::new(Arc::new(PerlinNoise::new(rng)), scale, octaves, phase)
+    }
+
+    pub fn value(&self, p: &Point3) -> f64 {
+        let p = self.scale * p;
+
+        match self.phase {
+            NoisePhase::Plain => 0.5 * (1.0 + self.noise.noise(&p)),
+            NoisePhase::Turbulence => self.noise.turbulence(&p, self.octaves),
+            NoisePhase::Marble { distortion } => {
+                0.5 * (1.0 + (p.z() + distortion * self.noise.turbulence(&p, self.octaves)).sin())
+            }
+            NoisePhase::Wood { distortion } => {
+                let rings = (p.x() * p.x() + p.z() * p.z()).sqrt()
+                    + distortion * self.noise.turbulence(&p, self.octaves);
+                rings - rings.floor()
+            }
+        }
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn color_value(&self, _u: f64, _v: f64, p: &Point3) -> Color {
+        Color::new(1.0, 1.0, 1.0) * self.value(p)
+    }
+
+    fn export(&self, _writer: &mut SceneWriter) -> Result<Entry, String> {
+        let (phase, distortion) = match self.phase {
+            NoisePhase::Plain => ("plain", None),
+            NoisePhase::Turbulence => ("turbulence", None),
+            NoisePhase::Marble { distortion } => ("marble", Some(distortion)),
+            NoisePhase::Wood { distortion } => ("wood", Some(distortion)),
+        };
+        let mut entry = Entry::new("noise")
+            .with("phase", choice(phase))
+            .with("scale", self.scale)
+            .with("octaves", self.octaves);
+        if let Some(distortion) = distortion {
+            entry = entry.with("distortion", distortion);
+        }
+
+        Ok(self.noise.export(entry))
     }
 }
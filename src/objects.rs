@@ -1,22 +1,37 @@
+use crate::animation::AnimatedTransform;
 use crate::bb::{BoundingBoxHit, BoxedBoundingBoxType, AABB};
+use crate::color::luminance;
+use crate::light::sample_cone;
 use crate::material::Material;
 use crate::math_traits::{CrossProduct, InnerProduct};
 use crate::ray::Ray;
+use crate::rng;
+use crate::sampler::uniform_sphere;
+use crate::scene_writer::{Entry, SceneWriter};
+use crate::texture::WrappedTextureType;
 use crate::vec3::{Point3, Vec3};
-use crate::WorldType;
+use crate::{WorldElementType, WorldType};
+use std::f64::consts::PI;
 use std::marker::Sync;
 use std::sync::Arc;
 
-type MaterialArc = Arc<dyn Material + Sync + Send>;
+pub type MaterialArc = Arc<dyn Material + Sync + Send>;
 
+#[derive(Clone)]
 pub struct HitRecord {
     pub t: f64,
     pub u: f64,
     pub v: f64,
     pub hit_point: Point3,
     pub normal: Vec3,
+    /// Unit surface derivative along u, used as the tangent space x axis.
+    pub tangent: Vec3,
+    /// Unit surface derivative along v, used as the tangent space y axis.
+    pub bitangent: Vec3,
     pub front_face: bool,
     pub material: MaterialArc,
+    /// Index of the scene light the hit object is registered as, if any.
+    pub light: Option<usize>,
 }
 
 impl HitRecord {
@@ -29,21 +44,132 @@ impl HitRecord {
         front_face: bool,
         material: &MaterialArc,
     ) -> Self {
+        let (tangent, bitangent) = Self::default_frame(&normal);
+
         HitRecord {
             t,
             u,
             v,
             hit_point,
             normal,
+            tangent,
+            bitangent,
             front_face,
             material: material.clone(),
+            light: None,
         }
     }
+
+    fn with_frame(mut self, tangent: Vec3, bitangent: Vec3) -> Self {
+        self.tangent = tangent;
+        self.bitangent = bitangent;
+        self
+    }
+
+    /// An arbitrary orthonormal frame around `normal`, for surfaces without
+    /// a meaningful uv parameterization.
+    pub fn default_frame(normal: &Vec3) -> (Vec3, Vec3) {
+        let helper = if normal.x().abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let bitangent = normal.cross(&helper).unit();
+        let tangent = bitangent.cross(normal);
+
+        (tangent, bitangent)
+    }
 }
 
 pub trait Hittable {
     fn hit(&self, ray: &Ray, min: f64, max: f64) -> Option<HitRecord>;
     fn bounding_box(&self, start_time: f64, end_time: f64) -> Option<BoxedBoundingBoxType>;
+
+    /// Whether the object changes over time. Acceleration structures over
+    /// objects that don't can be reused across frames.
+    fn is_animated(&self) -> bool {
+        false
+    }
+
+    /// Solid angle density with which `random` picks `direction` from
+    /// `origin` at `time`. Objects that can't be sampled as area lights
+    /// return zero.
+    fn pdf_value(&self, _origin: &Point3, _direction: &Vec3, _time: f64) -> f64 {
+        0.0
+    }
+
+    /// Direction from `origin` towards the point of the object picked by
+    /// the uniform 2D sample `u`, where the object is at `time`.
+    fn random(&self, _origin: &Point3, _u: (f64, f64), _time: f64) -> Vec3 {
+        Vec3::new(1.0, 0.0, 0.0)
+    }
+
+    /// Surface area, zero when unknown.
+    fn area(&self) -> f64 {
+        0.0
+    }
+
+    /// Unit normal shared by the whole surface, for flat objects.
+    fn flat_normal(&self) -> Option<Vec3> {
+        None
+    }
+
+    /// The object as blocks of a scene file, writing the materials and
+    /// textures it uses first. Groups give one block per member.
+    fn export(&self, writer: &mut SceneWriter) -> Result<Vec<Entry>, String>;
+}
+
+fn sphere_uv(p: &Vec3) -> (f64, f64) {
+    let theta = (-p.y()).acos();
+    let phi = -p.z().atan2(p.x()) + std::f64::consts::PI;
+    let u = phi / (2.0 * std::f64::consts::PI);
+    let v = theta / std::f64::consts::PI;
+
+    (u, v)
+}
+
+/// Unit dP/du and dP/dv on a sphere with the uv mapping of `sphere_uv`,
+/// given the outward unit normal.
+fn sphere_frame(out_normal: &Vec3) -> (Vec3, Vec3) {
+    let tangent = Vec3::new(out_normal.z(), 0.0, -out_normal.x());
+
+    if tangent.near_zero() {
+        return HitRecord::default_frame(out_normal);
+    }
+
+    let tangent = tangent.unit();
+    let bitangent = out_normal.cross(&tangent);
+
+    (tangent, bitangent)
+}
+
+/// Solid angle density of directions from `origin` picked uniformly over
+/// the cone the sphere covers, or over all directions from inside it.
+fn sphere_pdf(center: &Point3, radius: f64, origin: &Point3) -> f64 {
+    let distance_squared = (*center - *origin).length_squared();
+    let radius_squared = radius * radius;
+    if distance_squared <= radius_squared {
+        return 1.0 / (4.0 * PI);
+    }
+
+    let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+    1.0 / (2.0 * PI * (1.0 - cos_theta_max))
+}
+
+/// Uniform over the cone of directions the sphere covers.
+fn sphere_direction(center: &Point3, radius: f64, origin: &Point3, u: (f64, f64)) -> Vec3 {
+    let to_center = *center - *origin;
+    let distance_squared = to_center.length_squared();
+    let radius_squared = radius * radius;
+    if distance_squared <= radius_squared {
+        return uniform_sphere(u);
+    }
+
+    sample_cone(
+        &to_center.unit(),
+        (1.0 - radius_squared / distance_squared).sqrt(),
+        u,
+    )
 }
 
 pub struct Sphere {
@@ -62,12 +188,7 @@ impl Sphere {
     }
 
     fn get_uv(&self, p: &Vec3) -> (f64, f64) {
-        let theta = -p.y().acos();
-        let phi = -p.z().atan2(p.x()) + std::f64::consts::PI;
-        let u = phi / (2.0 * std::f64::consts::PI);
-        let v = theta / std::f64::consts::PI;
-
-        (u, v)
+        sphere_uv(p)
     }
 }
 
@@ -94,7 +215,9 @@ impl Hittable for Sphere {
 
         let hit_point = ray.at(t);
 
-        let out_normal = (hit_point - self.center).unit();
+        // dividing by the signed radius turns the normals of negative
+        // radius spheres inwards
+        let out_normal = (hit_point - self.center) / self.radius;
 
         let (normal, front_face) = if out_normal.dot(&ray.direction) > 0.0 {
             (-out_normal, false)
@@ -102,25 +225,54 @@ impl Hittable for Sphere {
             (out_normal, true)
         };
 
-        let (u, v) = self.get_uv(&normal);
+        let (u, v) = self.get_uv(&out_normal);
+        let (tangent, bitangent) = sphere_frame(&out_normal);
 
-        return Some(HitRecord::new(
-            t,
-            u,
-            v,
-            hit_point,
-            normal,
-            front_face,
-            &self.material,
-        ));
+        return Some(
+            HitRecord::new(t, u, v, hit_point, normal, front_face, &self.material)
+                .with_frame(tangent, bitangent),
+        );
     }
 
     fn bounding_box(&self, _: f64, _: f64) -> Option<BoxedBoundingBoxType> {
+        // a negative radius only flips the normals, the sphere is as large
+        let radius = Vec3::new(self.radius.abs(), self.radius.abs(), self.radius.abs());
+
         Some(Arc::new(AABB::new(
-            self.center - Vec3::new(self.radius, self.radius, self.radius),
-            self.center + Vec3::new(self.radius, self.radius, self.radius),
+            self.center - radius,
+            self.center + radius,
         )))
     }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, time: f64) -> f64 {
+        if self
+            .hit(
+                &Ray::with_timing(*origin, *direction, time),
+                0.001,
+                f64::INFINITY,
+            )
+            .is_none()
+        {
+            return 0.0;
+        }
+
+        sphere_pdf(&self.center, self.radius, origin)
+    }
+
+    fn random(&self, origin: &Point3, u: (f64, f64), _time: f64) -> Vec3 {
+        sphere_direction(&self.center, self.radius, origin, u)
+    }
+
+    fn area(&self) -> f64 {
+        4.0 * PI * self.radius * self.radius
+    }
+
+    fn export(&self, writer: &mut SceneWriter) -> Result<Vec<Entry>, String> {
+        Ok(vec![Entry::new("sphere")
+            .with("center", self.center)
+            .with("radius", self.radius)
+            .with("material", writer.material(&self.material)?)])
+    }
 }
 
 impl Hittable for crate::WorldType {
@@ -150,6 +302,19 @@ impl Hittable for crate::WorldType {
 
         Some(result_box)
     }
+
+    fn is_animated(&self) -> bool {
+        self.iter().any(|e| e.is_animated())
+    }
+
+    fn export(&self, writer: &mut SceneWriter) -> Result<Vec<Entry>, String> {
+        let mut entries = vec![];
+        for object in self {
+            entries.extend(object.export(writer)?);
+        }
+
+        Ok(entries)
+    }
 }
 
 pub struct MovingSphere {
@@ -187,12 +352,7 @@ impl MovingSphere {
     }
 
     fn get_uv(&self, p: &Vec3) -> (f64, f64) {
-        let theta = -p.y().acos();
-        let phi = -p.z().atan2(p.x()) + std::f64::consts::PI;
-        let u = phi / (2.0 * std::f64::consts::PI);
-        let v = theta / std::f64::consts::PI;
-
-        (u, v)
+        sphere_uv(p)
     }
 }
 
@@ -227,7 +387,9 @@ impl Hittable for MovingSphere {
 
         let hit_point = ray.at(t);
 
-        let out_normal = (hit_point - moving_center).unit();
+        // dividing by the signed radius turns the normals of negative
+        // radius spheres inwards
+        let out_normal = (hit_point - moving_center) / self.radius;
 
         let (normal, front_face) = if out_normal.dot(&ray.direction) > 0.0 {
             (-out_normal, false)
@@ -236,29 +398,63 @@ impl Hittable for MovingSphere {
         };
 
         let (u, v) = self.get_uv(&out_normal);
+        let (tangent, bitangent) = sphere_frame(&out_normal);
 
-        return Some(HitRecord::new(
-            t,
-            u,
-            v,
-            hit_point,
-            normal,
-            front_face,
-            &self.material,
-        ));
+        return Some(
+            HitRecord::new(t, u, v, hit_point, normal, front_face, &self.material)
+                .with_frame(tangent, bitangent),
+        );
     }
 
     fn bounding_box(&self, start_time: f64, end_time: f64) -> Option<BoxedBoundingBoxType> {
+        let radius = Vec3::new(self.radius.abs(), self.radius.abs(), self.radius.abs());
         let start_ball = AABB::new(
-            self.moving_center(start_time) - Point3::new(self.radius, self.radius, self.radius),
-            self.moving_center(start_time) + Point3::new(self.radius, self.radius, self.radius),
+            self.moving_center(start_time) - radius,
+            self.moving_center(start_time) + radius,
         );
         let end_ball = AABB::new(
-            self.moving_center(end_time) - Point3::new(self.radius, self.radius, self.radius),
-            self.moving_center(end_time) + Point3::new(self.radius, self.radius, self.radius),
+            self.moving_center(end_time) - radius,
+            self.moving_center(end_time) + radius,
         );
         return Some(start_ball.merge(Arc::new(end_ball)));
     }
+
+    fn is_animated(&self) -> bool {
+        true
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, time: f64) -> f64 {
+        if self
+            .hit(
+                &Ray::with_timing(*origin, *direction, time),
+                0.001,
+                f64::INFINITY,
+            )
+            .is_none()
+        {
+            return 0.0;
+        }
+
+        sphere_pdf(&self.moving_center(time), self.radius, origin)
+    }
+
+    fn random(&self, origin: &Point3, u: (f64, f64), time: f64) -> Vec3 {
+        sphere_direction(&self.moving_center(time), self.radius, origin, u)
+    }
+
+    fn area(&self) -> f64 {
+        4.0 * PI * self.radius * self.radius
+    }
+
+    fn export(&self, writer: &mut SceneWriter) -> Result<Vec<Entry>, String> {
+        Ok(vec![Entry::new("moving_sphere")
+            .with("start_center", self.start_center)
+            .with("end_center", self.end_center)
+            .with_non_default("start_time", self.start_time, 0.0)
+            .with_non_default("end_time", self.end_time, 1.0)
+            .with("radius", self.radius)
+            .with("material", writer.material(&self.material)?)])
+    }
 }
 
 pub struct XyPlane {
@@ -359,17 +555,62 @@ impl Hittable for XyPlane {
         if hit.x() < self.x0 || hit.x() > self.x1 || hit.y() < self.y0 || hit.y() > self.y1 {
             None
         } else {
-            Some(HitRecord::new(
-                t,
-                (hit.x() - self.x0) / (self.x1 - self.x0),
-                (hit.y() - self.y0) / (self.y1 - self.y0),
-                hit,
-                normal,
-                front_facing,
-                &self.material,
-            ))
+            Some(
+                HitRecord::new(
+                    t,
+                    (hit.x() - self.x0) / (self.x1 - self.x0),
+                    (hit.y() - self.y0) / (self.y1 - self.y0),
+                    hit,
+                    normal,
+                    front_facing,
+                    &self.material,
+                )
+                .with_frame(Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0)),
+            )
         }
     }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, time: f64) -> f64 {
+        match self.hit(
+            &Ray::with_timing(*origin, *direction, time),
+            0.001,
+            f64::INFINITY,
+        ) {
+            Some(record) => {
+                let distance_squared = record.t * record.t * direction.length_squared();
+                let cosine = direction.dot(&record.normal).abs() / direction.length();
+
+                distance_squared / (cosine * self.area())
+            }
+            None => 0.0,
+        }
+    }
+
+    fn random(&self, origin: &Point3, u: (f64, f64), _time: f64) -> Vec3 {
+        Point3::new(
+            self.x0 + u.0 * (self.x1 - self.x0),
+            self.y0 + u.1 * (self.y1 - self.y0),
+            self.k,
+        ) - *origin
+    }
+
+    fn area(&self) -> f64 {
+        (self.x1 - self.x0) * (self.y1 - self.y0)
+    }
+
+    fn flat_normal(&self) -> Option<Vec3> {
+        Some(Vec3::new(0.0, 0.0, 1.0))
+    }
+
+    fn export(&self, writer: &mut SceneWriter) -> Result<Vec<Entry>, String> {
+        Ok(vec![Entry::new("xy_rect")
+            .with("x0", self.x0)
+            .with("x1", self.x1)
+            .with("y0", self.y0)
+            .with("y1", self.y1)
+            .with("k", self.k)
+            .with("material", writer.material(&self.material)?)])
+    }
 }
 impl Hittable for YzPlane {
     fn bounding_box(&self, start_time: f64, end_time: f64) -> Option<BoxedBoundingBoxType> {
@@ -398,17 +639,62 @@ impl Hittable for YzPlane {
         if hit.y() < self.y0 || hit.y() > self.y1 || hit.z() < self.z0 || hit.z() > self.z1 {
             None
         } else {
-            Some(HitRecord::new(
-                t,
-                (hit.y() - self.y0) / (self.y1 - self.y0),
-                (hit.z() - self.z0) / (self.z1 - self.z0),
-                hit,
-                normal,
-                front_facing,
-                &self.material,
-            ))
+            Some(
+                HitRecord::new(
+                    t,
+                    (hit.y() - self.y0) / (self.y1 - self.y0),
+                    (hit.z() - self.z0) / (self.z1 - self.z0),
+                    hit,
+                    normal,
+                    front_facing,
+                    &self.material,
+                )
+                .with_frame(Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 0.0, 1.0)),
+            )
+        }
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, time: f64) -> f64 {
+        match self.hit(
+            &Ray::with_timing(*origin, *direction, time),
+            0.001,
+            f64::INFINITY,
+        ) {
+            Some(record) => {
+                let distance_squared = record.t * record.t * direction.length_squared();
+                let cosine = direction.dot(&record.normal).abs() / direction.length();
+
+                distance_squared / (cosine * self.area())
+            }
+            None => 0.0,
         }
     }
+
+    fn random(&self, origin: &Point3, u: (f64, f64), _time: f64) -> Vec3 {
+        Point3::new(
+            self.k,
+            self.y0 + u.0 * (self.y1 - self.y0),
+            self.z0 + u.1 * (self.z1 - self.z0),
+        ) - *origin
+    }
+
+    fn area(&self) -> f64 {
+        (self.y1 - self.y0) * (self.z1 - self.z0)
+    }
+
+    fn flat_normal(&self) -> Option<Vec3> {
+        Some(Vec3::new(1.0, 0.0, 0.0))
+    }
+
+    fn export(&self, writer: &mut SceneWriter) -> Result<Vec<Entry>, String> {
+        Ok(vec![Entry::new("yz_rect")
+            .with("y0", self.y0)
+            .with("y1", self.y1)
+            .with("z0", self.z0)
+            .with("z1", self.z1)
+            .with("k", self.k)
+            .with("material", writer.material(&self.material)?)])
+    }
 }
 
 impl Hittable for XzPlane {
@@ -438,17 +724,62 @@ impl Hittable for XzPlane {
         if hit.x() < self.x0 || hit.x() > self.x1 || hit.z() < self.z0 || hit.z() > self.z1 {
             None
         } else {
-            Some(HitRecord::new(
-                t,
-                (hit.x() - self.x0) / (self.x1 - self.x0),
-                (hit.z() - self.z0) / (self.z1 - self.z0),
-                hit,
-                normal,
-                front_facing,
-                &self.material,
-            ))
+            Some(
+                HitRecord::new(
+                    t,
+                    (hit.x() - self.x0) / (self.x1 - self.x0),
+                    (hit.z() - self.z0) / (self.z1 - self.z0),
+                    hit,
+                    normal,
+                    front_facing,
+                    &self.material,
+                )
+                .with_frame(Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0)),
+            )
         }
     }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, time: f64) -> f64 {
+        match self.hit(
+            &Ray::with_timing(*origin, *direction, time),
+            0.001,
+            f64::INFINITY,
+        ) {
+            Some(record) => {
+                let distance_squared = record.t * record.t * direction.length_squared();
+                let cosine = direction.dot(&record.normal).abs() / direction.length();
+
+                distance_squared / (cosine * self.area())
+            }
+            None => 0.0,
+        }
+    }
+
+    fn random(&self, origin: &Point3, u: (f64, f64), _time: f64) -> Vec3 {
+        Point3::new(
+            self.x0 + u.0 * (self.x1 - self.x0),
+            self.k,
+            self.z0 + u.1 * (self.z1 - self.z0),
+        ) - *origin
+    }
+
+    fn area(&self) -> f64 {
+        (self.x1 - self.x0) * (self.z1 - self.z0)
+    }
+
+    fn flat_normal(&self) -> Option<Vec3> {
+        Some(Vec3::new(0.0, 1.0, 0.0))
+    }
+
+    fn export(&self, writer: &mut SceneWriter) -> Result<Vec<Entry>, String> {
+        Ok(vec![Entry::new("xz_rect")
+            .with("x0", self.x0)
+            .with("x1", self.x1)
+            .with("z0", self.z0)
+            .with("z1", self.z1)
+            .with("k", self.k)
+            .with("material", writer.material(&self.material)?)])
+    }
 }
 
 pub struct Box {
@@ -499,20 +830,20 @@ impl Box {
         )));
 
         // left and right
-        walls.push(Arc::new(XyPlane::new(
-            p0.x(),
-            p1.x(),
+        walls.push(Arc::new(YzPlane::new(
             p0.y(),
             p1.y(),
             p0.z(),
+            p1.z(),
+            p0.x(),
             material.clone(),
         )));
-        walls.push(Arc::new(XyPlane::new(
-            p0.x(),
-            p1.x(),
+        walls.push(Arc::new(YzPlane::new(
             p0.y(),
             p1.y(),
+            p0.z(),
             p1.z(),
+            p1.x(),
             material.clone(),
         )));
 
@@ -533,4 +864,559 @@ impl Hittable for Box {
     fn bounding_box(&self, start_time: f64, end_time: f64) -> Option<BoxedBoundingBoxType> {
         Some(Arc::new(AABB::new(self.p0, self.p1)))
     }
+
+    /// Density of the walls picked in proportion to their area, summed
+    /// over every wall along `direction`.
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, time: f64) -> f64 {
+        let area = self.area();
+
+        self.walls
+            .iter()
+            .map(|wall| wall.area() / area * wall.pdf_value(origin, direction, time))
+            .sum()
+    }
+
+    /// Uniform over the surface: the first sample picks a wall by its area
+    /// and is then stretched back over the unit interval for the wall.
+    fn random(&self, origin: &Point3, u: (f64, f64), time: f64) -> Vec3 {
+        let mut position = u.0 * self.area();
+        let (last, walls) = self.walls.split_last().unwrap();
+
+        for wall in walls {
+            if position < wall.area() {
+                return wall.random(origin, (position / wall.area(), u.1), time);
+            }
+            position -= wall.area();
+        }
+
+        last.random(origin, ((position / last.area()).min(1.0), u.1), time)
+    }
+
+    fn area(&self) -> f64 {
+        self.walls.iter().map(|wall| wall.area()).sum()
+    }
+
+    fn export(&self, writer: &mut SceneWriter) -> Result<Vec<Entry>, String> {
+        Ok(vec![Entry::new("box")
+            .with("min", self.p0)
+            .with("max", self.p1)
+            .with("material", writer.material(&self.material)?)])
+    }
+}
+
+/// Fields of the modifiers the loader wraps around an object, innermost
+/// first.
+const MODIFIER_FIELDS: &[&str] = &[
+    "translation",
+    "rotation",
+    "scale",
+    "interpolation",
+    "opacity",
+    "alpha_cutoff",
+    "flip",
+    "light",
+];
+
+/// The entries of `object`, which `modifier` is about to add its fields to.
+/// The loader applies modifiers in a fixed order, so an object already
+/// carrying the fields from `first` on would have to be wrapped the other
+/// way round.
+fn modifiable(
+    object: &WorldElementType,
+    writer: &mut SceneWriter,
+    modifier: &str,
+    first: &str,
+) -> Result<Vec<Entry>, String> {
+    let entries = object.export(writer)?;
+    let later = MODIFIER_FIELDS
+        .iter()
+        .skip_while(|key| **key != first)
+        .copied()
+        .collect::<Vec<_>>();
+
+    for entry in &entries {
+        if let Some(key) = later.iter().find(|key| entry.get(key).is_some()) {
+            return Err(format!(
+                "{} around an object with `{}` cannot be exported",
+                modifier, key
+            ));
+        }
+    }
+
+    Ok(entries)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AlphaMode {
+    /// Surfaces are kept with probability equal to their opacity, which
+    /// averages to soft edges over many samples. The choice is hashed from
+    /// the ray and the hit, so it repeats exactly between renders.
+    Stochastic,
+    /// Surfaces are kept where the opacity is at least the cutoff.
+    Threshold(f64),
+}
+
+/// Cuts holes into `object` where the luminance of `opacity` is low. Masked
+/// hits are skipped inside `hit`, so every ray traced through the world,
+/// primary or shadow, sees the same cut-out geometry.
+pub struct AlphaMask {
+    pub object: WorldElementType,
+    pub opacity: WrappedTextureType,
+    pub mode: AlphaMode,
+}
+
+impl AlphaMask {
+    const PASS_THROUGH_OFFSET: f64 = 1e-6;
+
+    pub fn new(object: WorldElementType, opacity: WrappedTextureType, mode: AlphaMode) -> Self {
+        Self {
+            object,
+            opacity,
+            mode,
+        }
+    }
+
+    fn is_opaque(&self, ray: &Ray, record: &HitRecord) -> bool {
+        let opacity = luminance(
+            &self
+                .opacity
+                .color_value(record.u, record.v, &record.hit_point),
+        );
+
+        match self.mode {
+            AlphaMode::Stochastic => {
+                let (p, d) = (record.hit_point, ray.direction);
+                opacity >= 1.0 || rng::hash(&[p.x(), p.y(), p.z(), d.x(), d.y(), d.z()]) < opacity
+            }
+            AlphaMode::Threshold(cutoff) => opacity >= cutoff,
+        }
+    }
+}
+
+impl Hittable for AlphaMask {
+    fn hit(&self, ray: &Ray, min: f64, max: f64) -> Option<HitRecord> {
+        let mut min = min;
+
+        loop {
+            let record = self.object.hit(ray, min, max)?;
+
+            if self.is_opaque(ray, &record) {
+                return Some(record);
+            }
+
+            min = record.t + Self::PASS_THROUGH_OFFSET * record.t.abs().max(1.0);
+        }
+    }
+
+    fn bounding_box(&self, start_time: f64, end_time: f64) -> Option<BoxedBoundingBoxType> {
+        self.object.bounding_box(start_time, end_time)
+    }
+
+    fn is_animated(&self) -> bool {
+        self.object.is_animated()
+    }
+
+    /// Samples the whole object, holes included: directions through a hole
+    /// find whatever of the object lies behind it, or nothing.
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, time: f64) -> f64 {
+        self.object.pdf_value(origin, direction, time)
+    }
+
+    fn random(&self, origin: &Point3, u: (f64, f64), time: f64) -> Vec3 {
+        self.object.random(origin, u, time)
+    }
+
+    fn area(&self) -> f64 {
+        self.object.area()
+    }
+
+    fn export(&self, writer: &mut SceneWriter) -> Result<Vec<Entry>, String> {
+        let opacity = writer.texture(&self.opacity)?;
+        let mut entries = modifiable(&self.object, writer, "an alpha mask", "opacity")?;
+
+        for entry in entries.iter_mut() {
+            *entry = entry.clone().with("opacity", opacity.clone());
+            if let AlphaMode::Threshold(cutoff) = self.mode {
+                *entry = entry.clone().with("alpha_cutoff", cutoff);
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
+/// Swaps the front and back faces of `object`, e.g. to turn a one sided
+/// light towards the other side of a plane.
+pub struct FlipFace {
+    pub object: WorldElementType,
+}
+
+impl FlipFace {
+    pub fn new(object: WorldElementType) -> Self {
+        Self { object }
+    }
+}
+
+impl Hittable for FlipFace {
+    fn hit(&self, ray: &Ray, min: f64, max: f64) -> Option<HitRecord> {
+        let mut record = self.object.hit(ray, min, max)?;
+        record.front_face = !record.front_face;
+
+        Some(record)
+    }
+
+    fn bounding_box(&self, start_time: f64, end_time: f64) -> Option<BoxedBoundingBoxType> {
+        self.object.bounding_box(start_time, end_time)
+    }
+
+    fn is_animated(&self) -> bool {
+        self.object.is_animated()
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, time: f64) -> f64 {
+        self.object.pdf_value(origin, direction, time)
+    }
+
+    fn random(&self, origin: &Point3, u: (f64, f64), time: f64) -> Vec3 {
+        self.object.random(origin, u, time)
+    }
+
+    fn area(&self) -> f64 {
+        self.object.area()
+    }
+
+    fn flat_normal(&self) -> Option<Vec3> {
+        self.object.flat_normal().map(|normal| -normal)
+    }
+
+    fn export(&self, writer: &mut SceneWriter) -> Result<Vec<Entry>, String> {
+        let mut entries = modifiable(&self.object, writer, "a flipped face", "light")?;
+
+        // flipping twice gives back the original faces
+        for entry in entries.iter_mut() {
+            if entry.remove("flip").is_none() {
+                *entry = entry.clone().with("flip", true);
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
+/// Tags the hits of `object` with the index of the scene light it is
+/// registered as, so that hits found by scattering can be weighed against
+/// sampling the light directly.
+pub struct Emitter {
+    pub object: WorldElementType,
+    pub light: usize,
+}
+
+impl Emitter {
+    pub fn new(object: WorldElementType, light: usize) -> Self {
+        Self { object, light }
+    }
+}
+
+impl Hittable for Emitter {
+    fn hit(&self, ray: &Ray, min: f64, max: f64) -> Option<HitRecord> {
+        let mut record = self.object.hit(ray, min, max)?;
+        record.light = Some(self.light);
+
+        Some(record)
+    }
+
+    fn bounding_box(&self, start_time: f64, end_time: f64) -> Option<BoxedBoundingBoxType> {
+        self.object.bounding_box(start_time, end_time)
+    }
+
+    fn is_animated(&self) -> bool {
+        self.object.is_animated()
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, time: f64) -> f64 {
+        self.object.pdf_value(origin, direction, time)
+    }
+
+    fn random(&self, origin: &Point3, u: (f64, f64), time: f64) -> Vec3 {
+        self.object.random(origin, u, time)
+    }
+
+    fn area(&self) -> f64 {
+        self.object.area()
+    }
+
+    fn flat_normal(&self) -> Option<Vec3> {
+        self.object.flat_normal()
+    }
+
+    fn export(&self, writer: &mut SceneWriter) -> Result<Vec<Entry>, String> {
+        let mut entries = self.object.export(writer)?;
+
+        for entry in entries.iter_mut() {
+            if entry.get("light").is_none() {
+                *entry = entry.clone().with("light", true);
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
+/// Places `object` with a keyframed transform evaluated at each ray's time,
+/// so moving instances are motion blurred over the shutter interval.
+pub struct AnimatedInstance {
+    pub object: WorldElementType,
+    pub transform: AnimatedTransform,
+}
+
+impl AnimatedInstance {
+    /// Number of transforms sampled over the shutter interval to bound the
+    /// moving object.
+    const BOUND_STEPS: usize = 16;
+
+    pub fn new(object: WorldElementType, transform: AnimatedTransform) -> Self {
+        Self { object, transform }
+    }
+}
+
+impl Hittable for AnimatedInstance {
+    fn hit(&self, ray: &Ray, min: f64, max: f64) -> Option<HitRecord> {
+        let transform = self.transform.at(ray.time);
+
+        let local_ray = Ray::with_timing(
+            transform.inverse_point(&ray.origin),
+            transform.inverse_vector(&ray.direction),
+            ray.time,
+        );
+
+        let mut record = self.object.hit(&local_ray, min, max)?;
+
+        record.hit_point = transform.point(&record.hit_point);
+        record.normal = transform.normal(&record.normal).unit();
+        record.tangent = transform.vector(&record.tangent).unit();
+        record.bitangent = transform.vector(&record.bitangent).unit();
+
+        Some(record)
+    }
+
+    fn bounding_box(&self, start_time: f64, end_time: f64) -> Option<BoxedBoundingBoxType> {
+        let local_box = self.object.bounding_box(start_time, end_time)?;
+        let (low, high) = (local_box.min(), local_box.max());
+
+        let steps = if self.transform.is_animated() {
+            Self::BOUND_STEPS
+        } else {
+            1
+        };
+
+        let mut min = Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max = -min;
+
+        for step in 0..steps {
+            let time = if steps == 1 {
+                start_time
+            } else {
+                start_time + (end_time - start_time) * step as f64 / (steps - 1) as f64
+            };
+            let transform = self.transform.at(time);
+
+            for corner in 0..8 {
+                let p = Point3::new(
+                    if corner & 1 == 0 { low.x() } else { high.x() },
+                    if corner & 2 == 0 { low.y() } else { high.y() },
+                    if corner & 4 == 0 { low.z() } else { high.z() },
+                );
+                let p = transform.point(&p);
+
+                min = min.merge_min(&p);
+                max = max.merge_max(&p);
+            }
+        }
+
+        Some(Arc::new(AABB::new(min, max)))
+    }
+
+    fn is_animated(&self) -> bool {
+        self.transform.is_animated() || self.object.is_animated()
+    }
+
+    /// Density of the object's own sampling, carried over to the placed
+    /// object: the linear part of the transform stretches the solid angle
+    /// around a unit direction `d` by `|det| * |inverse(d)|^3`.
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, time: f64) -> f64 {
+        let transform = self.transform.at(time);
+        let local_direction = transform.inverse_vector(&direction.unit());
+        let local_pdf =
+            self.object
+                .pdf_value(&transform.inverse_point(origin), &local_direction, time);
+
+        local_pdf / (transform.determinant().abs() * local_direction.length().powi(3))
+    }
+
+    fn random(&self, origin: &Point3, u: (f64, f64), time: f64) -> Vec3 {
+        let transform = self.transform.at(time);
+
+        transform.vector(
+            &self
+                .object
+                .random(&transform.inverse_point(origin), u, time),
+        )
+    }
+
+    /// Exact for uniform scales and an estimate otherwise, which is enough
+    /// to weigh lights against each other.
+    fn area(&self) -> f64 {
+        let determinant = self.transform.at(0.0).determinant().abs();
+
+        self.object.area() * determinant.powf(2.0 / 3.0)
+    }
+
+    fn export(&self, writer: &mut SceneWriter) -> Result<Vec<Entry>, String> {
+        let mut entries = modifiable(&self.object, writer, "an animated instance", "translation")?;
+        for entry in entries.iter_mut() {
+            *entry = self.transform.export(entry.clone())?;
+        }
+
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::animation::{Quaternion, Transform};
+    use crate::bb::BvhNode;
+    use crate::material::Dielectric;
+    use crate::rng::Rng;
+    use crate::texture::SolidTexture;
+    use crate::vec3::Color;
+
+    fn glass() -> MaterialArc {
+        Arc::new(Dielectric::new(1.5))
+    }
+
+    #[test]
+    fn negative_radius_spheres_have_upright_boxes() {
+        let sphere = Sphere::new(Point3::new(1.0, 2.0, 3.0), -0.5, glass());
+        let moving = MovingSphere::new(
+            Point3::zero(),
+            Point3::new(1.0, 0.0, 0.0),
+            0.0,
+            1.0,
+            -0.5,
+            glass(),
+        );
+
+        for bounding_box in [
+            sphere.bounding_box(0.0, 1.0).unwrap(),
+            moving.bounding_box(0.0, 1.0).unwrap(),
+        ] {
+            for axis in 0..3 {
+                assert!(bounding_box.min()[axis] < bounding_box.max()[axis]);
+            }
+        }
+    }
+
+    #[test]
+    fn negative_radius_sphere_is_found_through_the_bvh() {
+        let world: WorldType = vec![
+            Arc::new(Sphere::new(Point3::zero(), -0.8, glass())),
+            Arc::new(Sphere::new(Point3::new(10.0, 0.0, 0.0), 1.0, glass())),
+        ];
+        let tree = BvhNode::build(&world, 0.0, 1.0, &mut Rng::new(0));
+
+        let ray = Ray::new(Point3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        let hit = tree[0]
+            .hit(&ray, 0.001, f64::INFINITY)
+            .expect("the ray hits the sphere");
+
+        assert!((hit.t - 4.2).abs() < 1e-9);
+    }
+
+    /// Integral over all directions from `origin` of the density with
+    /// which `object` is sampled at `time`, which should be one.
+    fn total_density(object: &dyn Hittable, origin: &Point3, time: f64) -> f64 {
+        const DIRECTIONS: usize = 200_000;
+        let mut rng = Rng::new(1);
+
+        let sum: f64 = (0..DIRECTIONS)
+            .map(|_| {
+                let direction = uniform_sphere((rng.random(), rng.random()));
+                object.pdf_value(origin, &direction, time)
+            })
+            .sum();
+
+        4.0 * PI * sum / DIRECTIONS as f64
+    }
+
+    #[test]
+    fn sampled_light_densities_integrate_to_one() {
+        let transform = Transform {
+            translation: Vec3::new(0.0, 2.0, 0.0),
+            rotation: Quaternion::from_euler(30.0, 0.0, 40.0),
+            scale: Vec3::new(2.0, 1.0, 0.5),
+        };
+        let objects: Vec<(&str, WorldElementType)> = vec![
+            (
+                "box",
+                Arc::new(Box::new(
+                    Point3::new(-0.5, 1.0, -0.5),
+                    Point3::new(0.5, 1.5, 0.5),
+                    glass(),
+                )),
+            ),
+            (
+                "moving sphere",
+                Arc::new(MovingSphere::new(
+                    Point3::new(-1.0, 1.0, 0.0),
+                    Point3::new(1.0, 1.0, 0.0),
+                    0.0,
+                    1.0,
+                    0.5,
+                    glass(),
+                )),
+            ),
+            (
+                "transformed rectangle",
+                Arc::new(AnimatedInstance::new(
+                    Arc::new(XzPlane::new(-0.5, 0.5, -0.5, 0.5, 0.0, glass())),
+                    AnimatedTransform::fixed(&transform),
+                )),
+            ),
+            (
+                "masked rectangle",
+                Arc::new(AlphaMask::new(
+                    Arc::new(XzPlane::new(-0.5, 0.5, -0.5, 0.5, 1.0, glass())),
+                    Arc::new(SolidTexture::new(Color::new(0.5, 0.5, 0.5))),
+                    AlphaMode::Threshold(0.25),
+                )),
+            ),
+        ];
+
+        for (name, object) in objects {
+            let origin = Point3::new(0.3, 0.0, 0.2);
+            assert!(object.area() > 0.0, "{} has no area", name);
+
+            let total = total_density(object.as_ref(), &origin, 0.7);
+            assert!(
+                (total - 1.0).abs() < 0.03,
+                "{} density sums to {}",
+                name,
+                total
+            );
+
+            let direction = object.random(&origin, (0.3, 0.6), 0.7);
+            assert!(
+                object
+                    .hit(
+                        &Ray::with_timing(origin, direction, 0.7),
+                        0.001,
+                        f64::INFINITY
+                    )
+                    .is_some(),
+                "{} is not found along its own sample",
+                name
+            );
+        }
+    }
 }
@@ -0,0 +1,267 @@
+use crate::math_traits::InnerProduct;
+use crate::noise::{DistanceMetric, WorleyNoise, WorleyOutput};
+use crate::scene_writer::{Entry, SceneWriter};
+use crate::texture::{Texture, WrappedTextureType};
+use crate::utils::clamp;
+use crate::vec3::{Color, Point3, Vec3};
+
+/// Running bond brick pattern in uv space. Every other row is shifted by
+/// `offset` bricks.
+pub struct BrickTexture {
+    pub brick: WrappedTextureType,
+    pub mortar: WrappedTextureType,
+    pub brick_width: f64,
+    pub brick_height: f64,
+    pub mortar_width: f64,
+    pub offset: f64,
+}
+
+impl BrickTexture {
+    pub fn new(
+        brick: WrappedTextureType,
+        mortar: WrappedTextureType,
+        brick_width: f64,
+        brick_height: f64,
+        mortar_width: f64,
+    ) -> Self {
+        BrickTexture {
+            brick,
+            mortar,
+            brick_width,
+            brick_height,
+            mortar_width,
+            offset: 0.5,
+        }
+    }
+
+    /// Shifts every other row by `offset` bricks instead of half a brick.
+    pub fn with_offset(mut self, offset: f64) -> Self {
+        self.offset = offset;
+        self
+    }
+}
+
+impl Texture for BrickTexture {
+    fn color_value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        let row_f = v / self.brick_height;
+        let row = row_f.floor();
+        let shift = if row.rem_euclid(2.0) == 1.0 {
+            self.offset
+        } else {
+            0.0
+        };
+        let column_f = u / self.brick_width + shift;
+
+        let x = (column_f - column_f.floor()) * self.brick_width;
+        let y = (row_f - row) * self.brick_height;
+        let half = self.mortar_width / 2.0;
+
+        if x < half || x > self.brick_width - half || y < half || y > self.brick_height - half {
+            self.mortar.color_value(u, v, p)
+        } else {
+            self.brick.color_value(u, v, p)
+        }
+    }
+
+    fn export(&self, writer: &mut SceneWriter) -> Result<Entry, String> {
+        Ok(Entry::new("brick")
+            .with("brick", writer.texture(&self.brick)?)
+            .with("mortar", writer.texture(&self.mortar)?)
+            .with("brick_width", self.brick_width)
+            .with("brick_height", self.brick_height)
+            .with("mortar_width", self.mortar_width)
+            .with_non_default("offset", self.offset, 0.5))
+    }
+}
+
+/// Irregular stone tiles from the Voronoi cells of Worley noise, evaluated at
+/// `scale * p`. Grout is drawn where the two closest cells are within
+/// `grout_width` of each other, and each tile's colour is scaled by a random
+/// factor in `[1 - variation, 1]`. The cells are laid out from `seed`.
+pub struct TileTexture {
+    pub noise: WorleyNoise,
+    pub tile: WrappedTextureType,
+    pub grout: WrappedTextureType,
+    pub scale: f64,
+    pub grout_width: f64,
+    pub variation: f64,
+}
+
+impl TileTexture {
+    pub fn new(
+        tile: WrappedTextureType,
+        grout: WrappedTextureType,
+        scale: f64,
+        grout_width: f64,
+        variation: f64,
+        seed: u32,
+    ) -> Self {
+        TileTexture {
+            noise: WorleyNoise::with_seed(DistanceMetric::Euclidean, WorleyOutput::F2MinusF1, seed),
+            tile,
+            grout,
+            scale,
+            grout_width,
+            variation,
+        }
+    }
+}
+
+impl Texture for TileTexture {
+    fn color_value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        let sample = self.noise.evaluate(&(self.scale * p));
+
+        if sample.f2 - sample.f1 < self.grout_width {
+            self.grout.color_value(u, v, p)
+        } else {
+            let shade = 1.0 - self.variation * self.noise.offsets[sample.id].x();
+            shade * self.tile.color_value(u, v, p)
+        }
+    }
+
+    fn export(&self, writer: &mut SceneWriter) -> Result<Entry, String> {
+        Ok(Entry::new("tile")
+            .with("tile", writer.texture(&self.tile)?)
+            .with("grout", writer.texture(&self.grout)?)
+            .with("scale", self.scale)
+            .with("grout_width", self.grout_width)
+            .with("variation", self.variation)
+            .with("seed", self.noise.seed))
+    }
+}
+
+/// Parallel stripes across `direction` in object space. `duty` is the
+/// fraction of each period covered by the first texture.
+pub struct StripeTexture {
+    pub first: WrappedTextureType,
+    pub second: WrappedTextureType,
+    pub direction: Vec3,
+    pub frequency: f64,
+    pub duty: f64,
+}
+
+impl StripeTexture {
+    pub fn new(
+        first: WrappedTextureType,
+        second: WrappedTextureType,
+        direction: Vec3,
+        frequency: f64,
+        duty: f64,
+    ) -> Self {
+        StripeTexture {
+            first,
+            second,
+            direction: direction.unit_direction(),
+            frequency,
+            duty,
+        }
+    }
+}
+
+impl Texture for StripeTexture {
+    fn color_value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        let t = p.dot(&self.direction) * self.frequency;
+
+        if t - t.floor() < self.duty {
+            self.first.color_value(u, v, p)
+        } else {
+            self.second.color_value(u, v, p)
+        }
+    }
+
+    fn export(&self, writer: &mut SceneWriter) -> Result<Entry, String> {
+        Ok(Entry::new("stripe")
+            .with("first", writer.texture(&self.first)?)
+            .with("second", writer.texture(&self.second)?)
+            .with("direction", self.direction)
+            .with("frequency", self.frequency)
+            .with("duty", self.duty))
+    }
+}
+
+/// Checker board laid out in uv space rather than in object space like
+/// `CheckerTexture`, so the squares follow the surface parameterization.
+pub struct UvCheckerTexture {
+    pub odd: WrappedTextureType,
+    pub even: WrappedTextureType,
+    pub columns: f64,
+    pub rows: f64,
+}
+
+impl UvCheckerTexture {
+    pub fn new(odd: WrappedTextureType, even: WrappedTextureType, columns: f64, rows: f64) -> Self {
+        UvCheckerTexture {
+            odd,
+            even,
+            columns,
+            rows,
+        }
+    }
+}
+
+impl Texture for UvCheckerTexture {
+    fn color_value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        let cell = (u * self.columns).floor() + (v * self.rows).floor();
+
+        if cell.rem_euclid(2.0) == 1.0 {
+            self.odd.color_value(u, v, p)
+        } else {
+            self.even.color_value(u, v, p)
+        }
+    }
+
+    fn export(&self, writer: &mut SceneWriter) -> Result<Entry, String> {
+        Ok(Entry::new("uv_checker")
+            .with("odd", writer.texture(&self.odd)?)
+            .with("even", writer.texture(&self.even)?)
+            .with("columns", self.columns)
+            .with("rows", self.rows))
+    }
+}
+
+/// Blends from `inner` at `(center_u, center_v)` to `outer` at `radius` away
+/// in uv space.
+pub struct RadialGradientTexture {
+    pub inner: WrappedTextureType,
+    pub outer: WrappedTextureType,
+    pub center_u: f64,
+    pub center_v: f64,
+    pub radius: f64,
+}
+
+impl RadialGradientTexture {
+    pub fn new(
+        inner: WrappedTextureType,
+        outer: WrappedTextureType,
+        center_u: f64,
+        center_v: f64,
+        radius: f64,
+    ) -> Self {
+        RadialGradientTexture {
+            inner,
+            outer,
+            center_u,
+            center_v,
+            radius,
+        }
+    }
+}
+
+impl Texture for RadialGradientTexture {
+    fn color_value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        let du = u - self.center_u;
+        let dv = v - self.center_v;
+        let t = clamp((du * du + dv * dv).sqrt() / self.radius, 0.0, 1.0);
+
+        (1.0 - t) * self.inner.color_value(u, v, p) + t * self.outer.color_value(u, v, p)
+    }
+
+    fn export(&self, writer: &mut SceneWriter) -> Result<Entry, String> {
+        Ok(Entry::new("radial_gradient")
+            .with("inner", writer.texture(&self.inner)?)
+            .with("outer", writer.texture(&self.outer)?)
+            .with("center_u", self.center_u)
+            .with("center_v", self.center_v)
+            .with("radius", self.radius))
+    }
+}
@@ -1,5 +1,9 @@
+use crate::light;
 use crate::math_traits::InnerProduct;
-use crate::objects::Hittable;
+use crate::objects::{HitRecord, Hittable};
+use crate::rng::Rng;
+use crate::sampler::{BounceSample, Sampler};
+use crate::scene::Scene;
 use crate::vec3;
 
 pub struct Ray {
@@ -42,39 +46,154 @@ pub fn background(ray: &Ray) -> vec3::Color {
 #[inline(always)]
 pub fn ray_color(
     ray: &Ray,
-    world: &crate::WorldType,
+    scene: &Scene,
     iter: u32,
     background: &vec3::Color,
+    sampler: &mut dyn Sampler,
 ) -> vec3::Color {
-    if iter <= 0 {
+    trace(ray, scene, iter, background, None, sampler)
+}
+
+/// Hit that scattered a ray in a direction the lights were also sampled in.
+struct Bounce {
+    point: vec3::Point3,
+    normal: vec3::Vec3,
+    /// Density with which the material picked the direction of the ray.
+    pdf: f64,
+}
+
+impl Bounce {
+    /// Multiple importance sampling weight of light reaching the bounce from
+    /// `light` along `direction` at `time`, which the light sampler could
+    /// have found as well.
+    fn weight(&self, scene: &Scene, light: usize, direction: &vec3::Vec3, time: f64) -> f64 {
+        let light_pdf = scene.light_sampler.pmf(&self.point, &self.normal, light)
+            * scene.lights[light].pdf(&self.point, direction, time);
+
+        light::power_heuristic(self.pdf, light_pdf)
+    }
+}
+
+/// `previous` is the last hit, unless the ray left it by a specular bounce
+/// or is a camera ray.
+fn trace(
+    ray: &Ray,
+    scene: &Scene,
+    iter: u32,
+    background: &vec3::Color,
+    previous: Option<Bounce>,
+    sampler: &mut dyn Sampler,
+) -> vec3::Color {
+    if iter == 0 {
         return vec3::Color::zero();
     }
 
-    if let Some(record) = world.hit(&ray, 0.001, f64::INFINITY) {
-        let emitted = record.material.emit(record.u, record.v, &record.hit_point);
-        if let Some((color, out_ray)) = record.material.scatter(&ray, &record) {
-            emitted + ray_color(&out_ray, world, iter - 1, background) * color
-        } else {
+    if let Some(record) = scene.world.hit(ray, 0.001, f64::INFINITY) {
+        let mut emitted = record.material.emit(ray, &record);
+        if let (Some(light), Some(previous)) = (record.light, &previous) {
+            emitted = previous.weight(scene, light, &ray.direction.unit(), ray.time) * emitted;
+        }
+
+        let sample = BounceSample::get(sampler);
+        let direct = direct_light(ray, &record, scene, &sample);
+
+        if let Some(scatter) =
+            record
+                .material
+                .scatter(ray, &record, sample.scatter, sample.direction)
+        {
+            let bounce = if scatter.specular {
+                None
+            } else {
+                Some(Bounce {
+                    point: record.hit_point,
+                    normal: record.normal,
+                    pdf: record
+                        .material
+                        .pdf(ray, &record, &scatter.ray.direction.unit()),
+                })
+            };
+
             emitted
+                + direct
+                + trace(&scatter.ray, scene, iter - 1, background, bounce, sampler)
+                    * scatter.attenuation
+        } else {
+            emitted + direct
+        }
+    } else if let Some(environment) = &scene.environment {
+        let radiance = environment.radiance(&ray.direction);
+
+        match (scene.environment_light, &previous) {
+            (Some(light), Some(previous)) => {
+                previous.weight(scene, light, &ray.direction.unit(), ray.time) * radiance
+            }
+            _ => radiance,
         }
     } else {
         *background
     }
 }
 
+/// Light arriving at the hit straight from one light picked by the light
+/// sampler, scattered towards the ray origin.
+#[inline(always)]
+pub fn direct_light(
+    ray: &Ray,
+    record: &HitRecord,
+    scene: &Scene,
+    bounce: &BounceSample,
+) -> vec3::Color {
+    let (light, pmf) =
+        match scene
+            .light_sampler
+            .sample(&record.hit_point, &record.normal, bounce.light)
+        {
+            Some(choice) => choice,
+            None => return vec3::Color::zero(),
+        };
+
+    let sample =
+        match scene.lights[light].sample(&record.hit_point, bounce.light_position, ray.time) {
+            Some(sample) => sample,
+            None => return vec3::Color::zero(),
+        };
+
+    let bsdf = record.material.eval(ray, record, &sample.direction);
+    if bsdf.near_zero() || !light::visible(&scene.world, &record.hit_point, &sample, ray.time) {
+        return vec3::Color::zero();
+    }
+
+    let weight = match sample.pdf {
+        Some(pdf) => light::power_heuristic(
+            pmf * pdf,
+            record.material.pdf(ray, record, &sample.direction),
+        ),
+        None => 1.0,
+    };
+
+    weight / pmf * bsdf * sample.radiance
+}
+
 #[allow(dead_code)]
 #[inline(always)]
-pub fn ray_color_unit_vector(ray: &Ray, world: &crate::WorldType, iter: u32) -> vec3::Color {
+pub fn ray_color_unit_vector(
+    ray: &Ray,
+    world: &crate::WorldType,
+    iter: u32,
+    rng: &mut Rng,
+) -> vec3::Color {
     if iter <= 0 {
         return vec3::Color::zero();
     }
 
     if let Some(record) = world.hit(&ray, 0.001, f64::INFINITY) {
-        let target = record.hit_point + record.normal + vec3::Vec3::random_unit_vector();
+        let target = record.hit_point + record.normal + vec3::Vec3::random_unit_vector(rng);
         0.5 * ray_color_unit_vector(
             &Ray::new(record.hit_point, target - record.hit_point),
             world,
             iter - 1,
+            rng,
         )
     } else {
         background(&ray)
@@ -83,17 +202,23 @@ pub fn ray_color_unit_vector(ray: &Ray, world: &crate::WorldType, iter: u32) ->
 
 #[allow(dead_code)]
 #[inline(always)]
-pub fn ray_color_hemisphere(ray: &Ray, world: &crate::WorldType, iter: u32) -> vec3::Color {
+pub fn ray_color_hemisphere(
+    ray: &Ray,
+    world: &crate::WorldType,
+    iter: u32,
+    rng: &mut Rng,
+) -> vec3::Color {
     if iter <= 0 {
         return vec3::Color::zero();
     }
 
     if let Some(record) = world.hit(&ray, 0.001, f64::INFINITY) {
-        let target = record.hit_point + vec3::Vec3::random_in_hemisphere(&record.normal);
+        let target = record.hit_point + vec3::Vec3::random_in_hemisphere(&record.normal, rng);
         0.5 * ray_color_hemisphere(
             &Ray::new(record.hit_point, target - record.hit_point),
             world,
             iter - 1,
+            rng,
         )
     } else {
         background(&ray)
@@ -102,17 +227,23 @@ pub fn ray_color_hemisphere(ray: &Ray, world: &crate::WorldType, iter: u32) -> v
 
 #[allow(dead_code)]
 #[inline(always)]
-pub fn ray_color_unit_sphere(ray: &Ray, world: &crate::WorldType, iter: u32) -> vec3::Color {
+pub fn ray_color_unit_sphere(
+    ray: &Ray,
+    world: &crate::WorldType,
+    iter: u32,
+    rng: &mut Rng,
+) -> vec3::Color {
     if iter <= 0 {
         return vec3::Color::zero();
     }
 
     if let Some(record) = world.hit(&ray, 0.001, f64::INFINITY) {
-        let target = record.hit_point + record.normal + vec3::Vec3::random_in_unit_sphere();
+        let target = record.hit_point + record.normal + vec3::Vec3::random_in_unit_sphere(rng);
         0.5 * ray_color_unit_sphere(
             &Ray::new(record.hit_point, target - record.hit_point),
             world,
             iter - 1,
+            rng,
         )
     } else {
         background(&ray)
@@ -0,0 +1,322 @@
+use crate::bb::BvhNode;
+use crate::camera::{CameraSettings, Eye, StereoCamera, WrappedCameraType};
+use crate::film::{Film, Filter};
+use crate::image::Image;
+use crate::ray;
+use crate::rng::Rng;
+use crate::sampler::{CameraSample, SamplerType};
+use crate::scene::Scene;
+use crate::vec3::Color;
+use crate::WorldType;
+use std::io;
+use std::sync::Arc;
+use std::thread;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RenderSettings {
+    pub width: usize,
+    pub height: usize,
+    pub samples_per_pixel: u32,
+    pub max_depth: u32,
+    pub background: Color,
+    /// Renders with the same seed and settings are identical.
+    pub seed: u64,
+    pub sampler: SamplerType,
+    /// Reconstruction filter the samples are splatted into the pixels with.
+    pub filter: Filter,
+    /// Spends the samples where the image is noisy instead of evenly when
+    /// set. `samples_per_pixel` is then the average over the image.
+    pub adaptive: Option<AdaptiveSettings>,
+}
+
+/// Adaptive sampling first takes `min_samples` in every pixel. Then, pass by
+/// pass, it takes `min_samples` more in the pixels whose relative error is
+/// still above `threshold` (see `FilmPixel::relative_error`), until they all
+/// fall below it, reach `max_samples` or the budget runs out.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AdaptiveSettings {
+    pub threshold: f64,
+    pub min_samples: u32,
+    pub max_samples: u32,
+}
+
+impl AdaptiveSettings {
+    /// A quarter of the average samples to start with, and at most eight
+    /// times the average in any pixel.
+    pub fn new(threshold: f64, samples_per_pixel: u32) -> Self {
+        Self {
+            threshold,
+            min_samples: (samples_per_pixel / 4).max(2),
+            max_samples: samples_per_pixel.saturating_mul(8).max(2),
+        }
+    }
+}
+
+/// Frame range of an animation. Frame `n` opens the shutter at `n / fps`
+/// and keeps it open for `shutter` of the frame duration (0.5 is a 180
+/// degree shutter).
+pub struct SequenceSettings {
+    pub first_frame: u32,
+    pub last_frame: u32,
+    pub fps: f64,
+    pub shutter: f64,
+    /// Frames are written to `<output>_<frame>.png`, with the frame number
+    /// padded to four digits.
+    pub output: String,
+}
+
+impl SequenceSettings {
+    pub fn frame_interval(&self, frame: u32) -> (f64, f64) {
+        let start_time = frame as f64 / self.fps;
+
+        (start_time, start_time + self.shutter / self.fps)
+    }
+
+    pub fn frame_path(&self, frame: u32) -> String {
+        format!("{}_{:04}.png", self.output, frame)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StereoLayout {
+    /// Left eye on the left half, right eye on the right half.
+    SideBySide,
+    /// Left eye on the top half, right eye on the bottom half.
+    TopBottom,
+}
+
+/// How the two eyes of a stereo render are set up and packed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StereoMode {
+    /// Off-axis perspective pair, packed side by side.
+    SideBySide,
+    /// Off-axis perspective pair, packed top and bottom.
+    TopBottom,
+    /// Omni-directional stereo panoramas, left eye on top.
+    Omnidirectional,
+}
+
+impl StereoMode {
+    /// Names of the modes on the command line.
+    pub const NAMES: [(&'static str, StereoMode); 3] = [
+        ("side-by-side", StereoMode::SideBySide),
+        ("top-bottom", StereoMode::TopBottom),
+        ("ods", StereoMode::Omnidirectional),
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::NAMES
+            .iter()
+            .find(|(option, _)| *option == name)
+            .map(|(_, mode)| *mode)
+    }
+
+    pub fn layout(&self) -> StereoLayout {
+        match self {
+            StereoMode::SideBySide => StereoLayout::SideBySide,
+            StereoMode::TopBottom | StereoMode::Omnidirectional => StereoLayout::TopBottom,
+        }
+    }
+}
+
+/// Renders the filtered linear radiance of every pixel, top row first. The
+/// samples of each pixel only depend on the seed and the pixel position.
+pub fn render(camera: &WrappedCameraType, scene: &Arc<Scene>, settings: &RenderSettings) -> Image {
+    let mut film = Film::new(settings.width, settings.height, settings.filter);
+    render_film(camera, scene, settings, &mut film);
+
+    film.image()
+}
+
+/// Adds `samples_per_pixel` samples per pixel to `film`, or as many on
+/// average when sampling adaptively. The sample sequence of each pixel
+/// continues after the samples it already has.
+pub fn render_film(
+    camera: &WrappedCameraType,
+    scene: &Arc<Scene>,
+    settings: &RenderSettings,
+    film: &mut Film,
+) {
+    let adaptive = match &settings.adaptive {
+        Some(adaptive) => adaptive,
+        None => {
+            add_samples(camera, scene, settings, film, |_, _| {
+                settings.samples_per_pixel
+            });
+            return;
+        }
+    };
+
+    let pixels = (film.width * film.height) as u64;
+    let mut budget = settings.samples_per_pixel as u64 * pixels;
+    let first_pass = adaptive.min_samples.min(settings.samples_per_pixel);
+    add_samples(camera, scene, settings, film, |_, _| first_pass);
+    budget = budget.saturating_sub(first_pass as u64 * pixels);
+
+    loop {
+        let batches: Vec<u32> = (0..film.height)
+            .flat_map(|j| (0..film.width).map(move |i| (i, j)))
+            .map(|(i, j)| {
+                let pixel = film.pixel(i, j);
+                if pixel.relative_error() > adaptive.threshold {
+                    adaptive
+                        .max_samples
+                        .saturating_sub(pixel.samples)
+                        .min(adaptive.min_samples)
+                } else {
+                    0
+                }
+            })
+            .collect();
+
+        let noisy = batches.iter().filter(|batch| **batch > 0).count() as u64;
+        let wanted: u64 = batches.iter().map(|batch| *batch as u64).sum();
+        if noisy == 0 || budget < noisy {
+            break;
+        }
+
+        // with too little budget left for full batches, every noisy pixel
+        // gets an equal share of it
+        let most = if wanted <= budget {
+            adaptive.min_samples
+        } else {
+            (budget / noisy) as u32
+        };
+        eprintln!("Adaptive pass over {} noisy pixels", noisy);
+
+        let width = film.width;
+        add_samples(camera, scene, settings, film, |i, j| {
+            batches[j * width + i].min(most)
+        });
+        budget -= batches
+            .iter()
+            .map(|batch| (*batch).min(most) as u64)
+            .sum::<u64>();
+    }
+}
+
+/// Adds `count(i, j)` samples to pixel `(i, j)` of `film`.
+fn add_samples<F>(
+    camera: &WrappedCameraType,
+    scene: &Arc<Scene>,
+    settings: &RenderSettings,
+    film: &mut Film,
+    count: F,
+) where
+    F: Fn(usize, usize) -> u32,
+{
+    let (width, height) = (film.width, film.height);
+
+    for j in (0..height).rev() {
+        eprintln!("Output remaining {}", j + 1);
+        let mut handlers = vec![];
+
+        for i in 0..width {
+            let samples = count(i, j);
+            if samples == 0 {
+                continue;
+            }
+
+            let scene = scene.clone();
+            let camera = camera.clone();
+            let max_depth = settings.max_depth;
+            let background = settings.background;
+            let mut sampler = settings
+                .sampler
+                .build(settings.seed, settings.samples_per_pixel);
+            let first = film.pixel(i, j).samples;
+
+            handlers.push(thread::spawn(move || {
+                let mut splats = Vec::with_capacity(samples as usize);
+
+                for index in first..first + samples {
+                    sampler.start_pixel_sample(i, j, index);
+                    let sample = CameraSample::get(sampler.as_mut());
+                    let (x, y) = (i as f64 + sample.film.0, j as f64 + sample.film.1);
+                    let u = x / (width - 1) as f64;
+                    let v = y / (height - 1) as f64;
+                    let color = match camera.get_ray(u, v, &sample) {
+                        Some(ray) => {
+                            ray::ray_color(&ray, &scene, max_depth, &background, sampler.as_mut())
+                        }
+                        None => Color::zero(),
+                    };
+
+                    splats.push((x, y, color));
+                }
+
+                splats
+            }));
+        }
+
+        for handler in handlers {
+            for (x, y, color) in handler.join().unwrap() {
+                film.add_sample(x, y, color);
+            }
+        }
+    }
+}
+
+/// Renders both eyes of `camera` at the resolution in `settings` and packs
+/// them into one image.
+pub fn render_stereo(
+    camera: &StereoCamera,
+    scene: &Arc<Scene>,
+    settings: &RenderSettings,
+    layout: StereoLayout,
+) -> Image {
+    let left = render(camera.eye(Eye::Left), scene, settings);
+    let right = render(camera.eye(Eye::Right), scene, settings);
+
+    match layout {
+        StereoLayout::SideBySide => Image::side_by_side(&left, &right),
+        StereoLayout::TopBottom => Image::top_bottom(&left, &right),
+    }
+}
+
+/// Renders every frame of `sequence` to a numbered PNG. The camera is built
+/// per frame for its shutter interval, so it follows its tracks and blurs
+/// with its motion during the frame. Objects that don't move share one BVH
+/// across all frames; only the animated ones are rebuilt each frame.
+pub fn render_sequence(
+    camera: &CameraSettings,
+    scene: &Scene,
+    settings: &RenderSettings,
+    sequence: &SequenceSettings,
+) -> io::Result<()> {
+    let aspect_ratio = settings.width as f64 / settings.height as f64;
+
+    let (animated, fixed): (WorldType, WorldType) = scene
+        .world
+        .iter()
+        .cloned()
+        .partition(|object| object.is_animated());
+
+    let (start_time, _) = sequence.frame_interval(sequence.first_frame);
+    let (_, end_time) = sequence.frame_interval(sequence.last_frame);
+    let mut rng = Rng::new(settings.seed);
+    let fixed = BvhNode::build(&fixed, start_time, end_time, &mut rng);
+
+    for frame in sequence.first_frame..=sequence.last_frame {
+        eprintln!("Rendering frame {}", frame);
+
+        let (start_time, end_time) = sequence.frame_interval(frame);
+        let mut frame_world = fixed.clone();
+        if !animated.is_empty() {
+            frame_world.extend(BvhNode::build(&animated, start_time, end_time, &mut rng));
+        }
+
+        let frame_camera = camera.build(aspect_ratio, start_time, end_time);
+        let frame_scene = scene.with_world(frame_world);
+        // a new seed per frame keeps the noise from standing still
+        let frame_settings = RenderSettings {
+            seed: settings.seed.wrapping_add(frame as u64),
+            ..*settings
+        };
+        let image = render(&frame_camera, &Arc::new(frame_scene), &frame_settings);
+
+        image.write_png(sequence.frame_path(frame))?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,92 @@
+/// PCG32 random number generator (XSH-RR output on a 64 bit LCG). Streams
+/// with different `stream` values are independent, so every pixel can draw
+/// from its own and the image does not depend on how pixels are spread over
+/// threads.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+    increment: u64,
+}
+
+impl Rng {
+    const MULTIPLIER: u64 = 6364136223846793005;
+
+    pub fn new(seed: u64) -> Self {
+        Self::with_stream(seed, 0)
+    }
+
+    pub fn with_stream(seed: u64, stream: u64) -> Self {
+        let mut rng = Self {
+            state: 0,
+            increment: (stream << 1) | 1,
+        };
+
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+
+        rng
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old
+            .wrapping_mul(Self::MULTIPLIER)
+            .wrapping_add(self.increment);
+
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        xorshifted.rotate_right((old >> 59) as u32)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        ((self.next_u32() as u64) << 32) | self.next_u32() as u64
+    }
+
+    /// Uniform in `[0, 1)`, with all 53 bits of the mantissa random.
+    #[inline(always)]
+    pub fn random(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    #[inline(always)]
+    pub fn random_range(&mut self, min: f64, max: f64) -> f64 {
+        min + (max - min) * self.random()
+    }
+
+    /// Uniform in `min..=max`.
+    #[inline(always)]
+    pub fn random_uint(&mut self, min: usize, max: usize) -> usize {
+        let span = (max - min) as u128 + 1;
+
+        min + ((self.next_u64() as u128 * span) >> 64) as usize
+    }
+}
+
+impl Default for Rng {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+/// Scrambles the bits of `value` (the splitmix64 finalizer), so that nearby
+/// inputs give unrelated outputs.
+#[inline(always)]
+pub fn mix_bits(value: u64) -> u64 {
+    let mut z = value;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// Uniform in `[0, 1)`, fixed by the bits of `values`. Stands in for a
+/// random number where no generator is at hand, such as during ray
+/// intersection.
+pub fn hash(values: &[f64]) -> f64 {
+    let mut hash: u64 = 0x9e3779b97f4a7c15;
+
+    for value in values {
+        hash = mix_bits(hash ^ value.to_bits()).wrapping_add(0x9e3779b97f4a7c15);
+    }
+
+    (hash >> 11) as f64 / (1u64 << 53) as f64
+}
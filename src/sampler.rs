@@ -0,0 +1,478 @@
+use crate::rng::{mix_bits, Rng};
+use crate::vec3::Vec3;
+use std::f64::consts::PI;
+
+/// Hands out the uniform numbers of one pixel sample, one dimension at a
+/// time. Every sample asks for its dimensions in the same order, so the
+/// same dimension always drives the same decision:
+///
+/// - 2D position in the pixel,
+/// - 2D position on the lens,
+/// - 1D time in the shutter interval,
+/// - per bounce, 1D light choice, 2D point on the light, 1D scattering
+///   choice and 2D scattering direction.
+pub trait Sampler {
+    /// Starts sample `index` of the pixel at column `x` and row `y`.
+    fn start_pixel_sample(&mut self, x: usize, y: usize, index: u32);
+
+    fn get_1d(&mut self) -> f64;
+
+    fn get_2d(&mut self) -> (f64, f64);
+}
+
+pub type WrappedSamplerType = Box<dyn Sampler + Send>;
+
+/// The dimensions a camera ray is made from.
+#[derive(Debug, Copy, Clone)]
+pub struct CameraSample {
+    /// Position in the pixel, from its lower left corner.
+    pub film: (f64, f64),
+    pub lens: (f64, f64),
+    /// Fraction of the shutter interval.
+    pub time: f64,
+}
+
+impl CameraSample {
+    pub fn get(sampler: &mut dyn Sampler) -> Self {
+        let film = sampler.get_2d();
+        let lens = sampler.get_2d();
+        let time = sampler.get_1d();
+
+        Self { film, lens, time }
+    }
+}
+
+/// The dimensions used at one hit along a path. They are all drawn up front,
+/// so the next bounce starts at the same dimension whichever of them the
+/// materials and lights at this hit use.
+#[derive(Debug, Copy, Clone)]
+pub struct BounceSample {
+    pub light: f64,
+    pub light_position: (f64, f64),
+    pub scatter: f64,
+    pub direction: (f64, f64),
+}
+
+impl BounceSample {
+    pub fn get(sampler: &mut dyn Sampler) -> Self {
+        let light = sampler.get_1d();
+        let light_position = sampler.get_2d();
+        let scatter = sampler.get_1d();
+        let direction = sampler.get_2d();
+
+        Self {
+            light,
+            light_position,
+            scatter,
+            direction,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SamplerType {
+    /// Independent uniform random numbers.
+    Independent,
+    /// One jittered sample per stratum of each dimension, with the strata
+    /// shuffled between dimensions.
+    Stratified,
+    /// Two dimensional Halton points per pair of dimensions, with the
+    /// digits randomly permuted.
+    Halton,
+    /// Two dimensional Sobol points per pair of dimensions, with Owen
+    /// scrambling.
+    Sobol,
+}
+
+impl SamplerType {
+    /// Names of the samplers in scene files and on the command line.
+    pub const NAMES: [(&'static str, SamplerType); 4] = [
+        ("independent", SamplerType::Independent),
+        ("stratified", SamplerType::Stratified),
+        ("halton", SamplerType::Halton),
+        ("sobol", SamplerType::Sobol),
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::NAMES
+            .iter()
+            .find(|(option, _)| *option == name)
+            .map(|(_, sampler)| *sampler)
+    }
+
+    pub fn name(&self) -> &'static str {
+        Self::NAMES
+            .iter()
+            .find(|(_, sampler)| sampler == self)
+            .map_or("independent", |(name, _)| name)
+    }
+
+    /// A sampler drawing `samples_per_pixel` samples in every pixel.
+    pub fn build(&self, seed: u64, samples_per_pixel: u32) -> WrappedSamplerType {
+        match self {
+            SamplerType::Independent => Box::new(IndependentSampler::new(seed)),
+            SamplerType::Stratified => Box::new(StratifiedSampler::new(seed, samples_per_pixel)),
+            SamplerType::Halton => Box::new(HaltonSampler::new(seed, samples_per_pixel)),
+            SamplerType::Sobol => Box::new(SobolSampler::new(seed, samples_per_pixel)),
+        }
+    }
+}
+
+/// Largest value below one, which uniform samples stay under.
+const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON / 2.0;
+
+/// Position of `value` between `low` and `high`, as a new uniform sample
+/// in `[0, 1)`. Lets a sample that made a discrete choice drive the next
+/// decision as well.
+#[inline(always)]
+pub fn remap(value: f64, low: f64, high: f64) -> f64 {
+    if high > low {
+        ((value - low) / (high - low)).clamp(0.0, ONE_MINUS_EPSILON)
+    } else {
+        0.0
+    }
+}
+
+/// Point in the unit disk (with z = 0), keeping the strata of `u` compact.
+pub fn concentric_disk(u: (f64, f64)) -> Vec3 {
+    let (a, b) = (2.0 * u.0 - 1.0, 2.0 * u.1 - 1.0);
+
+    if a == 0.0 && b == 0.0 {
+        return Vec3::zero();
+    }
+
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, PI / 4.0 * (b / a))
+    } else {
+        (b, PI / 2.0 - PI / 4.0 * (a / b))
+    };
+
+    Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
+}
+
+/// Direction on the unit sphere.
+pub fn uniform_sphere(u: (f64, f64)) -> Vec3 {
+    let z = 1.0 - 2.0 * u.0;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.1;
+
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+/// Point in the unit ball; `radius` is a third uniform sample.
+pub fn uniform_ball(u: (f64, f64), radius: f64) -> Vec3 {
+    radius.cbrt() * uniform_sphere(u)
+}
+
+/// Position of pixel `(x, y)` along a single index, for hashing.
+#[inline(always)]
+fn pixel_key(x: usize, y: usize) -> u64 {
+    ((y as u64) << 32) | x as u64
+}
+
+/// Hash of the seed, pixel and dimension, which decorrelates the dimensions
+/// of different pixels.
+#[inline(always)]
+fn dimension_hash(seed: u64, pixel: u64, dimension: u32) -> u64 {
+    mix_bits(mix_bits(seed ^ mix_bits(pixel)) ^ dimension as u64)
+}
+
+#[inline(always)]
+fn to_unit(bits: u32) -> f64 {
+    bits as f64 / (1u64 << 32) as f64
+}
+
+pub struct IndependentSampler {
+    seed: u64,
+    rng: Rng,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: Rng::new(seed),
+        }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, x: usize, y: usize, index: u32) {
+        self.rng = Rng::with_stream(mix_bits(self.seed ^ index as u64), pixel_key(x, y));
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        self.rng.random()
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.rng.random(), self.rng.random())
+    }
+}
+
+pub struct StratifiedSampler {
+    seed: u64,
+    /// Strata along each axis of the 2D dimensions; 1D dimensions use all
+    /// of them in a row.
+    strata: u32,
+    pixel: u64,
+    index: u32,
+    dimension: u32,
+    rng: Rng,
+}
+
+impl StratifiedSampler {
+    pub fn new(seed: u64, samples_per_pixel: u32) -> Self {
+        Self {
+            seed,
+            strata: ((samples_per_pixel.max(1) as f64).sqrt().ceil()) as u32,
+            pixel: 0,
+            index: 0,
+            dimension: 0,
+            rng: Rng::new(seed),
+        }
+    }
+
+    /// The stratum this sample takes among `count`, shuffled per dimension.
+    fn stratum(&mut self, count: u32) -> u32 {
+        let hash = dimension_hash(self.seed, self.pixel, self.dimension);
+        self.dimension += 1;
+
+        permutation_element(self.index % count, count, hash as u32)
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, x: usize, y: usize, index: u32) {
+        self.pixel = pixel_key(x, y);
+        self.index = index;
+        self.dimension = 0;
+        self.rng = Rng::with_stream(mix_bits(self.seed ^ index as u64), self.pixel);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let count = self.strata * self.strata;
+        let stratum = self.stratum(count);
+
+        (stratum as f64 + self.rng.random()) / count as f64
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let stratum = self.stratum(self.strata * self.strata);
+        let (x, y) = (stratum % self.strata, stratum / self.strata);
+
+        (
+            (x as f64 + self.rng.random()) / self.strata as f64,
+            (y as f64 + self.rng.random()) / self.strata as f64,
+        )
+    }
+}
+
+/// Every dimension (and pair of dimensions) takes the first two Halton
+/// dimensions, in bases two and three, with the sample order shuffled and
+/// the digits randomly permuted differently for each.
+pub struct HaltonSampler {
+    seed: u64,
+    samples_per_pixel: u32,
+    pixel: u64,
+    index: u32,
+    dimension: u32,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64, samples_per_pixel: u32) -> Self {
+        Self {
+            seed,
+            samples_per_pixel: samples_per_pixel.max(1),
+            pixel: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    /// Shuffled sample index and the scrambling seed of the next dimension.
+    fn next_dimension(&mut self) -> (u32, u64) {
+        let hash = dimension_hash(self.seed, self.pixel, self.dimension);
+        self.dimension += 1;
+
+        let index = permutation_element(
+            self.index % self.samples_per_pixel,
+            self.samples_per_pixel,
+            hash as u32,
+        ) + self.index / self.samples_per_pixel * self.samples_per_pixel;
+
+        (index, mix_bits(hash))
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, x: usize, y: usize, index: u32) {
+        self.pixel = pixel_key(x, y);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let (index, hash) = self.next_dimension();
+
+        scrambled_radical_inverse(2, index, hash)
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let (index, hash) = self.next_dimension();
+
+        (
+            scrambled_radical_inverse(2, index, hash),
+            scrambled_radical_inverse(3, index, mix_bits(hash)),
+        )
+    }
+}
+
+/// The digits of `index` in `base` mirrored around the radix point, with
+/// each digit position permuted differently depending on `seed`. The
+/// permutations also apply to the zeros past the last digit of `index`, up
+/// to the precision of the result.
+fn scrambled_radical_inverse(base: u32, mut index: u32, seed: u64) -> f64 {
+    let inverse_base = 1.0 / base as f64;
+    let mut scale = inverse_base;
+    let mut result = 0.0;
+    let mut position = 0;
+
+    while scale > f64::EPSILON {
+        let permutation = mix_bits(seed ^ position) as u32;
+        result += permutation_element(index % base, base, permutation) as f64 * scale;
+        index /= base;
+        scale *= inverse_base;
+        position += 1;
+    }
+
+    result.min(1.0 - f64::EPSILON)
+}
+
+/// Every dimension (and pair of dimensions) takes the first two Sobol
+/// dimensions, with the sample order shuffled and the points Owen
+/// scrambled differently for each. Best with a power of two samples per
+/// pixel.
+pub struct SobolSampler {
+    seed: u64,
+    samples_per_pixel: u32,
+    pixel: u64,
+    index: u32,
+    dimension: u32,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64, samples_per_pixel: u32) -> Self {
+        Self {
+            seed,
+            samples_per_pixel: samples_per_pixel.max(1),
+            pixel: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    /// Shuffled sample index and the scrambling seed of the next dimension.
+    fn next_dimension(&mut self) -> (u32, u64) {
+        let hash = dimension_hash(self.seed, self.pixel, self.dimension);
+        self.dimension += 1;
+
+        let index = permutation_element(
+            self.index % self.samples_per_pixel,
+            self.samples_per_pixel,
+            hash as u32,
+        ) + self.index / self.samples_per_pixel * self.samples_per_pixel;
+
+        (index, mix_bits(hash))
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, x: usize, y: usize, index: u32) {
+        self.pixel = pixel_key(x, y);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let (index, hash) = self.next_dimension();
+
+        to_unit(owen_scramble(index.reverse_bits(), hash as u32))
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let (index, hash) = self.next_dimension();
+
+        (
+            to_unit(owen_scramble(index.reverse_bits(), hash as u32)),
+            to_unit(owen_scramble(sobol_second(index), (hash >> 32) as u32)),
+        )
+    }
+}
+
+/// Second dimension of the Sobol sequence, whose generator matrix is
+/// Pascal's triangle modulo 2.
+fn sobol_second(mut index: u32) -> u32 {
+    let mut result = 0;
+    let mut column = 1u32 << 31;
+
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= column;
+        }
+        index >>= 1;
+        column ^= column >> 1;
+    }
+
+    result
+}
+
+/// Hash based nested uniform scrambling of the bits of a point in `[0, 1)`,
+/// after Laine and Karras: each bit is flipped depending only on the bits
+/// above it.
+fn owen_scramble(value: u32, seed: u32) -> u32 {
+    let mut v = value.reverse_bits();
+    v ^= v.wrapping_mul(0x3d20adea);
+    v = v.wrapping_add(seed);
+    v = v.wrapping_mul((seed >> 16) | 1);
+    v ^= v.wrapping_mul(0x05526c56);
+    v ^= v.wrapping_mul(0x53a22864);
+
+    v.reverse_bits()
+}
+
+/// Element `index` of a pseudo-random permutation of `0..count` chosen by
+/// `seed` (Kensler's hashed permutation).
+fn permutation_element(mut index: u32, count: u32, seed: u32) -> u32 {
+    let mut mask = count.saturating_sub(1);
+    mask |= mask >> 1;
+    mask |= mask >> 2;
+    mask |= mask >> 4;
+    mask |= mask >> 8;
+    mask |= mask >> 16;
+
+    loop {
+        index ^= seed;
+        index = index.wrapping_mul(0xe170893d);
+        index ^= seed >> 16;
+        index ^= (index & mask) >> 4;
+        index ^= seed >> 8;
+        index = index.wrapping_mul(0x0929eb3f);
+        index ^= seed >> 23;
+        index ^= (index & mask) >> 1;
+        index = index.wrapping_mul(1 | seed >> 27);
+        index = index.wrapping_mul(0x6935fa69);
+        index ^= (index & mask) >> 11;
+        index = index.wrapping_mul(0x74dcb303);
+        index ^= (index & mask) >> 2;
+        index = index.wrapping_mul(0x9e501cc3);
+        index ^= (index & mask) >> 2;
+        index = index.wrapping_mul(0xc860a3df);
+        index &= mask;
+        index ^= index >> 5;
+
+        if index < count {
+            return (index.wrapping_add(seed)) % count;
+        }
+    }
+}
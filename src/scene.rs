@@ -1,12 +1,78 @@
+use crate::light::{self, LightList};
+use crate::light_tree::LightSampler;
 use crate::material;
+use crate::math_traits::InnerProduct;
 use crate::noise;
 use crate::objects;
+use crate::rng::Rng;
+use crate::sky;
 use crate::texture;
-use crate::utils;
 use crate::vec3;
-use crate::WorldType;
+use crate::{WorldElementType, WorldType};
 use std::sync::Arc;
 
+/// Geometry together with the lights that are sampled explicitly. Rays
+/// leaving the scene see the environment, if any, and the render background
+/// otherwise.
+pub struct Scene {
+    pub world: WorldType,
+    pub lights: LightList,
+    pub environment: Option<Arc<light::EnvironmentLight>>,
+    /// Index of the environment in `lights`.
+    pub environment_light: Option<usize>,
+    /// Picks the light to sample at each hit; rebuilt whenever lights are
+    /// added.
+    pub light_sampler: LightSampler,
+}
+
+impl Scene {
+    pub fn new(world: WorldType) -> Self {
+        let lights = LightList::new();
+
+        Self {
+            world,
+            light_sampler: LightSampler::new(&lights),
+            lights,
+            environment: None,
+            environment_light: None,
+        }
+    }
+
+    /// Adds lights that are not part of the world geometry.
+    pub fn with_lights(mut self, lights: LightList) -> Self {
+        self.lights.extend(lights);
+        self.light_sampler = LightSampler::new(&self.lights);
+        self
+    }
+
+    /// Adds an emissive `object` to the world and samples it as a light.
+    pub fn with_area_light(mut self, object: WorldElementType) -> Self {
+        let index = self.lights.len();
+
+        self.world
+            .push(Arc::new(objects::Emitter::new(object.clone(), index)));
+        self.with_lights(vec![Arc::new(light::AreaLight::new(object))])
+    }
+
+    pub fn with_environment(mut self, environment: Arc<light::EnvironmentLight>) -> Self {
+        self.environment_light = Some(self.lights.len());
+        self.environment = Some(environment.clone());
+        self.with_lights(vec![environment])
+    }
+
+    /// The same lights around other geometry, such as the world put into a
+    /// BVH.
+    pub fn with_world(&self, world: WorldType) -> Self {
+        Self {
+            world,
+            lights: self.lights.clone(),
+            environment: self.environment.clone(),
+            environment_light: self.environment_light,
+            light_sampler: LightSampler::new(&self.lights),
+        }
+    }
+}
+
 pub fn simple_world() -> WorldType {
     let ground_mat = Arc::new(material::Lambertian::with_color(vec3::Color::new(
         0.8, 0.8, 0.0,
@@ -79,7 +145,7 @@ pub fn simple_world() -> WorldType {
     world
 }
 
-pub fn random_world() -> WorldType {
+pub fn random_world(rng: &mut Rng) -> WorldType {
     let mut world = WorldType::new();
 
     let ground_tx = Arc::new(texture::CheckerTexture::with_color(
@@ -98,24 +164,24 @@ pub fn random_world() -> WorldType {
 
     for a in -BOUND..BOUND {
         for b in -BOUND..BOUND {
-            let mat_choice = utils::random();
+            let mat_choice = rng.random();
 
             let center = vec3::Point3::new(
-                a as f64 + 0.9 * utils::random(),
+                a as f64 + 0.9 * rng.random(),
                 0.2,
-                b as f64 + 0.9 * utils::random(),
+                b as f64 + 0.9 * rng.random(),
             );
 
             if mat_choice < 0.8 {
-                let albedo = vec3::Color::random() * vec3::Color::random();
+                let albedo = vec3::Color::random(rng) * vec3::Color::random(rng);
                 let sphere_mat = Arc::new(material::Lambertian::with_color(albedo));
-                let center2 = center + vec3::Vec3::new(0.0, utils::random_range(0.0, 0.5), 0.0);
+                let center2 = center + vec3::Vec3::new(0.0, rng.random_range(0.0, 0.5), 0.0);
                 world.push(Arc::new(objects::MovingSphere::new(
                     center, center2, 0.0, 1.0, 0.2, sphere_mat,
                 )));
             } else if mat_choice < 0.95 {
-                let albedo = vec3::Color::random_from_range(0.5, 1.0);
-                let fuzz = utils::random_range(0.0, 0.5);
+                let albedo = vec3::Color::random_from_range(0.5, 1.0, rng);
+                let fuzz = rng.random_range(0.0, 0.5);
                 let sphere_mat = Arc::new(material::Metal::new(albedo, fuzz));
                 world.push(Arc::new(objects::Sphere::new(center, 0.2, sphere_mat)));
             } else {
@@ -146,7 +212,7 @@ pub fn random_world() -> WorldType {
         mat3,
     )));
     let mat4 = Arc::new(material::Lambertian::new(Arc::new(
-        noise::PerlinNoise::new(),
+        noise::NoiseTexture::perlin(4.0, 7, noise::NoisePhase::Marble { distortion: 10.0 }, rng),
     )));
     world.push(Arc::new(objects::Sphere::new(
         vec3::Point3::new(0.0, 1.0, 2.0),
@@ -164,7 +230,8 @@ pub fn random_world() -> WorldType {
     world
 }
 
-pub fn cornell_world() -> WorldType {
+/// The Cornell box, with its ceiling light sampled directly.
+pub fn cornell_scene() -> Scene {
     let mut world = WorldType::new();
 
     let red = Arc::new(material::Lambertian::with_color(vec3::Color::new(
@@ -176,9 +243,10 @@ pub fn cornell_world() -> WorldType {
     let green = Arc::new(material::Lambertian::with_color(vec3::Color::new(
         0.12, 0.45, 0.15,
     )));
-    let light = Arc::new(material::DiffuseLight::with_color(vec3::Color::new(
-        15.0, 15.0, 15.0,
-    )));
+    let light = Arc::new(
+        material::DiffuseLight::with_color(vec3::Color::new(15.0, 15.0, 15.0))
+            .with_two_sided(false),
+    );
 
     world.push(Arc::new(objects::YzPlane::new(
         0.0,
@@ -197,15 +265,6 @@ pub fn cornell_world() -> WorldType {
         red.clone(),
     )));
 
-    world.push(Arc::new(objects::XzPlane::new(
-        213.0,
-        343.0,
-        227.0,
-        332.0,
-        554.0,
-        light.clone(),
-    )));
-
     world.push(Arc::new(objects::XzPlane::new(
         0.0,
         555.0,
@@ -243,5 +302,111 @@ pub fn cornell_world() -> WorldType {
         white.clone(),
     )));
 
-    world
+    // the plane faces up, so flip it to light the room below
+    Scene::new(world).with_area_light(Arc::new(objects::FlipFace::new(Arc::new(
+        objects::XzPlane::new(213.0, 343.0, 227.0, 332.0, 554.0, light),
+    ))))
+}
+
+/// Diffuse spheres on a ground plane lit only by a point light, a spot light
+/// and a soft sun.
+pub fn lights_world() -> Scene {
+    let mut world: WorldType = WorldType::new();
+
+    let ground_mat = Arc::new(material::Lambertian::with_color(vec3::Color::new(
+        0.8, 0.8, 0.8,
+    )));
+    world.push(Arc::new(objects::Sphere::new(
+        vec3::Point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        ground_mat,
+    )));
+
+    for (x, color) in [
+        (-2.2, vec3::Color::new(0.8, 0.2, 0.2)),
+        (0.0, vec3::Color::new(0.2, 0.8, 0.2)),
+        (2.2, vec3::Color::new(0.2, 0.2, 0.8)),
+    ] {
+        world.push(Arc::new(objects::Sphere::new(
+            vec3::Point3::new(x, 1.0, 0.0),
+            1.0,
+            Arc::new(material::Lambertian::with_color(color)),
+        )));
+    }
+
+    let lights: LightList = vec![
+        Arc::new(light::PointLight::new(
+            vec3::Point3::new(-3.0, 4.0, 3.0),
+            vec3::Color::new(1.0, 0.9, 0.8),
+            30.0,
+        )),
+        Arc::new(light::SpotLight::new(
+            vec3::Point3::new(2.2, 6.0, 1.0),
+            vec3::Vec3::new(0.0, -6.0, -1.0),
+            vec3::Color::new(0.8, 0.9, 1.0),
+            60.0,
+            15.0,
+            25.0,
+        )),
+        Arc::new(light::DirectionalLight::new(
+            vec3::Vec3::new(1.0, 2.0, 1.0),
+            vec3::Color::new(1.0, 0.95, 0.9),
+            1.0,
+            2.0,
+        )),
+    ];
+
+    Scene::new(world).with_lights(lights)
+}
+
+/// `random_world` outdoors under a clear afternoon sky.
+pub fn sky_world(rng: &mut Rng) -> Scene {
+    let sky = sky::Sky::new(35.0, 60.0, 3.0);
+
+    Scene::new(random_world(rng))
+        .with_lights(vec![Arc::new(sky.sun())])
+        .with_environment(Arc::new(sky.environment(512)))
+}
+
+/// A grey room lit only by hundreds of small coloured lamps, which the light
+/// tree has to pick from at every hit.
+pub fn many_lights_scene(rng: &mut Rng) -> Scene {
+    let mut world: WorldType = WorldType::new();
+
+    let ground_mat = Arc::new(material::Lambertian::with_color(vec3::Color::new(
+        0.6, 0.6, 0.6,
+    )));
+    world.push(Arc::new(objects::Sphere::new(
+        vec3::Point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        ground_mat.clone(),
+    )));
+    world.push(Arc::new(objects::Sphere::new(
+        vec3::Point3::new(0.0, 1.0, 0.0),
+        1.0,
+        ground_mat,
+    )));
+
+    let mut scene = Scene::new(world);
+
+    const BOUND: isize = 12;
+
+    for a in -BOUND..BOUND {
+        for b in -BOUND..BOUND {
+            let center = vec3::Point3::new(
+                a as f64 + 0.9 * rng.random(),
+                rng.random_range(0.1, 3.0),
+                b as f64 + 0.9 * rng.random(),
+            );
+            if (center - vec3::Point3::new(0.0, 1.0, 0.0)).length() < 1.3 {
+                continue;
+            }
+
+            let color = vec3::Color::random_from_range(0.2, 1.0, rng);
+            let lamp_mat = Arc::new(material::DiffuseLight::with_color(20.0 * color));
+            scene = scene.with_area_light(Arc::new(objects::Sphere::new(center, 0.08, lamp_mat)));
+        }
+    }
+
+    scene
 }
@@ -0,0 +1,530 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Where something was written in a scene file, with lines and columns
+/// counted from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub file: Arc<PathBuf>,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file.display(), self.line, self.column)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Severity {
+    /// The scene cannot be built as written.
+    Error,
+    /// The scene builds, but probably not into what was meant.
+    Warning,
+}
+
+/// A problem found in a scene file and where it was written.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub location: Location,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn error<S: Into<String>>(location: &Location, message: S) -> Self {
+        Self {
+            severity: Severity::Error,
+            location: location.clone(),
+            message: message.into(),
+        }
+    }
+
+    pub fn warning<S: Into<String>>(location: &Location, message: S) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::error(location, message)
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+
+        write!(f, "{}: {}: {}", self.location, severity, self.message)
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Number(f64),
+    Bool(bool),
+    String(String),
+    /// Reference to a texture or material defined earlier.
+    Name(String),
+    List(Vec<Value>),
+}
+
+impl Value {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "a number",
+            Value::Bool(_) => "a boolean",
+            Value::String(_) => "a string",
+            Value::Name(_) => "a name",
+            Value::List(_) => "a list",
+        }
+    }
+}
+
+/// Writes values back in the syntax they are read from. Numbers keep every
+/// digit needed to read them back exactly.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number(number) => write!(f, "{}", number),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::String(string) => {
+                f.write_str("\"")?;
+                for c in string.chars() {
+                    match c {
+                        '"' => f.write_str("\\\"")?,
+                        '\\' => f.write_str("\\\\")?,
+                        '\n' => f.write_str("\\n")?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                f.write_str("\"")
+            }
+            Value::Name(name) => f.write_str(name),
+            Value::List(items) => {
+                f.write_str("[")?;
+                for (index, item) in items.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                f.write_str("]")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Field {
+    pub key: String,
+    pub value: Value,
+    pub location: Location,
+    /// Location of the value, for errors about what was written.
+    pub value_location: Location,
+}
+
+/// One statement of a scene file:
+///
+/// ```text
+/// kind [type] [name] {
+///     key = value
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub kind: String,
+    pub type_name: Option<String>,
+    pub name: Option<String>,
+    pub fields: Vec<Field>,
+    pub location: Location,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(f64),
+    String(String),
+    OpenBrace,
+    CloseBrace,
+    OpenBracket,
+    CloseBracket,
+    Equals,
+    Comma,
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Ident(ident) => format!("`{}`", ident),
+            Token::Number(number) => format!("number {}", number),
+            Token::String(string) => format!("string {:?}", string),
+            Token::OpenBrace => String::from("`{`"),
+            Token::CloseBrace => String::from("`}`"),
+            Token::OpenBracket => String::from("`[`"),
+            Token::CloseBracket => String::from("`]`"),
+            Token::Equals => String::from("`=`"),
+            Token::Comma => String::from("`,`"),
+        }
+    }
+}
+
+fn tokenize(text: &str, file: &Arc<PathBuf>) -> Result<Vec<(Token, Location)>, Diagnostic> {
+    let mut tokens = vec![];
+    let mut chars = text.chars().peekable();
+    let (mut line, mut column) = (1, 1);
+
+    while let Some(&c) = chars.peek() {
+        let location = Location {
+            file: file.clone(),
+            line,
+            column,
+        };
+        let mut advance = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+            let c = chars.next();
+            if c == Some('\n') {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+            c
+        };
+
+        let token = match c {
+            _ if c.is_whitespace() => {
+                advance(&mut chars);
+                continue;
+            }
+            '#' => {
+                while chars.peek().is_some_and(|c| *c != '\n') {
+                    advance(&mut chars);
+                }
+                continue;
+            }
+            '{' | '}' | '[' | ']' | '=' | ',' => {
+                advance(&mut chars);
+                match c {
+                    '{' => Token::OpenBrace,
+                    '}' => Token::CloseBrace,
+                    '[' => Token::OpenBracket,
+                    ']' => Token::CloseBracket,
+                    '=' => Token::Equals,
+                    _ => Token::Comma,
+                }
+            }
+            '"' => {
+                advance(&mut chars);
+                let mut string = String::new();
+
+                loop {
+                    match advance(&mut chars) {
+                        None | Some('\n') => {
+                            return Err(Diagnostic::error(&location, "unterminated string"))
+                        }
+                        Some('"') => break,
+                        Some('\\') => match advance(&mut chars) {
+                            Some(c @ ('"' | '\\')) => string.push(c),
+                            Some('n') => string.push('\n'),
+                            _ => {
+                                return Err(Diagnostic::error(
+                                    &location,
+                                    "invalid escape in string",
+                                ))
+                            }
+                        },
+                        Some(c) => string.push(c),
+                    }
+                }
+
+                Token::String(string)
+            }
+            _ if c.is_ascii_digit() || c == '-' || c == '+' || c == '.' => {
+                let mut number = String::new();
+
+                while let Some(&c) = chars.peek() {
+                    let exponent_sign = (c == '-' || c == '+') && number.ends_with(['e', 'E']);
+                    if c.is_ascii_alphanumeric() || c == '.' || exponent_sign || number.is_empty() {
+                        number.push(c);
+                        advance(&mut chars);
+                    } else {
+                        break;
+                    }
+                }
+
+                match number.parse() {
+                    Ok(number) => Token::Number(number),
+                    Err(_) => {
+                        return Err(Diagnostic::error(
+                            &location,
+                            format!("invalid number `{}`", number),
+                        ))
+                    }
+                }
+            }
+            _ if c.is_alphabetic() || c == '_' => {
+                let mut ident = String::new();
+
+                while let Some(&c) = chars.peek() {
+                    if c.is_alphanumeric() || c == '_' {
+                        ident.push(c);
+                        advance(&mut chars);
+                    } else {
+                        break;
+                    }
+                }
+
+                Token::Ident(ident)
+            }
+            _ => return Err(Diagnostic::error(&location, format!("unexpected `{}`", c))),
+        };
+
+        tokens.push((token, location));
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(Token, Location)>,
+    position: usize,
+    /// Location just past the end of the file.
+    end: Location,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&(Token, Location)> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self, expected: &str) -> Result<(Token, Location), Diagnostic> {
+        match self.tokens.get(self.position) {
+            Some(token) => {
+                self.position += 1;
+                Ok(token.clone())
+            }
+            None => Err(Diagnostic::error(
+                &self.end,
+                format!("expected {}, found the end of the file", expected),
+            )),
+        }
+    }
+
+    fn expect(&mut self, token: Token) -> Result<Location, Diagnostic> {
+        let expected = token.describe();
+        let (found, location) = self.next(&expected)?;
+
+        if found == token {
+            Ok(location)
+        } else {
+            Err(Diagnostic::error(
+                &location,
+                format!("expected {}, found {}", expected, found.describe()),
+            ))
+        }
+    }
+
+    fn statement(&mut self) -> Result<Statement, Diagnostic> {
+        let (kind, location) = match self.next("a statement")? {
+            (Token::Ident(kind), location) => (kind, location),
+            (token, location) => {
+                return Err(Diagnostic::error(
+                    &location,
+                    format!("expected a statement, found {}", token.describe()),
+                ))
+            }
+        };
+
+        if kind == "include" {
+            return match self.next("a file name")? {
+                (Token::String(path), _) => Ok(Statement::Include(path, location)),
+                (token, location) => Err(Diagnostic::error(
+                    &location,
+                    format!("expected a file name, found {}", token.describe()),
+                )),
+            };
+        }
+
+        let mut idents = vec![];
+        while let Some((Token::Ident(ident), _)) = self.peek() {
+            idents.push(ident.clone());
+            self.position += 1;
+        }
+        if idents.len() > 2 {
+            return Err(Diagnostic::error(
+                &location,
+                format!("too many names before `{{` in `{}`", kind),
+            ));
+        }
+
+        self.expect(Token::OpenBrace)?;
+
+        let mut fields = vec![];
+        loop {
+            let (key, field_location) = match self.next("a field or `}`")? {
+                (Token::CloseBrace, _) => break,
+                (Token::Ident(key), location) => (key, location),
+                (token, location) => {
+                    return Err(Diagnostic::error(
+                        &location,
+                        format!("expected a field or `}}`, found {}", token.describe()),
+                    ))
+                }
+            };
+
+            self.expect(Token::Equals)?;
+            let (value, value_location) = self.value()?;
+
+            fields.push(Field {
+                key,
+                value,
+                location: field_location,
+                value_location,
+            });
+        }
+
+        let mut idents = idents.into_iter();
+        Ok(Statement::Block(Block {
+            kind,
+            type_name: idents.next(),
+            name: idents.next(),
+            fields,
+            location,
+        }))
+    }
+
+    fn value(&mut self) -> Result<(Value, Location), Diagnostic> {
+        let (token, location) = self.next("a value")?;
+
+        let value = match token {
+            Token::Number(number) => Value::Number(number),
+            Token::String(string) => Value::String(string),
+            Token::Ident(ident) => match ident.as_str() {
+                "true" => Value::Bool(true),
+                "false" => Value::Bool(false),
+                // kept so that validation can point at them
+                "nan" => Value::Number(f64::NAN),
+                "inf" => Value::Number(f64::INFINITY),
+                _ => Value::Name(ident),
+            },
+            Token::OpenBracket => {
+                let mut items = vec![];
+
+                loop {
+                    if let Some((Token::CloseBracket, _)) = self.peek() {
+                        self.position += 1;
+                        break;
+                    }
+
+                    items.push(self.value()?.0);
+
+                    match self.next("`,` or `]`")? {
+                        (Token::Comma, _) => {}
+                        (Token::CloseBracket, _) => break,
+                        (token, location) => {
+                            return Err(Diagnostic::error(
+                                &location,
+                                format!("expected `,` or `]`, found {}", token.describe()),
+                            ))
+                        }
+                    }
+                }
+
+                Value::List(items)
+            }
+            token => {
+                return Err(Diagnostic::error(
+                    &location,
+                    format!("expected a value, found {}", token.describe()),
+                ))
+            }
+        };
+
+        Ok((value, location))
+    }
+}
+
+enum Statement {
+    Block(Block),
+    Include(String, Location),
+}
+
+/// Reads the blocks of a scene file, with every `include "path"` replaced
+/// by the blocks of that file. Included paths are relative to the file that
+/// includes them.
+pub fn parse_file<P: AsRef<Path>>(path: P) -> Result<Vec<Block>, Diagnostic> {
+    let mut blocks = vec![];
+    let location = Location {
+        file: Arc::new(path.as_ref().to_path_buf()),
+        line: 1,
+        column: 1,
+    };
+
+    read_file(path.as_ref(), &location, &mut vec![], &mut blocks)?;
+    Ok(blocks)
+}
+
+fn read_file(
+    path: &Path,
+    included_at: &Location,
+    stack: &mut Vec<PathBuf>,
+    blocks: &mut Vec<Block>,
+) -> Result<(), Diagnostic> {
+    let text = fs::read_to_string(path).map_err(|error| {
+        Diagnostic::error(
+            included_at,
+            format!("cannot read {}: {}", path.display(), error),
+        )
+    })?;
+
+    let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    if stack.contains(&canonical) {
+        return Err(Diagnostic::error(
+            included_at,
+            format!("{} includes itself", path.display()),
+        ));
+    }
+    stack.push(canonical);
+
+    for statement in parse(&text, path)? {
+        match statement {
+            Statement::Block(block) => blocks.push(block),
+            Statement::Include(include, location) => {
+                let included = path.parent().unwrap_or(Path::new("")).join(include);
+                read_file(&included, &location, stack, blocks)?;
+            }
+        }
+    }
+
+    stack.pop();
+    Ok(())
+}
+
+fn parse(text: &str, path: &Path) -> Result<Vec<Statement>, Diagnostic> {
+    let file = Arc::new(path.to_path_buf());
+    let tokens = tokenize(text, &file)?;
+    let lines = text.lines().count().max(1);
+    let end = Location {
+        file,
+        line: lines,
+        column: text.lines().last().map_or(0, |line| line.chars().count()) + 1,
+    };
+
+    let mut parser = Parser {
+        tokens,
+        position: 0,
+        end,
+    };
+    let mut statements = vec![];
+
+    while parser.peek().is_some() {
+        statements.push(parser.statement()?);
+    }
+
+    Ok(statements)
+}
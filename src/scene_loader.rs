@@ -0,0 +1,1712 @@
+use crate::animation::{
+    AnimatedTransform, CameraShake, Interpolation, Quaternion, Track, Transform,
+};
+use crate::camera::{CameraSettings, FisheyeProjection, Projection};
+use crate::film::{Filter, FilterType};
+use crate::ies::IesProfile;
+use crate::image::Image;
+use crate::lens::{ApertureMask, ApertureShape, BrownConrady, PhysicalLens};
+use crate::light::{self, EnvironmentLight, LightList};
+use crate::material::{self, NormalSource};
+use crate::math_traits::{CrossProduct, InnerProduct};
+use crate::noise::{self, DistanceMetric, NoisePhase, WorleyOutput};
+use crate::objects::{self, AlphaMode, MaterialArc};
+use crate::pattern;
+use crate::render::{AdaptiveSettings, RenderSettings};
+use crate::rng::Rng;
+use crate::sampler::SamplerType;
+use crate::scene::Scene;
+use crate::scene_file::{self, Block, Diagnostic, Field, Location, Severity, Value};
+use crate::sky::Sky;
+use crate::texture::{self, FilterMode, SolidTexture, WrapMode, WrappedTextureType};
+use crate::texture_graph::{self, RampInterpolation};
+use crate::vec3::{Color, Point3, Vec3};
+use crate::{WorldElementType, WorldType};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Everything a scene file describes.
+pub struct SceneDescription {
+    pub scene: Scene,
+    pub settings: RenderSettings,
+    pub camera: CameraSettings,
+}
+
+impl SceneDescription {
+    pub fn aspect_ratio(&self) -> f64 {
+        self.settings.width as f64 / self.settings.height as f64
+    }
+}
+
+/// Reads a scene file and builds the scene it describes, together with
+/// warnings about parts that look unintended. Textures and materials are
+/// named and have to be defined before they are used; files pulled in with
+/// `include` share the same names.
+///
+/// Loading carries on past errors so that all of them are reported at once,
+/// but any error means no scene is built.
+pub fn load<P: AsRef<Path>>(
+    path: P,
+) -> Result<(SceneDescription, Vec<Diagnostic>), Vec<Diagnostic>> {
+    let blocks = scene_file::parse_file(&path).map_err(|error| vec![error])?;
+    let start = Location {
+        file: Arc::new(path.as_ref().to_path_buf()),
+        line: 1,
+        column: 1,
+    };
+
+    let mut loader = Loader::default();
+    for block in &blocks {
+        loader.block(block);
+    }
+
+    // the checks of the whole scene would mostly repeat the errors of the
+    // blocks that failed, so they wait until every block has loaded
+    let failed = |diagnostics: &[Diagnostic]| {
+        diagnostics
+            .iter()
+            .any(|diagnostic| diagnostic.severity == Severity::Error)
+    };
+    if failed(&loader.diagnostics) {
+        return Err(loader.diagnostics);
+    }
+
+    loader.check_unused();
+    loader.check_lighting(&start);
+
+    let mut diagnostics = loader.diagnostics;
+    let camera = match loader.camera {
+        Some(camera) => camera,
+        None => {
+            diagnostics.push(Diagnostic::error(&start, "the scene has no camera"));
+            return Err(diagnostics);
+        }
+    };
+
+    let mut scene = Scene::new(loader.world).with_lights(loader.lights);
+    for object in loader.area_lights {
+        scene = scene.with_area_light(object);
+    }
+    if let Some(environment) = loader.environment {
+        scene = scene.with_environment(environment);
+    }
+
+    let description = SceneDescription {
+        scene,
+        settings: loader.settings.unwrap_or_else(default_settings),
+        camera,
+    };
+    Ok((description, diagnostics))
+}
+
+/// Warnings for the definitions that nothing refers to, in file order.
+fn unused<T>(kind: &str, names: &HashMap<String, Definition<T>>) -> Vec<Diagnostic> {
+    let mut unused: Vec<Diagnostic> = names
+        .iter()
+        .filter(|(_, definition)| !definition.used.get())
+        .map(|(name, definition)| {
+            Diagnostic::warning(
+                &definition.location,
+                format!("{} `{}` is never used", kind, name),
+            )
+        })
+        .collect();
+    unused.sort_by_key(|diagnostic| {
+        (
+            diagnostic.location.file.clone(),
+            diagnostic.location.line,
+            diagnostic.location.column,
+        )
+    });
+    unused
+}
+
+/// Largest render seed a scene file holds exactly: its numbers are doubles,
+/// whose whole numbers have gaps above it.
+pub const MAX_SEED: u64 = (1 << 53) - 1;
+
+/// A pinhole camera focused this many times further than the point it looks
+/// at probably lacks the aperture that was meant to go with the focus.
+const FOCUS_WARNING_RATIO: f64 = 10.0;
+
+fn default_settings() -> RenderSettings {
+    RenderSettings {
+        width: 600,
+        height: 600,
+        samples_per_pixel: 50,
+        max_depth: 50,
+        background: Color::zero(),
+        seed: 0,
+        sampler: SamplerType::Independent,
+        filter: Filter::default(),
+        adaptive: None,
+    }
+}
+
+/// A named texture or material.
+struct Definition<T> {
+    value: T,
+    type_name: String,
+    location: Location,
+    used: Cell<bool>,
+}
+
+impl<T> Definition<T> {
+    /// Placeholder for a definition that failed to load, counted as used.
+    fn stand_in(value: T, type_name: String, location: Location) -> Self {
+        Self {
+            value,
+            type_name,
+            location,
+            used: Cell::new(true),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Loader {
+    textures: HashMap<String, Definition<WrappedTextureType>>,
+    materials: HashMap<String, Definition<MaterialArc>>,
+    settings: Option<RenderSettings>,
+    camera: Option<CameraSettings>,
+    world: WorldType,
+    lights: LightList,
+    /// Emissive objects that are sampled as lights.
+    area_lights: WorldType,
+    environment: Option<Arc<EnvironmentLight>>,
+    /// Seeds the noise of textures that do not give their own, so loading a
+    /// file twice gives the same scene.
+    rng: RefCell<Rng>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Loader {
+    fn block(&mut self, block: &Block) {
+        let mut fields = Fields::new(block);
+
+        let result = self.statement(&mut fields).and_then(|_| fields.finish());
+        self.diagnostics.append(&mut fields.warnings);
+
+        if let Err(error) = result {
+            self.diagnostics.push(error);
+            self.stand_in(block);
+        }
+    }
+
+    fn statement(&mut self, fields: &mut Fields) -> Result<(), Diagnostic> {
+        let block = fields.block;
+
+        match block.kind.as_str() {
+            "settings" => {
+                if block.type_name.is_some() {
+                    return Err(Diagnostic::error(&block.location, "settings have no type"));
+                }
+                if self.settings.is_some() {
+                    return Err(Diagnostic::error(
+                        &block.location,
+                        "settings are given twice",
+                    ));
+                }
+
+                self.settings = Some(settings(fields)?);
+            }
+            "camera" => {
+                if self.camera.is_some() {
+                    return Err(Diagnostic::error(
+                        &block.location,
+                        "the camera is given twice",
+                    ));
+                }
+
+                let mut camera = camera(fields)?;
+                self.camera_motion(fields, &mut camera)?;
+                self.camera = Some(camera);
+            }
+            "texture" => {
+                let texture = self.texture(fields)?;
+                define(&mut self.textures, "texture", block, texture)?;
+            }
+            "material" => {
+                let material = self.material(fields)?;
+                define(&mut self.materials, "material", block, material)?;
+            }
+            "object" => self.object(fields)?,
+            "light" => self.light(fields)?,
+            kind => {
+                return Err(Diagnostic::error(
+                    &block.location,
+                    format!("unknown statement `{}`", kind),
+                ))
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Defines the name of a texture or material that failed to load, so
+    /// that its uses are not reported as well.
+    fn stand_in(&mut self, block: &Block) {
+        let name = match &block.name {
+            Some(name) => name.clone(),
+            None => return,
+        };
+        let type_name = block.type_name.clone().unwrap_or_default();
+        let location = block.location.clone();
+
+        match block.kind.as_str() {
+            "texture" => {
+                let value: WrappedTextureType = Arc::new(SolidTexture::new(Color::zero()));
+                self.textures
+                    .entry(name)
+                    .or_insert_with(|| Definition::stand_in(value, type_name, location));
+            }
+            "material" => {
+                let value: MaterialArc = Arc::new(material::Lambertian::with_color(Color::zero()));
+                self.materials
+                    .entry(name)
+                    .or_insert_with(|| Definition::stand_in(value, type_name, location));
+            }
+            _ => {}
+        }
+    }
+
+    fn texture_value(&self, field: &Field) -> Result<WrappedTextureType, Diagnostic> {
+        match &field.value {
+            Value::Name(name) => match self.textures.get(name) {
+                Some(definition) => {
+                    definition.used.set(true);
+                    Ok(definition.value.clone())
+                }
+                None => Err(Diagnostic::error(
+                    &field.value_location,
+                    format!("unknown texture `{}`", name),
+                )),
+            },
+            Value::Number(_) => {
+                let value = as_number(field)?;
+                Ok(Arc::new(SolidTexture::new(Color::new(value, value, value))))
+            }
+            _ => Ok(Arc::new(SolidTexture::new(as_vector(field)?))),
+        }
+    }
+
+    fn material_value(&self, field: &Field) -> Result<MaterialArc, Diagnostic> {
+        Ok(self.material_definition(field)?.value.clone())
+    }
+
+    fn material_definition(&self, field: &Field) -> Result<&Definition<MaterialArc>, Diagnostic> {
+        match &field.value {
+            Value::Name(name) => match self.materials.get(name) {
+                Some(definition) => {
+                    definition.used.set(true);
+                    Ok(definition)
+                }
+                None => Err(Diagnostic::error(
+                    &field.value_location,
+                    format!("unknown material `{}`", name),
+                )),
+            },
+            value => Err(Diagnostic::error(
+                &field.value_location,
+                format!("expected a material name, found {}", value.describe()),
+            )),
+        }
+    }
+
+    /// Type of the material of an object, if it is known.
+    fn material_type(&self, fields: &mut Fields) -> Option<&str> {
+        let field = fields.get("material").ok()??;
+
+        self.material_definition(field)
+            .ok()
+            .map(|definition| definition.type_name.as_str())
+    }
+
+    fn check_unused(&mut self) {
+        let textures = unused("texture", &self.textures);
+        self.diagnostics.extend(textures);
+        let materials = unused("material", &self.materials);
+        self.diagnostics.extend(materials);
+    }
+
+    /// Warns about scenes that would render black: nothing emits light and
+    /// the background is black.
+    fn check_lighting(&mut self, start: &Location) {
+        let background = self
+            .settings
+            .as_ref()
+            .map_or(Color::zero(), |settings| settings.background);
+        let emissive = self
+            .materials
+            .values()
+            .any(|definition| definition.type_name == "diffuse_light" && definition.used.get());
+
+        if self.lights.is_empty()
+            && self.area_lights.is_empty()
+            && self.environment.is_none()
+            && !emissive
+            && background.near_zero()
+        {
+            self.diagnostics.push(Diagnostic::warning(
+                start,
+                "nothing in the scene emits light and the background is black",
+            ));
+        }
+    }
+
+    fn texture(&self, fields: &mut Fields) -> Result<WrappedTextureType, Diagnostic> {
+        let texture = |field: &Field| self.texture_value(field);
+
+        let texture: WrappedTextureType = match fields.type_name()? {
+            "solid" => Arc::new(SolidTexture::new(fields.required("color", as_vector)?)),
+            "checker" => Arc::new(texture::CheckerTexture::new(
+                fields.required("odd", texture)?,
+                fields.required("even", texture)?,
+            )),
+            "image" => {
+                let path = fields.required("path", as_path)?;
+                let wrap = fields.choice(
+                    "wrap",
+                    &[
+                        ("repeat", WrapMode::Repeat),
+                        ("clamp", WrapMode::Clamp),
+                        ("mirror", WrapMode::Mirror),
+                    ],
+                    WrapMode::Repeat,
+                )?;
+                let filter = fields.choice(
+                    "filter",
+                    &[
+                        ("nearest", FilterMode::Nearest),
+                        ("bilinear", FilterMode::Bilinear),
+                    ],
+                    FilterMode::Bilinear,
+                )?;
+                let srgb = fields.optional("srgb", as_bool)?.unwrap_or(true);
+
+                Arc::new(
+                    texture::ImageTexture::load(&path, wrap, filter, srgb)
+                        .map_err(|error| fields.cannot_read("path", &path, error))?,
+                )
+            }
+            "noise" => {
+                let distortion = fields.number("distortion", 10.0)?;
+                let phase = fields.choice(
+                    "phase",
+                    &[
+                        ("plain", NoisePhase::Plain),
+                        ("turbulence", NoisePhase::Turbulence),
+                        ("marble", NoisePhase::Marble { distortion }),
+                        ("wood", NoisePhase::Wood { distortion }),
+                    ],
+                    NoisePhase::Plain,
+                )?;
+                let scale = fields.number("scale", 1.0)?;
+                let octaves = fields.optional("octaves", as_count)?.unwrap_or(7);
+
+                let seed = self.seed(fields)?;
+                let noise: noise::WrappedNoiseType = match fields.choice(
+                    "noise",
+                    &[("perlin", "perlin"), ("worley", "worley")],
+                    "perlin",
+                )? {
+                    "worley" => Arc::new(
+                        noise::WorleyNoise::with_seed(
+                            fields.choice(
+                                "metric",
+                                &[
+                                    ("euclidean", DistanceMetric::Euclidean),
+                                    ("manhattan", DistanceMetric::Manhattan),
+                                    ("chebyshev", DistanceMetric::Chebyshev),
+                                ],
+                                DistanceMetric::Euclidean,
+                            )?,
+                            fields.choice(
+                                "output",
+                                &[
+                                    ("f1", WorleyOutput::F1),
+                                    ("f2", WorleyOutput::F2),
+                                    ("f2_minus_f1", WorleyOutput::F2MinusF1),
+                                ],
+                                WorleyOutput::F1,
+                            )?,
+                            seed,
+                        )
+                        .with_jitter(fields.number("jitter", 1.0)?),
+                    ),
+                    _ => Arc::new(noise::PerlinNoise::with_seed(seed)),
+                };
+
+                Arc::new(noise::NoiseTexture::new(
+                    noise,
+                    scale,
+                    octaves as u32,
+                    phase,
+                ))
+            }
+            "brick" => Arc::new(
+                pattern::BrickTexture::new(
+                    fields.required("brick", texture)?,
+                    fields.required("mortar", texture)?,
+                    fields.required("brick_width", as_number)?,
+                    fields.required("brick_height", as_number)?,
+                    fields.required("mortar_width", as_number)?,
+                )
+                .with_offset(fields.number("offset", 0.5)?),
+            ),
+            "tile" => Arc::new(pattern::TileTexture::new(
+                fields.required("tile", texture)?,
+                fields.required("grout", texture)?,
+                fields.number("scale", 1.0)?,
+                fields.required("grout_width", as_number)?,
+                fields.number("variation", 0.0)?,
+                self.seed(fields)?,
+            )),
+            "stripe" => Arc::new(pattern::StripeTexture::new(
+                fields.required("first", texture)?,
+                fields.required("second", texture)?,
+                fields.required("direction", as_vector)?,
+                fields.number("frequency", 1.0)?,
+                fields.number("duty", 0.5)?,
+            )),
+            "uv_checker" => Arc::new(pattern::UvCheckerTexture::new(
+                fields.required("odd", texture)?,
+                fields.required("even", texture)?,
+                fields.required("columns", as_number)?,
+                fields.required("rows", as_number)?,
+            )),
+            "radial_gradient" => Arc::new(pattern::RadialGradientTexture::new(
+                fields.required("inner", texture)?,
+                fields.required("outer", texture)?,
+                fields.number("center_u", 0.5)?,
+                fields.number("center_v", 0.5)?,
+                fields.number("radius", 0.5)?,
+            )),
+            "mix" => Arc::new(texture_graph::MixTexture::new(
+                fields.required("first", texture)?,
+                fields.required("second", texture)?,
+                fields.required("factor", texture)?,
+            )),
+            "multiply" => Arc::new(texture_graph::MultiplyTexture::new(
+                fields.required("first", texture)?,
+                fields.required("second", texture)?,
+            )),
+            "add" => Arc::new(texture_graph::AddTexture::new(
+                fields.required("first", texture)?,
+                fields.required("second", texture)?,
+            )),
+            "invert" => Arc::new(texture_graph::InvertTexture::new(
+                fields.required("input", texture)?,
+            )),
+            "color_ramp" => Arc::new(texture_graph::ColorRampTexture::new(
+                fields.required("input", texture)?,
+                fields.required("stops", as_stops)?,
+                fields.choice(
+                    "interpolation",
+                    &[
+                        ("constant", RampInterpolation::Constant),
+                        ("linear", RampInterpolation::Linear),
+                        ("smoothstep", RampInterpolation::Smoothstep),
+                    ],
+                    RampInterpolation::Linear,
+                )?,
+            )),
+            "remap" => Arc::new(texture_graph::RemapTexture::new(
+                fields.required("input", texture)?,
+                fields.number("from_min", 0.0)?,
+                fields.number("from_max", 1.0)?,
+                fields.number("to_min", 0.0)?,
+                fields.number("to_max", 1.0)?,
+                fields.optional("clamp", as_bool)?.unwrap_or(true),
+            )),
+            "hsv_adjust" => Arc::new(texture_graph::HsvAdjustTexture::new(
+                fields.required("input", texture)?,
+                fields.number("hue_shift", 0.0)?,
+                fields.number("saturation", 1.0)?,
+                fields.number("value", 1.0)?,
+            )),
+            "uv_transform" => Arc::new(texture_graph::UvTransformTexture::new(
+                fields.required("input", texture)?,
+                fields.number("offset_u", 0.0)?,
+                fields.number("offset_v", 0.0)?,
+                fields.number("scale_u", 1.0)?,
+                fields.number("scale_v", 1.0)?,
+                fields.number("rotation", 0.0)?,
+            )),
+            _ => return Err(fields.unknown_type("texture")),
+        };
+
+        Ok(texture)
+    }
+
+    /// The `seed` of a noisy texture, or the next one of the loader.
+    fn seed(&self, fields: &mut Fields) -> Result<u32, Diagnostic> {
+        match fields.optional("seed", as_count)? {
+            Some(seed) if seed <= u32::MAX as usize => Ok(seed as u32),
+            Some(_) => Err(Diagnostic::error(
+                fields.value_location("seed"),
+                format!("`seed` should be at most {}", u32::MAX),
+            )),
+            None => Ok(self.rng.borrow_mut().next_u32()),
+        }
+    }
+
+    fn material(&self, fields: &mut Fields) -> Result<MaterialArc, Diagnostic> {
+        let texture = |field: &Field| self.texture_value(field);
+        let material = |field: &Field| self.material_value(field);
+
+        let material: MaterialArc = match fields.type_name()? {
+            "lambertian" => Arc::new(material::Lambertian::new(
+                fields.required("albedo", texture)?,
+            )),
+            "metal" => {
+                let fuzz = fields.number("fuzz", 0.0)?;
+                if fuzz > 1.0 {
+                    fields.warning(
+                        "fuzz",
+                        "a `fuzz` above 1 scatters most rays into the surface",
+                    );
+                }
+
+                Arc::new(material::Metal::new(
+                    fields.required("albedo", as_vector)?,
+                    fuzz,
+                ))
+            }
+            "dielectric" => Arc::new(material::Dielectric::new(
+                fields.optional("ir", as_positive)?.unwrap_or(1.5),
+            )),
+            "diffuse_light" => Arc::new({
+                let light = match fields.optional("temperature", as_positive)? {
+                    Some(kelvin) => material::DiffuseLight::with_temperature(
+                        kelvin,
+                        fields.number("intensity", 1.0)?,
+                    ),
+                    None => material::DiffuseLight::new(fields.required("emit", texture)?),
+                };
+                let light =
+                    light.with_two_sided(fields.optional("two_sided", as_bool)?.unwrap_or(true));
+
+                match fields.optional("profile", as_path)? {
+                    Some(path) => light.with_profile(Arc::new(
+                        IesProfile::load(&path)
+                            .map_err(|error| fields.cannot_read("profile", &path, error))?,
+                    )),
+                    None => light,
+                }
+            }),
+            "normal_mapped" => {
+                let base = fields.required("base", material)?;
+                let strength = fields.number("strength", 1.0)?;
+
+                match fields.optional("normal_map", texture)? {
+                    Some(map) => {
+                        Arc::new(material::NormalMapped::with_normal_map(base, map, strength))
+                    }
+                    None => Arc::new(material::NormalMapped::new(
+                        base,
+                        NormalSource::Height {
+                            texture: fields.required("height", texture)?,
+                            delta: fields
+                                .optional("delta", as_positive)?
+                                .unwrap_or(material::NormalMapped::HEIGHT_DELTA),
+                        },
+                        strength,
+                    )),
+                }
+            }
+            "mix" => Arc::new(material::MixMaterial::new(
+                fields.required("first", material)?,
+                fields.required("second", material)?,
+                fields.required("weight", texture)?,
+            )),
+            "coated" => Arc::new(material::CoatedMaterial::new(
+                fields.required("base", material)?,
+                fields.optional("ir", as_positive)?.unwrap_or(1.5),
+                fields.number("roughness", 0.0)?,
+            )),
+            _ => return Err(fields.unknown_type("material")),
+        };
+
+        Ok(material)
+    }
+
+    fn object(&mut self, fields: &mut Fields) -> Result<(), Diagnostic> {
+        let material = |field: &Field| self.material_value(field);
+
+        let mut object: WorldElementType = match fields.type_name()? {
+            "sphere" => Arc::new(objects::Sphere::new(
+                fields.required("center", as_vector)?,
+                self.radius(fields)?,
+                fields.required("material", material)?,
+            )),
+            "moving_sphere" => {
+                let (start_time, end_time) = (
+                    fields.number("start_time", 0.0)?,
+                    fields.number("end_time", 1.0)?,
+                );
+                if start_time == end_time {
+                    return Err(Diagnostic::error(
+                        fields.value_location("end_time"),
+                        "`start_time` and `end_time` should differ",
+                    ));
+                }
+
+                Arc::new(objects::MovingSphere::new(
+                    fields.required("start_center", as_vector)?,
+                    fields.required("end_center", as_vector)?,
+                    start_time,
+                    end_time,
+                    self.radius(fields)?,
+                    fields.required("material", material)?,
+                ))
+            }
+            "xy_rect" => {
+                let ((x0, x1), (y0, y1)) = (fields.extent("x0", "x1")?, fields.extent("y0", "y1")?);
+                Arc::new(objects::XyPlane::new(
+                    x0,
+                    x1,
+                    y0,
+                    y1,
+                    fields.required("k", as_number)?,
+                    fields.required("material", material)?,
+                ))
+            }
+            "xz_rect" => {
+                let ((x0, x1), (z0, z1)) = (fields.extent("x0", "x1")?, fields.extent("z0", "z1")?);
+                Arc::new(objects::XzPlane::new(
+                    x0,
+                    x1,
+                    z0,
+                    z1,
+                    fields.required("k", as_number)?,
+                    fields.required("material", material)?,
+                ))
+            }
+            "yz_rect" => {
+                let ((y0, y1), (z0, z1)) = (fields.extent("y0", "y1")?, fields.extent("z0", "z1")?);
+                Arc::new(objects::YzPlane::new(
+                    y0,
+                    y1,
+                    z0,
+                    z1,
+                    fields.required("k", as_number)?,
+                    fields.required("material", material)?,
+                ))
+            }
+            "box" => {
+                let (min, max) = (
+                    fields.required("min", as_vector)?,
+                    fields.required("max", as_vector)?,
+                );
+                if (0..3).any(|axis| min[axis] >= max[axis]) {
+                    return Err(Diagnostic::error(
+                        fields.value_location("max"),
+                        "degenerate box: `max` should be greater than `min` along every axis",
+                    ));
+                }
+
+                Arc::new(objects::Box::new(
+                    min,
+                    max,
+                    fields.required("material", material)?,
+                ))
+            }
+            _ => return Err(fields.unknown_type("object")),
+        };
+
+        if let Some(transform) = self.transform(fields)? {
+            object = Arc::new(objects::AnimatedInstance::new(object, transform));
+        }
+        if let Some(opacity) = fields.optional("opacity", |field| self.texture_value(field))? {
+            let mode = match fields.optional("alpha_cutoff", as_number)? {
+                Some(cutoff) => {
+                    if !(0.0..=1.0).contains(&cutoff) {
+                        fields.warning(
+                            "alpha_cutoff",
+                            "an `alpha_cutoff` outside 0 to 1 keeps all or nothing",
+                        );
+                    }
+                    AlphaMode::Threshold(cutoff)
+                }
+                None => AlphaMode::Stochastic,
+            };
+            object = Arc::new(objects::AlphaMask::new(object, opacity, mode));
+        }
+        if fields.optional("flip", as_bool)?.unwrap_or(false) {
+            object = Arc::new(objects::FlipFace::new(object));
+        }
+
+        if fields.optional("light", as_bool)?.unwrap_or(false) {
+            if let Some(material) = self.material_type(fields) {
+                if ["lambertian", "metal", "dielectric", "coated"].contains(&material) {
+                    fields.warning(
+                        "light",
+                        format!("a `{}` material does not emit light", material),
+                    );
+                }
+            }
+
+            self.area_lights.push(object);
+        } else {
+            self.world.push(object);
+        }
+
+        Ok(())
+    }
+
+    /// The placement of an object given by its `translation`, `rotation`
+    /// and `scale` fields, each either a single value or a list of keys.
+    fn transform(&self, fields: &mut Fields) -> Result<Option<AnimatedTransform>, Diagnostic> {
+        let translation = fields.optional("translation", |field| as_keys(field, 3))?;
+        let rotation = match (
+            fields.optional("rotation", as_rotation_keys)?,
+            fields.optional("euler", as_euler_keys)?,
+        ) {
+            (Some(_), Some(_)) => {
+                return Err(Diagnostic::error(
+                    fields.value_location("euler"),
+                    "give either `rotation` or `euler`, not both",
+                ))
+            }
+            (rotation, euler) => rotation.or(euler),
+        };
+        let scale = fields.optional("scale", |field| as_keys(field, 3))?;
+        let interpolation = fields.choice(
+            "interpolation",
+            &[
+                ("linear", Interpolation::Linear),
+                ("catmull_rom", Interpolation::CatmullRom),
+            ],
+            Interpolation::Linear,
+        )?;
+
+        if translation.is_none() && rotation.is_none() && scale.is_none() {
+            return Ok(None);
+        }
+
+        let identity = Transform::identity();
+        let vectors = |keys: Option<Vec<(f64, Vec<f64>)>>, default: Vec3| match keys {
+            Some(keys) => Track::new(
+                keys.into_iter()
+                    .map(|(time, v)| (time, Vec3::new(v[0], v[1], v[2])))
+                    .collect(),
+                interpolation,
+            ),
+            None => Track::constant(default),
+        };
+
+        Ok(Some(AnimatedTransform::new(
+            vectors(translation, identity.translation),
+            match rotation {
+                Some(keys) => Track::new(keys, interpolation),
+                None => Track::constant(identity.rotation),
+            },
+            vectors(scale, identity.scale),
+        )))
+    }
+
+    /// Keyframed motion and hand-held shake of the camera. The transform
+    /// tracks are offsets from and rotations about its `from` position.
+    fn camera_motion(
+        &self,
+        fields: &mut Fields,
+        camera: &mut CameraSettings,
+    ) -> Result<(), Diagnostic> {
+        if fields.get("scale")?.is_some() {
+            return Err(Diagnostic::error(
+                fields.value_location("scale"),
+                "a camera cannot be scaled",
+            ));
+        }
+        camera.motion = self.transform(fields)?;
+
+        if let Some(shake) = fields.optional("shake", as_vector)? {
+            let (amplitude, angle, frequency) = (shake.x(), shake.y(), shake.z());
+            if amplitude < 0.0 || angle < 0.0 || frequency <= 0.0 {
+                return Err(Diagnostic::error(
+                    fields.value_location("shake"),
+                    "`shake` should be `[amplitude, angle, frequency]` with a positive frequency",
+                ));
+            }
+
+            camera.shake = Some(CameraShake::new(
+                amplitude,
+                angle,
+                frequency,
+                self.seed(fields)?,
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn light(&mut self, fields: &mut Fields) -> Result<(), Diagnostic> {
+        let white = Color::new(1.0, 1.0, 1.0);
+
+        match fields.type_name()? {
+            "point" => {
+                let light = light::PointLight::new(
+                    fields.required("position", as_vector)?,
+                    fields.vector("color", white)?,
+                    fields.number("intensity", 1.0)?,
+                );
+                let axis = fields
+                    .optional("axis", as_direction)?
+                    .unwrap_or(Vec3::new(0.0, -1.0, 0.0));
+
+                let light = match fields.optional("profile", as_path)? {
+                    Some(path) => light.with_profile(
+                        Arc::new(
+                            IesProfile::load(&path)
+                                .map_err(|error| fields.cannot_read("profile", &path, error))?,
+                        ),
+                        axis,
+                    ),
+                    None => light,
+                };
+                self.lights.push(Arc::new(light));
+            }
+            "spot" => {
+                let inner_angle = fields.required("inner_angle", as_number)?;
+                let outer_angle = fields.required("outer_angle", as_number)?;
+                if inner_angle > outer_angle {
+                    fields.warning(
+                        "inner_angle",
+                        "`inner_angle` is wider than `outer_angle`, so the spot has a hard edge",
+                    );
+                }
+
+                self.lights.push(Arc::new(light::SpotLight::new(
+                    fields.required("position", as_vector)?,
+                    fields.required("direction", as_direction)?,
+                    fields.vector("color", white)?,
+                    fields.number("intensity", 1.0)?,
+                    inner_angle,
+                    outer_angle,
+                )))
+            }
+            "directional" => self.lights.push(Arc::new(light::DirectionalLight::new(
+                fields.required("direction", as_direction)?,
+                fields.vector("color", white)?,
+                fields.number("irradiance", 1.0)?,
+                fields.number("angular_diameter", 0.0)?,
+            ))),
+            "environment" => {
+                let path = fields.required("path", as_path)?;
+                let environment = EnvironmentLight::load(
+                    &path,
+                    fields.number("rotation", 0.0)?,
+                    fields.number("intensity", 1.0)?,
+                )
+                .map_err(|error| fields.cannot_read("path", &path, error))?;
+
+                self.set_environment(fields, environment)?;
+            }
+            "sky" => {
+                let elevation = fields.required("elevation", as_number)?;
+                if elevation < 0.0 {
+                    fields.warning(
+                        "elevation",
+                        "the sky model is only fitted for the sun above the horizon",
+                    );
+                }
+
+                let sky = Sky::new(
+                    elevation,
+                    fields.required("azimuth", as_number)?,
+                    fields.number("turbidity", 3.0)?,
+                )
+                .with_intensity(fields.number("intensity", 0.025)?)
+                .with_ground_albedo(fields.vector("ground_albedo", Color::new(0.3, 0.3, 0.3))?);
+                let resolution = fields.optional("resolution", as_size)?.unwrap_or(512);
+
+                if fields.optional("sun", as_bool)?.unwrap_or(true) {
+                    self.lights.push(Arc::new(sky.sun()));
+                }
+                self.set_environment(fields, sky.environment(resolution))?;
+            }
+            _ => return Err(fields.unknown_type("light")),
+        }
+
+        Ok(())
+    }
+
+    fn set_environment(
+        &mut self,
+        fields: &Fields,
+        environment: EnvironmentLight,
+    ) -> Result<(), Diagnostic> {
+        if self.environment.is_some() {
+            return Err(Diagnostic::error(
+                &fields.block.location,
+                "the scene already has an environment",
+            ));
+        }
+
+        self.environment = Some(Arc::new(environment));
+        Ok(())
+    }
+
+    /// Radius of a sphere. Negative radii turn the normals inwards, which
+    /// is only meant for the inside of hollow glass spheres.
+    fn radius(&self, fields: &mut Fields) -> Result<f64, Diagnostic> {
+        let radius = fields.required("radius", as_number)?;
+
+        if radius == 0.0 {
+            return Err(Diagnostic::error(
+                fields.value_location("radius"),
+                "`radius` should not be zero",
+            ));
+        }
+        if radius < 0.0 && self.material_type(fields) != Some("dielectric") {
+            return Err(Diagnostic::error(
+                fields.value_location("radius"),
+                "`radius` should be positive; negative radii are only for hollow spheres of a `dielectric` material",
+            ));
+        }
+
+        Ok(radius)
+    }
+}
+
+fn settings(fields: &mut Fields) -> Result<RenderSettings, Diagnostic> {
+    let defaults = default_settings();
+
+    let width = fields
+        .optional("width", as_image_size)?
+        .unwrap_or(defaults.width);
+    let height = match (
+        fields.optional("height", as_image_size)?,
+        fields.optional("aspect_ratio", as_positive)?,
+    ) {
+        (Some(_), Some(_)) => {
+            return Err(Diagnostic::error(
+                &fields.block.location,
+                "give either `height` or `aspect_ratio`, not both",
+            ))
+        }
+        (Some(height), None) => height,
+        (None, Some(aspect_ratio)) => ((width as f64 / aspect_ratio) as usize).max(2),
+        (None, None) => width,
+    };
+
+    let max_depth = fields
+        .optional("max_depth", as_count)?
+        .map_or(defaults.max_depth, |depth| depth as u32);
+    if max_depth == 0 {
+        fields.warning("max_depth", "a `max_depth` of 0 renders a black image");
+    }
+
+    let filter_type = fields.choice("filter", &FilterType::NAMES, defaults.filter.filter_type)?;
+    let filter = match fields.optional("filter_radius", as_positive)? {
+        Some(radius) => Filter::new(filter_type).with_radius(radius),
+        None => Filter::new(filter_type),
+    };
+
+    let samples_per_pixel = fields
+        .optional("samples", as_size)?
+        .map_or(defaults.samples_per_pixel, |samples| samples as u32);
+    let adaptive = adaptive(fields, samples_per_pixel)?;
+
+    Ok(RenderSettings {
+        width,
+        height,
+        samples_per_pixel,
+        max_depth,
+        background: fields.vector("background", defaults.background)?,
+        seed: fields.optional("seed", as_seed)?.unwrap_or(defaults.seed),
+        sampler: fields.choice("sampler", &SamplerType::NAMES, defaults.sampler)?,
+        filter,
+        adaptive,
+    })
+}
+
+/// Adaptive sampling is on when the settings give an `adaptive_threshold`.
+fn adaptive(
+    fields: &mut Fields,
+    samples_per_pixel: u32,
+) -> Result<Option<AdaptiveSettings>, Diagnostic> {
+    let threshold = fields.optional("adaptive_threshold", as_positive)?;
+    let min_samples = fields.optional("min_samples", as_count)?;
+    let max_samples = fields.optional("max_samples", as_count)?;
+
+    let threshold = match threshold {
+        Some(threshold) => threshold,
+        None => {
+            for (key, value) in [("min_samples", min_samples), ("max_samples", max_samples)] {
+                if value.is_some() {
+                    fields.warning(key, format!("`{}` needs an `adaptive_threshold`", key));
+                }
+            }
+            return Ok(None);
+        }
+    };
+
+    let mut adaptive = AdaptiveSettings::new(threshold, samples_per_pixel);
+    if let Some(min_samples) = min_samples {
+        if min_samples < 2 {
+            return Err(Diagnostic::error(
+                fields.value_location("min_samples"),
+                "`min_samples` should be at least 2, to estimate the noise from",
+            ));
+        }
+        adaptive.min_samples = min_samples as u32;
+    }
+    if let Some(max_samples) = max_samples {
+        adaptive.max_samples = max_samples as u32;
+    }
+    if adaptive.max_samples < adaptive.min_samples {
+        return Err(Diagnostic::error(
+            fields.value_location(if max_samples.is_some() {
+                "max_samples"
+            } else {
+                "min_samples"
+            }),
+            "`max_samples` should not be below `min_samples`",
+        ));
+    }
+
+    Ok(Some(adaptive))
+}
+
+fn camera(fields: &mut Fields) -> Result<CameraSettings, Diagnostic> {
+    let lookfrom = fields.required("from", as_vector)?;
+    let lookat = fields.required("at", as_vector)?;
+    let vup = fields
+        .optional("up", as_direction)?
+        .unwrap_or(Vec3::new(0.0, 1.0, 0.0));
+
+    let view = lookat - lookfrom;
+    if view.near_zero() {
+        return Err(Diagnostic::error(
+            fields.value_location("at"),
+            "the camera looks `at` the point it is `from`",
+        ));
+    }
+    if view.cross(&vup).near_zero() {
+        return Err(Diagnostic::error(
+            fields.value_location("up"),
+            "`up` is parallel to the view direction",
+        ));
+    }
+
+    let projection = match fields.type_name()? {
+        "perspective" => {
+            let fov = fields.number("fov", 40.0)?;
+            if fov <= 0.0 || fov >= 180.0 {
+                return Err(Diagnostic::error(
+                    fields.value_location("fov"),
+                    "`fov` should be between 0 and 180 degrees",
+                ));
+            }
+
+            let aperture = fields.number("aperture", 0.0)?;
+            if aperture < 0.0 {
+                return Err(Diagnostic::error(
+                    fields.value_location("aperture"),
+                    "`aperture` should not be negative",
+                ));
+            }
+
+            let focus_dist = fields
+                .optional("focus_distance", as_positive)?
+                .unwrap_or(view.length());
+            if aperture == 0.0 && focus_dist > FOCUS_WARNING_RATIO * view.length() {
+                fields.warning(
+                    "focus_distance",
+                    "a pinhole camera is sharp everywhere; set `aperture` for depth of field",
+                );
+            }
+
+            if aperture == 0.0 {
+                for key in ["blades", "aperture_mask"] {
+                    if fields.get(key)?.is_some() {
+                        fields.warning(key, "a pinhole camera has no aperture to shape");
+                    }
+                }
+            }
+
+            Projection::Perspective {
+                fov,
+                aperture,
+                focus_dist,
+            }
+        }
+        "physical" => {
+            let focal_length = fields.required("focal_length", as_positive)?;
+            let f_number = fields.required("f_number", as_positive)?;
+            let mut lens = match fields.optional("sensor", as_pair)? {
+                Some((width, height)) if width > 0.0 && height > 0.0 => {
+                    PhysicalLens::new(focal_length, width, height, f_number)
+                }
+                Some(_) => {
+                    return Err(Diagnostic::error(
+                        fields.value_location("sensor"),
+                        "the `sensor` width and height should be greater than zero",
+                    ))
+                }
+                None => PhysicalLens::full_frame(focal_length, f_number),
+            };
+            lens.units_per_meter = fields
+                .optional("units_per_meter", as_positive)?
+                .unwrap_or(1.0);
+
+            Projection::Physical {
+                lens,
+                focus_dist: fields
+                    .optional("focus_distance", as_positive)?
+                    .unwrap_or(view.length()),
+            }
+        }
+        "orthographic" => Projection::Orthographic {
+            view_height: fields.required("view_height", as_positive)?,
+        },
+        "fisheye" => {
+            let fov = fields.number("fov", 180.0)?;
+            if fov <= 0.0 || fov > 360.0 {
+                return Err(Diagnostic::error(
+                    fields.value_location("fov"),
+                    "`fov` should be between 0 and 360 degrees",
+                ));
+            }
+
+            Projection::Fisheye {
+                fov,
+                projection: fields.choice(
+                    "mapping",
+                    &[
+                        ("equidistant", FisheyeProjection::Equidistant),
+                        ("equisolid", FisheyeProjection::Equisolid),
+                    ],
+                    FisheyeProjection::Equidistant,
+                )?,
+            }
+        }
+        "equirectangular" => Projection::Equirectangular,
+        _ => return Err(fields.unknown_type("camera")),
+    };
+
+    let mut camera = CameraSettings::new(lookfrom, lookat, vup, projection);
+    if matches!(
+        projection,
+        Projection::Perspective { .. } | Projection::Physical { .. }
+    ) {
+        camera.aperture_shape = lens_aperture(fields)?;
+        camera.shift = fields.optional("shift", as_pair)?.unwrap_or((0.0, 0.0));
+        camera.tilt = (fields.number("tilt", 0.0)?, fields.number("swing", 0.0)?);
+        if camera.tilt.0.abs() >= 90.0 || camera.tilt.1.abs() >= 90.0 {
+            return Err(Diagnostic::error(
+                fields.value_location(if camera.tilt.0.abs() >= 90.0 {
+                    "tilt"
+                } else {
+                    "swing"
+                }),
+                "`tilt` and `swing` should be between -90 and 90 degrees",
+            ));
+        }
+        camera.distortion = fields.optional("distortion", as_distortion)?;
+    }
+
+    Ok(camera)
+}
+
+/// The aperture of a thin lens camera: a polygon with `blades` corners or
+/// the image at `aperture_mask`, round when neither is given.
+fn lens_aperture(fields: &mut Fields) -> Result<ApertureShape, Diagnostic> {
+    let blades = fields.optional("blades", as_count)?;
+    let mask = fields.optional("aperture_mask", as_path)?;
+
+    match (blades, mask) {
+        (Some(_), Some(_)) => Err(Diagnostic::error(
+            fields.value_location("aperture_mask"),
+            "give either `blades` or `aperture_mask`, not both",
+        )),
+        (Some(blades), None) if blades < 3 => Err(Diagnostic::error(
+            fields.value_location("blades"),
+            "`blades` should be at least 3",
+        )),
+        (Some(blades), None) => Ok(ApertureShape::Polygon {
+            blades: blades as u32,
+            rotation: fields.number("blade_rotation", 0.0)?,
+        }),
+        (None, Some(path)) => {
+            let image = Image::load(&path, false)
+                .map_err(|error| fields.cannot_read("aperture_mask", &path, error))?;
+
+            Ok(ApertureShape::Mask(Arc::new(ApertureMask::new(image))))
+        }
+        (None, None) => Ok(ApertureShape::Circle),
+    }
+}
+
+fn define<T>(
+    names: &mut HashMap<String, Definition<T>>,
+    kind: &str,
+    block: &Block,
+    value: T,
+) -> Result<(), Diagnostic> {
+    let name = match &block.name {
+        Some(name) => name,
+        None => {
+            return Err(Diagnostic::error(
+                &block.location,
+                format!("{} needs a name", kind),
+            ))
+        }
+    };
+
+    if let Some(definition) = names.get(name) {
+        return Err(Diagnostic::error(
+            &block.location,
+            format!(
+                "{} `{}` is already defined at {}",
+                kind, name, definition.location
+            ),
+        ));
+    }
+
+    names.insert(
+        name.clone(),
+        Definition {
+            value,
+            type_name: block.type_name.clone().unwrap_or_default(),
+            location: block.location.clone(),
+            used: Cell::new(false),
+        },
+    );
+    Ok(())
+}
+
+/// Rejects NaN and infinite numbers, which would spread through every
+/// computation they enter.
+fn finite(field: &Field, number: f64) -> Result<f64, Diagnostic> {
+    if number.is_finite() {
+        Ok(number)
+    } else {
+        Err(Diagnostic::error(
+            &field.value_location,
+            format!(
+                "`{}` should be a finite number, found {}",
+                field.key, number
+            ),
+        ))
+    }
+}
+
+fn as_number(field: &Field) -> Result<f64, Diagnostic> {
+    match field.value {
+        Value::Number(number) => finite(field, number),
+        ref value => Err(expected(field, "a number", value)),
+    }
+}
+
+fn as_positive(field: &Field) -> Result<f64, Diagnostic> {
+    match as_number(field)? {
+        number if number > 0.0 => Ok(number),
+        _ => Err(Diagnostic::error(
+            &field.value_location,
+            format!("`{}` should be greater than zero", field.key),
+        )),
+    }
+}
+
+fn as_count(field: &Field) -> Result<usize, Diagnostic> {
+    match as_number(field)? {
+        number if number >= 0.0 && number.fract() == 0.0 => Ok(number as usize),
+        _ => Err(expected(field, "a whole number", &field.value)),
+    }
+}
+
+/// A render seed, which written numbers only keep exactly up to `MAX_SEED`.
+fn as_seed(field: &Field) -> Result<u64, Diagnostic> {
+    match as_count(field)? as u64 {
+        seed if seed <= MAX_SEED => Ok(seed),
+        _ => Err(Diagnostic::error(
+            &field.value_location,
+            format!("`seed` should be at most {}", MAX_SEED),
+        )),
+    }
+}
+
+/// A count of at least one, such as an image size.
+fn as_size(field: &Field) -> Result<usize, Diagnostic> {
+    match as_count(field)? {
+        0 => Err(Diagnostic::error(
+            &field.value_location,
+            format!("`{}` should be at least 1", field.key),
+        )),
+        count => Ok(count),
+    }
+}
+
+/// An image width or height. Pixel positions are mapped from the first to
+/// the last pixel, so there have to be two of them.
+fn as_image_size(field: &Field) -> Result<usize, Diagnostic> {
+    match as_count(field)? {
+        count if count < 2 => Err(Diagnostic::error(
+            &field.value_location,
+            format!("`{}` should be at least 2", field.key),
+        )),
+        count => Ok(count),
+    }
+}
+
+fn as_bool(field: &Field) -> Result<bool, Diagnostic> {
+    match field.value {
+        Value::Bool(value) => Ok(value),
+        ref value => Err(expected(field, "`true` or `false`", value)),
+    }
+}
+
+fn as_string(field: &Field) -> Result<String, Diagnostic> {
+    match &field.value {
+        Value::String(string) => Ok(string.clone()),
+        value => Err(expected(field, "a string", value)),
+    }
+}
+
+/// A file name, relative to the scene file it is written in.
+fn as_path(field: &Field) -> Result<PathBuf, Diagnostic> {
+    let path = as_string(field)?;
+    let directory = field.location.file.parent().unwrap_or(Path::new(""));
+
+    Ok(directory.join(path))
+}
+
+fn numbers(field: &Field, items: &[Value], count: usize) -> Result<Vec<f64>, Diagnostic> {
+    let numbers: Vec<f64> = items
+        .iter()
+        .filter_map(|item| match item {
+            Value::Number(number) => Some(*number),
+            _ => None,
+        })
+        .collect();
+
+    if numbers.len() != items.len() || numbers.len() != count {
+        return Err(Diagnostic::error(
+            &field.value_location,
+            format!("expected a list of {} numbers", count),
+        ));
+    }
+
+    numbers
+        .into_iter()
+        .map(|number| finite(field, number))
+        .collect()
+}
+
+fn as_pair(field: &Field) -> Result<(f64, f64), Diagnostic> {
+    match &field.value {
+        Value::List(items) => {
+            let v = numbers(field, items, 2)?;
+            Ok((v[0], v[1]))
+        }
+        value => Err(expected(field, "a list of 2 numbers", value)),
+    }
+}
+
+/// Brown-Conrady coefficients, `[k1, k2, k3]` or `[k1, k2, k3, p1, p2]`.
+fn as_distortion(field: &Field) -> Result<BrownConrady, Diagnostic> {
+    match &field.value {
+        Value::List(items) if items.len() == 3 => {
+            let k = numbers(field, items, 3)?;
+            Ok(BrownConrady::radial(k[0], k[1], k[2]))
+        }
+        Value::List(items) if items.len() == 5 => {
+            let k = numbers(field, items, 5)?;
+            Ok(BrownConrady::new(k[0], k[1], k[2], k[3], k[4]))
+        }
+        value => Err(expected(field, "a list of 3 or 5 numbers", value)),
+    }
+}
+
+fn as_vector(field: &Field) -> Result<Vec3, Diagnostic> {
+    match &field.value {
+        Value::List(items) => {
+            let v = numbers(field, items, 3)?;
+            Ok(Point3::new(v[0], v[1], v[2]))
+        }
+        value => Err(expected(field, "a list of 3 numbers", value)),
+    }
+}
+
+fn as_direction(field: &Field) -> Result<Vec3, Diagnostic> {
+    let direction = as_vector(field)?;
+
+    if direction.near_zero() {
+        return Err(Diagnostic::error(
+            &field.value_location,
+            format!("`{}` should not be zero", field.key),
+        ));
+    }
+
+    Ok(direction)
+}
+
+/// Keys of an animated field: a single value of `count` numbers, held at
+/// all times, or a list of keys written `[time, value...]`.
+fn as_keys(field: &Field, count: usize) -> Result<Vec<(f64, Vec<f64>)>, Diagnostic> {
+    match &field.value {
+        Value::List(keys) if matches!(keys.first(), Some(Value::List(_))) => keys
+            .iter()
+            .map(|key| match key {
+                Value::List(items) => {
+                    let v = numbers(field, items, count + 1)?;
+                    Ok((v[0], v[1..].to_vec()))
+                }
+                value => Err(expected(
+                    field,
+                    &format!("a list of {} numbers", count + 1),
+                    value,
+                )),
+            })
+            .collect(),
+        Value::List(items) => Ok(vec![(0.0, numbers(field, items, count)?)]),
+        value => Err(expected(field, "a value or a list of keys", value)),
+    }
+}
+
+/// Rotation keys, with quaternions written `[w, x, y, z]`.
+fn as_rotation_keys(field: &Field) -> Result<Vec<(f64, Quaternion)>, Diagnostic> {
+    as_keys(field, 4)?
+        .into_iter()
+        .map(|(time, q)| {
+            let rotation = Quaternion {
+                w: q[0],
+                v: Vec3::new(q[1], q[2], q[3]),
+            };
+            let length_squared = rotation.dot(&rotation);
+            if length_squared == 0.0 {
+                return Err(Diagnostic::error(
+                    &field.value_location,
+                    "`rotation` should not be a zero quaternion",
+                ));
+            }
+
+            // written unit quaternions are kept bit for bit
+            if (length_squared - 1.0).abs() > 1e-12 {
+                return Ok((time, rotation.normalized()));
+            }
+            Ok((time, rotation))
+        })
+        .collect()
+}
+
+/// Rotation keys written as `[yaw, pitch, roll]` in degrees.
+fn as_euler_keys(field: &Field) -> Result<Vec<(f64, Quaternion)>, Diagnostic> {
+    Ok(as_keys(field, 3)?
+        .into_iter()
+        .map(|(time, angles)| {
+            (
+                time,
+                Quaternion::from_euler(angles[0], angles[1], angles[2]),
+            )
+        })
+        .collect())
+}
+
+/// Colour ramp stops, each written as `[position, r, g, b]`.
+fn as_stops(field: &Field) -> Result<Vec<(f64, Color)>, Diagnostic> {
+    match &field.value {
+        Value::List(stops) if !stops.is_empty() => stops
+            .iter()
+            .map(|stop| match stop {
+                Value::List(items) => {
+                    let v = numbers(field, items, 4)?;
+                    Ok((v[0], Color::new(v[1], v[2], v[3])))
+                }
+                value => Err(expected(field, "a list of 4 numbers", value)),
+            })
+            .collect(),
+        value => Err(expected(field, "a list of stops", value)),
+    }
+}
+
+fn expected(field: &Field, what: &str, found: &Value) -> Diagnostic {
+    Diagnostic::error(
+        &field.value_location,
+        format!(
+            "`{}` should be {}, found {}",
+            field.key,
+            what,
+            found.describe()
+        ),
+    )
+}
+
+/// The fields of one block, looked up by key. Every field has to be used by
+/// the time the block is done, so misspelt keys are reported instead of
+/// being ignored.
+struct Fields<'a> {
+    block: &'a Block,
+    used: Vec<bool>,
+    warnings: Vec<Diagnostic>,
+}
+
+impl<'a> Fields<'a> {
+    fn new(block: &'a Block) -> Self {
+        Self {
+            block,
+            used: vec![false; block.fields.len()],
+            warnings: vec![],
+        }
+    }
+
+    /// Where the value of `key` is written, or the block if it is not.
+    fn value_location(&self, key: &str) -> &'a Location {
+        self.block
+            .fields
+            .iter()
+            .find(|field| field.key == key)
+            .map_or(&self.block.location, |field| &field.value_location)
+    }
+
+    fn warning<S: Into<String>>(&mut self, key: &str, message: S) {
+        let warning = Diagnostic::warning(self.value_location(key), message);
+        self.warnings.push(warning);
+    }
+
+    fn type_name(&self) -> Result<&'a str, Diagnostic> {
+        match &self.block.type_name {
+            Some(type_name) => Ok(type_name),
+            None => Err(Diagnostic::error(
+                &self.block.location,
+                format!("`{}` needs a type", self.block.kind),
+            )),
+        }
+    }
+
+    fn unknown_type(&self, kind: &str) -> Diagnostic {
+        Diagnostic::error(
+            &self.block.location,
+            format!("unknown {} type `{}`", kind, self.type_name().unwrap_or("")),
+        )
+    }
+
+    fn get(&mut self, key: &str) -> Result<Option<&'a Field>, Diagnostic> {
+        let mut found = None;
+
+        for (index, field) in self.block.fields.iter().enumerate() {
+            if field.key == key {
+                if found.is_some() {
+                    return Err(Diagnostic::error(
+                        &field.location,
+                        format!("`{}` is given twice", key),
+                    ));
+                }
+
+                self.used[index] = true;
+                found = Some(field);
+            }
+        }
+
+        Ok(found)
+    }
+
+    fn optional<T, F>(&mut self, key: &str, convert: F) -> Result<Option<T>, Diagnostic>
+    where
+        F: Fn(&Field) -> Result<T, Diagnostic>,
+    {
+        self.get(key)?.map(convert).transpose()
+    }
+
+    fn required<T, F>(&mut self, key: &str, convert: F) -> Result<T, Diagnostic>
+    where
+        F: Fn(&Field) -> Result<T, Diagnostic>,
+    {
+        match self.get(key)? {
+            Some(field) => convert(field),
+            None => Err(Diagnostic::error(
+                &self.block.location,
+                format!(
+                    "{} `{}` is missing `{}`",
+                    self.block.kind,
+                    self.block.type_name.as_deref().unwrap_or(""),
+                    key
+                ),
+            )),
+        }
+    }
+
+    fn number(&mut self, key: &str, default: f64) -> Result<f64, Diagnostic> {
+        Ok(self.optional(key, as_number)?.unwrap_or(default))
+    }
+
+    fn vector(&mut self, key: &str, default: Vec3) -> Result<Vec3, Diagnostic> {
+        Ok(self.optional(key, as_vector)?.unwrap_or(default))
+    }
+
+    /// Lower and upper bounds of a rectangle along one axis.
+    fn extent(&mut self, low: &str, high: &str) -> Result<(f64, f64), Diagnostic> {
+        let (low_value, high_value) = (
+            self.required(low, as_number)?,
+            self.required(high, as_number)?,
+        );
+
+        if low_value >= high_value {
+            return Err(Diagnostic::error(
+                self.value_location(high),
+                format!(
+                    "degenerate rectangle: `{}` should be greater than `{}`",
+                    high, low
+                ),
+            ));
+        }
+
+        Ok((low_value, high_value))
+    }
+
+    /// One of a fixed set of names, such as a wrap mode.
+    fn choice<T: Copy>(
+        &mut self,
+        key: &str,
+        options: &[(&str, T)],
+        default: T,
+    ) -> Result<T, Diagnostic> {
+        let field = match self.get(key)? {
+            Some(field) => field,
+            None => return Ok(default),
+        };
+
+        if let Value::Name(name) = &field.value {
+            if let Some((_, option)) = options.iter().find(|(option, _)| option == name) {
+                return Ok(*option);
+            }
+        }
+
+        let names: Vec<&str> = options.iter().map(|(name, _)| *name).collect();
+        Err(Diagnostic::error(
+            &field.value_location,
+            format!("`{}` should be one of {}", key, names.join(", ")),
+        ))
+    }
+
+    fn cannot_read(&self, key: &str, path: &Path, error: std::io::Error) -> Diagnostic {
+        Diagnostic::error(
+            self.value_location(key),
+            format!("cannot read {}: {}", path.display(), error),
+        )
+    }
+
+    fn finish(&self) -> Result<(), Diagnostic> {
+        match self.used.iter().position(|used| !used) {
+            Some(index) => {
+                let field = &self.block.fields[index];
+
+                Err(Diagnostic::error(
+                    &field.location,
+                    format!(
+                        "unknown field `{}` for {} `{}`",
+                        field.key,
+                        self.block.kind,
+                        self.block.type_name.as_deref().unwrap_or("")
+                    ),
+                ))
+            }
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,388 @@
+use crate::camera::{CameraSettings, FisheyeProjection, Projection};
+use crate::film::FilterType;
+use crate::lens::ApertureShape;
+use crate::objects::MaterialArc;
+use crate::render::{AdaptiveSettings, RenderSettings};
+use crate::sampler::SamplerType;
+use crate::scene_file::Value;
+use crate::scene_loader::{SceneDescription, MAX_SEED};
+use crate::texture::WrappedTextureType;
+use crate::vec3::Vec3;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Blocks longer than this are written with one field per line.
+const LINE_WIDTH: usize = 100;
+
+/// The type and fields of one block of a scene file, as read by
+/// `scene_loader`.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub type_name: &'static str,
+    pub fields: Vec<(&'static str, Value)>,
+}
+
+impl Entry {
+    pub fn new(type_name: &'static str) -> Self {
+        Self {
+            type_name,
+            fields: vec![],
+        }
+    }
+
+    pub fn with<V: Into<Value>>(mut self, key: &'static str, value: V) -> Self {
+        self.fields.push((key, value.into()));
+        self
+    }
+
+    /// Adds the field unless it has the value the loader uses when it is
+    /// left out.
+    pub fn with_non_default<V: Into<Value> + PartialEq>(
+        self,
+        key: &'static str,
+        value: V,
+        default: V,
+    ) -> Self {
+        if value == default {
+            self
+        } else {
+            self.with(key, value)
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.fields
+            .iter()
+            .find(|(field, _)| *field == key)
+            .map(|(_, value)| value)
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<Value> {
+        let index = self.fields.iter().position(|(field, _)| *field == key)?;
+        Some(self.fields.remove(index).1)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(number: f64) -> Self {
+        Value::Number(number)
+    }
+}
+
+impl From<u32> for Value {
+    fn from(number: u32) -> Self {
+        Value::Number(number as f64)
+    }
+}
+
+impl From<u64> for Value {
+    fn from(number: u64) -> Self {
+        Value::Number(number as f64)
+    }
+}
+
+impl From<usize> for Value {
+    fn from(number: usize) -> Self {
+        Value::Number(number as f64)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<Vec<f64>> for Value {
+    fn from(numbers: Vec<f64>) -> Self {
+        Value::List(numbers.into_iter().map(Value::Number).collect())
+    }
+}
+
+impl From<Vec3> for Value {
+    fn from(vector: Vec3) -> Self {
+        Value::List(vec![
+            Value::Number(vector.x()),
+            Value::Number(vector.y()),
+            Value::Number(vector.z()),
+        ])
+    }
+}
+
+/// One of the fixed set of names a field accepts, such as a wrap mode.
+pub fn choice(name: &str) -> Value {
+    Value::Name(String::from(name))
+}
+
+/// Writes scenes in the format read by `scene_loader`. Textures and
+/// materials shared by several users are written once and referred to by
+/// name; solid colours are written in place.
+pub struct SceneWriter {
+    text: String,
+    /// Values standing for the textures and materials written so far, by
+    /// address.
+    written: HashMap<usize, Value>,
+    /// Number of names handed out per type.
+    counts: HashMap<&'static str, usize>,
+    /// Directory the scene is written to, which paths are made relative to.
+    directory: Option<PathBuf>,
+}
+
+impl SceneWriter {
+    pub fn new() -> Self {
+        Self {
+            text: String::new(),
+            written: HashMap::new(),
+            counts: HashMap::new(),
+            directory: None,
+        }
+    }
+
+    /// Writes the paths of files below `directory` relative to it, so the
+    /// scene can be moved together with them.
+    pub fn with_directory<P: AsRef<Path>>(mut self, directory: P) -> Self {
+        self.directory = fs::canonicalize(directory).ok();
+        self
+    }
+
+    /// Writes the whole description: settings, camera, objects and lights.
+    pub fn write(mut self, description: &SceneDescription) -> Result<String, String> {
+        self.block("settings", None, &settings(&description.settings)?);
+        let entry = camera(&self, &description.camera)?;
+        self.block("camera", None, &entry);
+
+        let mut objects = vec![];
+        for object in &description.scene.world {
+            objects.extend(object.export(&mut self)?);
+        }
+
+        // the loader adds the area lights after the rest of the world
+        let (lights, others): (Vec<Entry>, Vec<Entry>) = objects
+            .into_iter()
+            .partition(|entry| entry.get("light").is_some());
+        for entry in others.iter().chain(lights.iter()) {
+            self.block("object", None, entry);
+        }
+
+        for light in &description.scene.lights {
+            if let Some(entry) = light.export(&mut self)? {
+                self.block("light", None, &entry);
+            }
+        }
+
+        Ok(self.text)
+    }
+
+    /// The value to refer to `texture` by, writing it first if needed.
+    pub fn texture(&mut self, texture: &WrappedTextureType) -> Result<Value, String> {
+        let address = Arc::as_ptr(texture) as *const () as usize;
+        if let Some(value) = self.written.get(&address) {
+            return Ok(value.clone());
+        }
+
+        let mut entry = texture.export(self)?;
+        let value = match (entry.type_name, entry.fields.len()) {
+            ("solid", 1) => entry.remove("color").unwrap_or(Value::List(vec![])),
+            _ => {
+                let name = self.name(entry.type_name);
+                self.block("texture", Some(&name), &entry);
+                Value::Name(name)
+            }
+        };
+
+        self.written.insert(address, value.clone());
+        Ok(value)
+    }
+
+    /// The name to refer to `material` by, writing it first if needed.
+    pub fn material(&mut self, material: &MaterialArc) -> Result<Value, String> {
+        let address = Arc::as_ptr(material) as *const () as usize;
+        if let Some(value) = self.written.get(&address) {
+            return Ok(value.clone());
+        }
+
+        let entry = material.export(self)?;
+        let name = self.name(entry.type_name);
+        self.block("material", Some(&name), &entry);
+
+        let value = Value::Name(name);
+        self.written.insert(address, value.clone());
+        Ok(value)
+    }
+
+    pub fn path(&self, path: &Path) -> Value {
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        let relative = self
+            .directory
+            .as_ref()
+            .and_then(|directory| path.strip_prefix(directory).ok())
+            .unwrap_or(&path);
+
+        Value::String(relative.display().to_string())
+    }
+
+    fn name(&mut self, type_name: &'static str) -> String {
+        let count = self.counts.entry(type_name).or_insert(0);
+        *count += 1;
+
+        format!("{}_{}", type_name, count)
+    }
+
+    fn block(&mut self, kind: &str, name: Option<&str>, entry: &Entry) {
+        let mut head = String::from(kind);
+        for word in [Some(entry.type_name), name].into_iter().flatten() {
+            if !word.is_empty() {
+                head.push(' ');
+                head.push_str(word);
+            }
+        }
+
+        let fields: Vec<String> = entry
+            .fields
+            .iter()
+            .map(|(key, value)| format!("{} = {}", key, value))
+            .collect();
+
+        let line = format!("{} {{ {} }}\n", head, fields.join(" "));
+        if line.len() <= LINE_WIDTH {
+            self.text.push_str(&line);
+            return;
+        }
+
+        // long blocks stand apart from the one-liners around them
+        if !self.text.is_empty() && !self.text.ends_with("\n\n") {
+            self.text.push('\n');
+        }
+        self.text.push_str(&format!("{} {{\n", head));
+        for field in fields {
+            self.text.push_str(&format!("    {}\n", field));
+        }
+        self.text.push_str("}\n\n");
+    }
+}
+
+fn settings(settings: &RenderSettings) -> Result<Entry, String> {
+    if settings.seed > MAX_SEED {
+        return Err(format!(
+            "seeds above {} cannot be written exactly",
+            MAX_SEED
+        ));
+    }
+
+    let entry = Entry::new("")
+        .with("width", settings.width)
+        .with("height", settings.height)
+        .with("samples", settings.samples_per_pixel)
+        .with("max_depth", settings.max_depth)
+        .with("background", settings.background)
+        .with_non_default("seed", settings.seed, 0)
+        .with_non_default(
+            "sampler",
+            choice(settings.sampler.name()),
+            choice(SamplerType::Independent.name()),
+        )
+        .with_non_default(
+            "filter",
+            choice(settings.filter.filter_type.name()),
+            choice(FilterType::Box.name()),
+        )
+        .with_non_default(
+            "filter_radius",
+            settings.filter.radius,
+            settings.filter.filter_type.default_radius(),
+        );
+
+    Ok(match settings.adaptive {
+        None => entry,
+        Some(adaptive) => {
+            let defaults = AdaptiveSettings::new(adaptive.threshold, settings.samples_per_pixel);
+
+            entry
+                .with("adaptive_threshold", adaptive.threshold)
+                .with_non_default("min_samples", adaptive.min_samples, defaults.min_samples)
+                .with_non_default("max_samples", adaptive.max_samples, defaults.max_samples)
+        }
+    })
+}
+
+fn camera(writer: &SceneWriter, camera: &CameraSettings) -> Result<Entry, String> {
+    let entry = |type_name| {
+        Entry::new(type_name)
+            .with("from", camera.lookfrom)
+            .with("at", camera.lookat)
+            .with_non_default("up", camera.vup, Vec3::new(0.0, 1.0, 0.0))
+    };
+
+    let entry = match camera.projection {
+        Projection::Perspective {
+            fov,
+            aperture,
+            focus_dist,
+        } => entry("perspective")
+            .with("fov", fov)
+            .with("aperture", aperture)
+            .with("focus_distance", focus_dist),
+        Projection::Physical { lens, focus_dist } => entry("physical")
+            .with("focal_length", lens.focal_length)
+            .with("f_number", lens.f_number)
+            .with_non_default(
+                "sensor",
+                vec![lens.sensor_width, lens.sensor_height],
+                vec![36.0, 24.0],
+            )
+            .with_non_default("units_per_meter", lens.units_per_meter, 1.0)
+            .with("focus_distance", focus_dist),
+        Projection::Orthographic { view_height } => {
+            entry("orthographic").with("view_height", view_height)
+        }
+        Projection::Fisheye { fov, projection } => entry("fisheye").with("fov", fov).with(
+            "mapping",
+            choice(match projection {
+                FisheyeProjection::Equidistant => "equidistant",
+                FisheyeProjection::Equisolid => "equisolid",
+            }),
+        ),
+        Projection::Equirectangular => entry("equirectangular"),
+    };
+
+    let entry = entry
+        .with_non_default(
+            "shift",
+            vec![camera.shift.0, camera.shift.1],
+            vec![0.0, 0.0],
+        )
+        .with_non_default("tilt", camera.tilt.0, 0.0)
+        .with_non_default("swing", camera.tilt.1, 0.0);
+    let entry = match camera.distortion {
+        None => entry,
+        Some(d) if d.p1 == 0.0 && d.p2 == 0.0 => entry.with("distortion", vec![d.k1, d.k2, d.k3]),
+        Some(d) => entry.with("distortion", vec![d.k1, d.k2, d.k3, d.p1, d.p2]),
+    };
+
+    let entry = match &camera.motion {
+        Some(motion) => motion.export(entry)?,
+        None => entry,
+    };
+    let entry = match &camera.shake {
+        Some(shake) => entry
+            .with("shake", vec![shake.amplitude, shake.angle, shake.frequency])
+            .with("seed", shake.noise.seed),
+        None => entry,
+    };
+
+    Ok(match &camera.aperture_shape {
+        ApertureShape::Circle => entry,
+        ApertureShape::Polygon { blades, rotation } => entry
+            .with("blades", *blades)
+            .with_non_default("blade_rotation", *rotation, 0.0),
+        ApertureShape::Mask(mask) => {
+            let source = mask.image.source.as_ref().ok_or_else(|| {
+                String::from("aperture masks that were not loaded from a file cannot be exported")
+            })?;
+
+            entry.with("aperture_mask", writer.path(&source.path))
+        }
+    })
+}
@@ -0,0 +1,215 @@
+use crate::color::xyy_to_rgb;
+use crate::light::{DirectionalLight, EnvironmentLight};
+use crate::math_traits::InnerProduct;
+use crate::utils::degrees_to_radians;
+use crate::vec3::{Color, Vec3};
+use std::f64::consts::PI;
+
+/// Luminance of the sun's disk outside the atmosphere, in kcd/m^2.
+const SUN_LUMINANCE: f64 = 1.6e6;
+/// Angular diameter of the sun in degrees.
+const SUN_DIAMETER: f64 = 0.53;
+/// Wavelengths in micrometres standing in for the red, green and blue
+/// channels when attenuating sunlight.
+const WAVELENGTHS: [f64; 3] = [0.68, 0.55, 0.44];
+
+/// Coefficients of the Perez sky luminance distribution
+/// `(1 + A e^(B / cos theta)) (1 + C e^(D gamma) + E cos^2 gamma)`.
+#[derive(Debug, Copy, Clone)]
+struct Perez {
+    a: f64,
+    b: f64,
+    c: f64,
+    d: f64,
+    e: f64,
+}
+
+impl Perez {
+    fn eval(&self, cos_theta: f64, gamma: f64) -> f64 {
+        (1.0 + self.a * (self.b / cos_theta).exp())
+            * (1.0 + self.c * (self.d * gamma).exp() + self.e * gamma.cos().powi(2))
+    }
+}
+
+/// Clear sky of the Preetham, Shirley and Smits model. Radiance is in kcd/m^2
+/// times `intensity`, so the default intensity brings a sunlit white surface
+/// close to one. `turbidity` ranges from about 2 for a very clear sky to 10
+/// for a hazy one.
+///
+/// The sky is used through a baked `environment`, which leaves out the sun
+/// itself; `sun` is the matching distant light.
+#[derive(Clone)]
+pub struct Sky {
+    /// Degrees of the sun above the horizon.
+    pub elevation: f64,
+    /// Degrees of the sun from -z towards +x.
+    pub azimuth: f64,
+    /// Unit vector towards the sun.
+    pub sun_direction: Vec3,
+    pub turbidity: f64,
+    pub intensity: f64,
+    /// Reflectance of the ground below the horizon, which is lit by the
+    /// horizon.
+    pub ground_albedo: Color,
+    luminance: Perez,
+    x: Perez,
+    y: Perez,
+    zenith: (f64, f64, f64),
+}
+
+impl Sky {
+    /// The sun at `elevation` degrees above the horizon and `azimuth` degrees
+    /// from -z towards +x.
+    pub fn new(elevation_degrees: f64, azimuth_degrees: f64, turbidity: f64) -> Self {
+        let (elevation, azimuth) = (
+            degrees_to_radians(elevation_degrees),
+            degrees_to_radians(azimuth_degrees),
+        );
+        let sun_direction = Vec3::new(
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+            -elevation.cos() * azimuth.cos(),
+        );
+
+        let t = turbidity;
+        let luminance = Perez {
+            a: 0.1787 * t - 1.4630,
+            b: -0.3554 * t + 0.4275,
+            c: -0.0227 * t + 5.3251,
+            d: 0.1206 * t - 2.5771,
+            e: -0.0670 * t + 0.3703,
+        };
+        let x = Perez {
+            a: -0.0193 * t - 0.2592,
+            b: -0.0665 * t + 0.0008,
+            c: -0.0004 * t + 0.2125,
+            d: -0.0641 * t - 0.8989,
+            e: -0.0033 * t + 0.0452,
+        };
+        let y = Perez {
+            a: -0.0167 * t - 0.2608,
+            b: -0.0950 * t + 0.0092,
+            c: -0.0079 * t + 0.2102,
+            d: -0.0441 * t - 1.6537,
+            e: -0.0109 * t + 0.0529,
+        };
+
+        // the model is fitted for the sun above the horizon
+        let theta_s = (PI / 2.0 - elevation).clamp(0.0, PI / 2.0);
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_luminance = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.0);
+
+        let cubic = |c: [f64; 4]| ((c[0] * theta_s + c[1]) * theta_s + c[2]) * theta_s + c[3];
+        let zenith_x = t * t * cubic([0.00166, -0.00375, 0.00209, 0.0])
+            + t * cubic([-0.02903, 0.06377, -0.03202, 0.00394])
+            + cubic([0.11693, -0.21196, 0.06052, 0.25886]);
+        let zenith_y = t * t * cubic([0.00275, -0.00610, 0.00317, 0.0])
+            + t * cubic([-0.04214, 0.08970, -0.04153, 0.00516])
+            + cubic([0.15346, -0.26756, 0.06670, 0.26688]);
+
+        Self {
+            elevation: elevation_degrees,
+            azimuth: azimuth_degrees,
+            sun_direction,
+            turbidity,
+            intensity: 0.025,
+            ground_albedo: Color::new(0.3, 0.3, 0.3),
+            luminance,
+            x,
+            y,
+            zenith: (zenith_luminance, zenith_x, zenith_y),
+        }
+    }
+
+    pub fn with_intensity(mut self, intensity: f64) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    pub fn with_ground_albedo(mut self, ground_albedo: Color) -> Self {
+        self.ground_albedo = ground_albedo;
+        self
+    }
+
+    /// Sky radiance seen along `direction`, not scaled by `intensity`.
+    pub fn radiance(&self, direction: &Vec3) -> Color {
+        let direction = direction.unit();
+
+        if direction.y() < 0.0 {
+            let horizon = Vec3::new(direction.x(), 0.0, direction.z());
+            if horizon.near_zero() {
+                return self.ground_albedo * self.radiance(&self.sun_direction);
+            }
+            return self.ground_albedo * self.radiance(&horizon);
+        }
+
+        // keep 1 / cos theta finite at the horizon
+        let cos_theta = direction.y().max(0.01);
+        let theta_s = (PI / 2.0 - self.sun_direction.y().clamp(-1.0, 1.0).asin()).min(PI / 2.0);
+        let gamma = direction.dot(&self.sun_direction).clamp(-1.0, 1.0).acos();
+
+        let relative = |perez: &Perez| perez.eval(cos_theta, gamma) / perez.eval(1.0, theta_s);
+        let (zenith_luminance, zenith_x, zenith_y) = self.zenith;
+
+        xyy_to_rgb(
+            zenith_x * relative(&self.x),
+            zenith_y * relative(&self.y),
+            zenith_luminance * relative(&self.luminance),
+        )
+    }
+
+    /// Fraction of sunlight of each channel that makes it through the
+    /// atmosphere, from Rayleigh and aerosol (Angstrom) extinction along the
+    /// relative air mass.
+    pub fn sun_transmittance(&self) -> Color {
+        let elevation = self.sun_direction.y().clamp(-1.0, 1.0).asin().to_degrees();
+        if elevation <= 0.0 {
+            return Color::zero();
+        }
+
+        let zenith_angle = 90.0 - elevation;
+        let air_mass = 1.0
+            / (degrees_to_radians(zenith_angle).cos()
+                + 0.15 * (93.885 - zenith_angle).powf(-1.253));
+        let beta = 0.04608 * self.turbidity - 0.04586;
+
+        let channel = |lambda: f64| {
+            let rayleigh = 0.008735 * lambda.powf(-4.08);
+            let aerosol = beta * lambda.powf(-1.3);
+
+            (-(rayleigh + aerosol) * air_mass).exp()
+        };
+
+        Color::new(
+            channel(WAVELENGTHS[0]),
+            channel(WAVELENGTHS[1]),
+            channel(WAVELENGTHS[2]),
+        )
+    }
+
+    /// The sun as a soft shadowed distant light, in the same units as the
+    /// sky.
+    pub fn sun(&self) -> DirectionalLight {
+        let solid_angle = 2.0 * PI * (1.0 - degrees_to_radians(SUN_DIAMETER / 2.0).cos());
+        let transmittance = self.sun_transmittance();
+
+        DirectionalLight::new(
+            self.sun_direction,
+            transmittance,
+            self.intensity * SUN_LUMINANCE * solid_angle,
+            SUN_DIAMETER,
+        )
+    }
+
+    /// The sky tabulated into an importance sampled environment map of
+    /// `width` by `width / 2` pixels.
+    pub fn environment(&self, width: usize) -> EnvironmentLight {
+        let mut environment =
+            EnvironmentLight::bake(width, (width / 2).max(1), self.intensity, |direction| {
+                self.radiance(direction)
+            });
+        environment.sky = Some(self.clone());
+
+        environment
+    }
+}
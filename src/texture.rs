@@ -1,8 +1,16 @@
+use crate::image::Image;
+use crate::scene_writer::{choice, Entry, SceneWriter};
 use crate::vec3::{Color, Point3};
+use std::io;
+use std::path::Path;
 use std::sync::Arc;
 
 pub trait Texture {
     fn color_value(&self, u: f64, v: f64, p: &Point3) -> Color;
+
+    /// The texture as a block of a scene file, writing the textures it
+    /// depends on first.
+    fn export(&self, writer: &mut SceneWriter) -> Result<Entry, String>;
 }
 
 pub type WrappedTextureType = Arc<dyn Texture + Sync + Send>;
@@ -21,6 +29,10 @@ impl Texture for SolidTexture {
     fn color_value(&self, _u: f64, _v: f64, _p: &Point3) -> Color {
         self.color.clone()
     }
+
+    fn export(&self, _writer: &mut SceneWriter) -> Result<Entry, String> {
+        Ok(Entry::new("solid").with("color", self.color))
+    }
 }
 
 pub struct CheckerTexture {
@@ -50,4 +62,132 @@ impl Texture for CheckerTexture {
             self.even.color_value(u, v, p)
         }
     }
+
+    fn export(&self, writer: &mut SceneWriter) -> Result<Entry, String> {
+        Ok(Entry::new("checker")
+            .with("odd", writer.texture(&self.odd)?)
+            .with("even", writer.texture(&self.even)?))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WrapMode {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+impl WrapMode {
+    #[inline(always)]
+    fn apply(&self, i: isize, size: usize) -> usize {
+        let size = size as isize;
+
+        let wrapped = match self {
+            WrapMode::Repeat => i.rem_euclid(size),
+            WrapMode::Clamp => i.clamp(0, size - 1),
+            WrapMode::Mirror => {
+                let period = i.rem_euclid(2 * size);
+                if period < size {
+                    period
+                } else {
+                    2 * size - 1 - period
+                }
+            }
+        };
+
+        wrapped as usize
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FilterMode {
+    Nearest,
+    Bilinear,
+}
+
+pub struct ImageTexture {
+    pub image: Image,
+    pub wrap: WrapMode,
+    pub filter: FilterMode,
+}
+
+impl ImageTexture {
+    pub fn new(image: Image, wrap: WrapMode, filter: FilterMode) -> Self {
+        ImageTexture {
+            image,
+            wrap,
+            filter,
+        }
+    }
+
+    /// Loads a PPM or PNG file. Set `srgb` for colour maps so the texels are
+    /// converted to linear; leave it unset for data maps.
+    pub fn load<P: AsRef<Path>>(
+        path: P,
+        wrap: WrapMode,
+        filter: FilterMode,
+        srgb: bool,
+    ) -> io::Result<Self> {
+        Ok(Self::new(Image::load(path, srgb)?, wrap, filter))
+    }
+
+    #[inline(always)]
+    fn texel(&self, x: isize, y: isize) -> Color {
+        self.image.get(
+            self.wrap.apply(x, self.image.width),
+            self.wrap.apply(y, self.image.height),
+        )
+    }
+}
+
+impl Texture for ImageTexture {
+    fn color_value(&self, u: f64, v: f64, _p: &Point3) -> Color {
+        if self.image.width == 0 || self.image.height == 0 {
+            return Color::new(0.0, 1.0, 1.0);
+        }
+
+        // image rows go top to bottom while v goes bottom to top
+        let x = u * self.image.width as f64;
+        let y = (1.0 - v) * self.image.height as f64;
+
+        match self.filter {
+            FilterMode::Nearest => self.texel(x.floor() as isize, y.floor() as isize),
+            FilterMode::Bilinear => {
+                let x = x - 0.5;
+                let y = y - 0.5;
+                let x0 = x.floor();
+                let y0 = y.floor();
+                let tx = x - x0;
+                let ty = y - y0;
+                let x0 = x0 as isize;
+                let y0 = y0 as isize;
+
+                let top = (1.0 - tx) * self.texel(x0, y0) + tx * self.texel(x0 + 1, y0);
+                let bottom = (1.0 - tx) * self.texel(x0, y0 + 1) + tx * self.texel(x0 + 1, y0 + 1);
+
+                (1.0 - ty) * top + ty * bottom
+            }
+        }
+    }
+
+    fn export(&self, writer: &mut SceneWriter) -> Result<Entry, String> {
+        let source = self.image.source.as_ref().ok_or_else(|| {
+            String::from("image textures that were not loaded from a file cannot be exported")
+        })?;
+        let wrap = match self.wrap {
+            WrapMode::Repeat => "repeat",
+            WrapMode::Clamp => "clamp",
+            WrapMode::Mirror => "mirror",
+        };
+        let filter = match self.filter {
+            FilterMode::Nearest => "nearest",
+            FilterMode::Bilinear => "bilinear",
+        };
+
+        Ok(Entry::new("image")
+            .with("path", writer.path(&source.path))
+            .with("wrap", choice(wrap))
+            .with("filter", choice(filter))
+            .with_non_default("srgb", source.srgb, true))
+    }
 }
@@ -0,0 +1,352 @@
+use crate::color::{hsv_to_rgb, luminance, rgb_to_hsv};
+use crate::scene_file::Value;
+use crate::scene_writer::{choice, Entry, SceneWriter};
+use crate::texture::{Texture, WrappedTextureType};
+use crate::utils::{clamp, degrees_to_radians};
+use crate::vec3::{Color, Point3};
+
+/// Blends `first` into `second` channel by channel, using the colour of
+/// `factor` as the blend weight.
+pub struct MixTexture {
+    pub first: WrappedTextureType,
+    pub second: WrappedTextureType,
+    pub factor: WrappedTextureType,
+}
+
+impl MixTexture {
+    pub fn new(
+        first: WrappedTextureType,
+        second: WrappedTextureType,
+        factor: WrappedTextureType,
+    ) -> Self {
+        MixTexture {
+            first,
+            second,
+            factor,
+        }
+    }
+}
+
+impl Texture for MixTexture {
+    fn color_value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        let factor = self.factor.color_value(u, v, p);
+
+        (Color::new(1.0, 1.0, 1.0) - factor) * self.first.color_value(u, v, p)
+            + factor * self.second.color_value(u, v, p)
+    }
+
+    fn export(&self, writer: &mut SceneWriter) -> Result<Entry, String> {
+        Ok(Entry::new("mix")
+            .with("first", writer.texture(&self.first)?)
+            .with("second", writer.texture(&self.second)?)
+            .with("factor", writer.texture(&self.factor)?))
+    }
+}
+
+pub struct MultiplyTexture {
+    pub first: WrappedTextureType,
+    pub second: WrappedTextureType,
+}
+
+impl MultiplyTexture {
+    pub fn new(first: WrappedTextureType, second: WrappedTextureType) -> Self {
+        MultiplyTexture { first, second }
+    }
+}
+
+impl Texture for MultiplyTexture {
+    fn color_value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        self.first.color_value(u, v, p) * self.second.color_value(u, v, p)
+    }
+
+    fn export(&self, writer: &mut SceneWriter) -> Result<Entry, String> {
+        Ok(Entry::new("multiply")
+            .with("first", writer.texture(&self.first)?)
+            .with("second", writer.texture(&self.second)?))
+    }
+}
+
+pub struct AddTexture {
+    pub first: WrappedTextureType,
+    pub second: WrappedTextureType,
+}
+
+impl AddTexture {
+    pub fn new(first: WrappedTextureType, second: WrappedTextureType) -> Self {
+        AddTexture { first, second }
+    }
+}
+
+impl Texture for AddTexture {
+    fn color_value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        self.first.color_value(u, v, p) + self.second.color_value(u, v, p)
+    }
+
+    fn export(&self, writer: &mut SceneWriter) -> Result<Entry, String> {
+        Ok(Entry::new("add")
+            .with("first", writer.texture(&self.first)?)
+            .with("second", writer.texture(&self.second)?))
+    }
+}
+
+pub struct InvertTexture {
+    pub input: WrappedTextureType,
+}
+
+impl InvertTexture {
+    pub fn new(input: WrappedTextureType) -> Self {
+        InvertTexture { input }
+    }
+}
+
+impl Texture for InvertTexture {
+    fn color_value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        Color::new(1.0, 1.0, 1.0) - self.input.color_value(u, v, p)
+    }
+
+    fn export(&self, writer: &mut SceneWriter) -> Result<Entry, String> {
+        Ok(Entry::new("invert").with("input", writer.texture(&self.input)?))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RampInterpolation {
+    Constant,
+    Linear,
+    Smoothstep,
+}
+
+/// Maps the luminance of `input` through a list of colour stops. Positions
+/// outside the first and last stops take the colour of the nearest stop.
+pub struct ColorRampTexture {
+    pub input: WrappedTextureType,
+    pub stops: Vec<(f64, Color)>,
+    pub interpolation: RampInterpolation,
+}
+
+impl ColorRampTexture {
+    pub fn new(
+        input: WrappedTextureType,
+        mut stops: Vec<(f64, Color)>,
+        interpolation: RampInterpolation,
+    ) -> Self {
+        assert!(!stops.is_empty());
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        ColorRampTexture {
+            input,
+            stops,
+            interpolation,
+        }
+    }
+
+    pub fn evaluate(&self, position: f64) -> Color {
+        let upper = self.stops.partition_point(|stop| stop.0 <= position);
+
+        if upper == 0 {
+            return self.stops[0].1;
+        }
+        if upper == self.stops.len() {
+            return self.stops[upper - 1].1;
+        }
+
+        let (p0, c0) = self.stops[upper - 1];
+        let (p1, c1) = self.stops[upper];
+        let t = (position - p0) / (p1 - p0);
+
+        let t = match self.interpolation {
+            RampInterpolation::Constant => 0.0,
+            RampInterpolation::Linear => t,
+            RampInterpolation::Smoothstep => t * t * (3.0 - 2.0 * t),
+        };
+
+        (1.0 - t) * c0 + t * c1
+    }
+}
+
+impl Texture for ColorRampTexture {
+    fn color_value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        self.evaluate(luminance(&self.input.color_value(u, v, p)))
+    }
+
+    fn export(&self, writer: &mut SceneWriter) -> Result<Entry, String> {
+        let stops = self
+            .stops
+            .iter()
+            .map(|(position, color)| {
+                Value::List(vec![
+                    Value::Number(*position),
+                    Value::Number(color.x()),
+                    Value::Number(color.y()),
+                    Value::Number(color.z()),
+                ])
+            })
+            .collect();
+        let interpolation = match self.interpolation {
+            RampInterpolation::Constant => "constant",
+            RampInterpolation::Linear => "linear",
+            RampInterpolation::Smoothstep => "smoothstep",
+        };
+
+        Ok(Entry::new("color_ramp")
+            .with("input", writer.texture(&self.input)?)
+            .with("stops", Value::List(stops))
+            .with("interpolation", choice(interpolation)))
+    }
+}
+
+/// Linearly maps each channel of `input` from `[from_min, from_max]` to
+/// `[to_min, to_max]`, optionally clamping to the target range.
+pub struct RemapTexture {
+    pub input: WrappedTextureType,
+    pub from_min: f64,
+    pub from_max: f64,
+    pub to_min: f64,
+    pub to_max: f64,
+    pub clamp: bool,
+}
+
+impl RemapTexture {
+    pub fn new(
+        input: WrappedTextureType,
+        from_min: f64,
+        from_max: f64,
+        to_min: f64,
+        to_max: f64,
+        clamp: bool,
+    ) -> Self {
+        RemapTexture {
+            input,
+            from_min,
+            from_max,
+            to_min,
+            to_max,
+            clamp,
+        }
+    }
+
+    #[inline(always)]
+    fn remap(&self, x: f64) -> f64 {
+        let t = (x - self.from_min) / (self.from_max - self.from_min);
+        let t = if self.clamp { clamp(t, 0.0, 1.0) } else { t };
+
+        self.to_min + t * (self.to_max - self.to_min)
+    }
+}
+
+impl Texture for RemapTexture {
+    fn color_value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        let color = self.input.color_value(u, v, p);
+
+        Color::new(
+            self.remap(color.x()),
+            self.remap(color.y()),
+            self.remap(color.z()),
+        )
+    }
+
+    fn export(&self, writer: &mut SceneWriter) -> Result<Entry, String> {
+        Ok(Entry::new("remap")
+            .with("input", writer.texture(&self.input)?)
+            .with("from_min", self.from_min)
+            .with("from_max", self.from_max)
+            .with("to_min", self.to_min)
+            .with("to_max", self.to_max)
+            .with("clamp", self.clamp))
+    }
+}
+
+/// Shifts the hue of `input` by `hue_shift` turns and scales its saturation
+/// and value.
+pub struct HsvAdjustTexture {
+    pub input: WrappedTextureType,
+    pub hue_shift: f64,
+    pub saturation: f64,
+    pub value: f64,
+}
+
+impl HsvAdjustTexture {
+    pub fn new(input: WrappedTextureType, hue_shift: f64, saturation: f64, value: f64) -> Self {
+        HsvAdjustTexture {
+            input,
+            hue_shift,
+            saturation,
+            value,
+        }
+    }
+}
+
+impl Texture for HsvAdjustTexture {
+    fn color_value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        let (h, s, value) = rgb_to_hsv(&self.input.color_value(u, v, p));
+
+        hsv_to_rgb(
+            h + self.hue_shift,
+            clamp(s * self.saturation, 0.0, 1.0),
+            value * self.value,
+        )
+    }
+
+    fn export(&self, writer: &mut SceneWriter) -> Result<Entry, String> {
+        Ok(Entry::new("hsv_adjust")
+            .with("input", writer.texture(&self.input)?)
+            .with("hue_shift", self.hue_shift)
+            .with("saturation", self.saturation)
+            .with("value", self.value))
+    }
+}
+
+/// Transforms the uv coordinates before looking up `input`: they are scaled
+/// and rotated (in degrees) about the centre of the uv square, then offset.
+pub struct UvTransformTexture {
+    pub input: WrappedTextureType,
+    pub offset_u: f64,
+    pub offset_v: f64,
+    pub scale_u: f64,
+    pub scale_v: f64,
+    pub rotation: f64,
+}
+
+impl UvTransformTexture {
+    pub fn new(
+        input: WrappedTextureType,
+        offset_u: f64,
+        offset_v: f64,
+        scale_u: f64,
+        scale_v: f64,
+        rotation: f64,
+    ) -> Self {
+        UvTransformTexture {
+            input,
+            offset_u,
+            offset_v,
+            scale_u,
+            scale_v,
+            rotation,
+        }
+    }
+}
+
+impl Texture for UvTransformTexture {
+    fn color_value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        let (sin, cos) = degrees_to_radians(self.rotation).sin_cos();
+        let su = (u - 0.5) * self.scale_u;
+        let sv = (v - 0.5) * self.scale_v;
+
+        self.input.color_value(
+            cos * su - sin * sv + 0.5 + self.offset_u,
+            sin * su + cos * sv + 0.5 + self.offset_v,
+            p,
+        )
+    }
+
+    fn export(&self, writer: &mut SceneWriter) -> Result<Entry, String> {
+        Ok(Entry::new("uv_transform")
+            .with("input", writer.texture(&self.input)?)
+            .with("offset_u", self.offset_u)
+            .with("offset_v", self.offset_v)
+            .with("scale_u", self.scale_u)
+            .with("scale_v", self.scale_v)
+            .with("rotation", self.rotation))
+    }
+}
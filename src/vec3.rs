@@ -1,8 +1,8 @@
 use crate::math_traits::{CrossProduct, InnerProduct};
-use crate::utils::{random, random_range};
+use crate::rng::Rng;
 use std::ops;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Vec3 {
     pub coor: [f64; 3],
 }
@@ -16,21 +16,21 @@ impl Vec3 {
         Vec3::from([x, y, z])
     }
 
-    pub fn random() -> Self {
-        Vec3::new(random(), random(), random())
+    pub fn random(rng: &mut Rng) -> Self {
+        Vec3::new(rng.random(), rng.random(), rng.random())
     }
 
-    pub fn random_from_range(min: f64, max: f64) -> Self {
+    pub fn random_from_range(min: f64, max: f64, rng: &mut Rng) -> Self {
         Vec3::new(
-            random_range(min, max),
-            random_range(min, max),
-            random_range(min, max),
+            rng.random_range(min, max),
+            rng.random_range(min, max),
+            rng.random_range(min, max),
         )
     }
 
-    pub fn random_in_unit_sphere() -> Self {
+    pub fn random_in_unit_sphere(rng: &mut Rng) -> Self {
         loop {
-            let p = Self::random_from_range(-1.0, 1.0);
+            let p = Self::random_from_range(-1.0, 1.0, rng);
             if p.length_squared() >= 1.0 {
                 continue;
             }
@@ -39,9 +39,13 @@ impl Vec3 {
         }
     }
 
-    pub fn random_in_unit_disk() -> Self {
+    pub fn random_in_unit_disk(rng: &mut Rng) -> Self {
         loop {
-            let p = Self::new(random_range(-1.0, 1.0), random_range(-1.0, 1.0), 0.0);
+            let p = Self::new(
+                rng.random_range(-1.0, 1.0),
+                rng.random_range(-1.0, 1.0),
+                0.0,
+            );
             if p.length_squared() >= 1.0 {
                 continue;
             }
@@ -50,12 +54,12 @@ impl Vec3 {
         }
     }
 
-    pub fn random_unit_vector() -> Self {
-        Self::random_in_unit_sphere().unit()
+    pub fn random_unit_vector(rng: &mut Rng) -> Self {
+        Self::random_in_unit_sphere(rng).unit()
     }
 
-    pub fn random_in_hemisphere(normal: &Vec3) -> Self {
-        let vec = Self::random_in_unit_sphere();
+    pub fn random_in_hemisphere(normal: &Vec3, rng: &mut Rng) -> Self {
+        let vec = Self::random_in_unit_sphere(rng);
 
         if vec.dot(normal) > 0.0 {
             vec
@@ -93,6 +97,18 @@ impl Vec3 {
         self.coor[0].abs() < EPS && self.coor[1].abs() < EPS && self.coor[2].abs() < EPS
     }
 
+    /// Unit vector along `self`. Vectors that already have unit length are
+    /// returned as they are, so directions written out and read back in
+    /// keep their bits.
+    #[inline(always)]
+    pub fn unit_direction(&self) -> Self {
+        if (self.length_squared() - 1.0).abs() <= 4.0 * f64::EPSILON {
+            *self
+        } else {
+            self.unit()
+        }
+    }
+
     #[inline(always)]
     pub fn reflected(&self, normal: &Vec3) -> Self {
         *self - self.dot(normal) * 2.0 * normal
@@ -209,6 +225,18 @@ impl ops::Div<f64> for Vec3 {
     }
 }
 
+impl ops::Div<Vec3> for Vec3 {
+    type Output = Vec3;
+
+    fn div(self, _rhs: Vec3) -> Vec3 {
+        Vec3::new(
+            self.x() / _rhs.x(),
+            self.y() / _rhs.y(),
+            self.z() / _rhs.z(),
+        )
+    }
+}
+
 impl<'a> ops::Mul<&'a Vec3> for f64 {
     type Output = Vec3;
 